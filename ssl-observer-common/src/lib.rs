@@ -4,6 +4,7 @@ use core::mem::size_of;
 
 pub const MAX_BUF_SIZE: usize = 1024 * 3
     - size_of::<(
+        u64,
         u64,
         u64,
         u32,
@@ -22,11 +23,18 @@ pub const READ: u8 = 0;
 pub const WRITE: u8 = 1;
 // pub const HANDSHAKE: u8 = 2;
 
+// COUNTERS 中各计数器的下标
+pub const COUNTER_RINGBUF_DROPS: u32 = 0; // RingBuf 预留失败而丢弃的事件
+pub const COUNTER_TRUNCATED: u32 = 1;     // 超过 MAX_BUF_SIZE 被截断的事件
+pub const COUNTER_READ_FAILED: u32 = 2;   // 读取用户态缓冲区失败的事件
+pub const COUNTERS_LEN: u32 = 3;
+
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
 pub struct ProbeSslData {
     pub timestamp_ns: u64,         // 时间戳（纳秒）
     pub delta_ns: u64,             // 函数执行时间
+    pub conn_id: u64,              // SSL* 或 PRFileDesc* 地址，用于区分同一进程内的连接
     pub pid: u32,                  // 进程 ID
     pub tgid: u32,                 // 线程 ID
     pub uid: u32,                  // 用户 ID
@@ -39,3 +47,47 @@ pub struct ProbeSslData {
     pub len: usize,              // 读/写数据的长度
 }
 
+
+pub const SSL3_RANDOM_SIZE: usize = 32;
+pub const SSL_MAX_MASTER_KEY_LENGTH: usize = 48;
+pub const EVP_MAX_MD_SIZE: usize = 64;
+pub const TLS1_3_VERSION: i32 = 0x0304;
+// 偏移量未配置，或 s3 为内嵌结构体而非指针
+pub const OFFSET_NONE: u64 = u64::MAX;
+
+/// 握手完成后从 SSL 结构体中读取的密钥材料
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct ProbeMasterSecret {
+    pub timestamp_ns: u64,
+    pub delta_ns: u64,               // SSL_do_handshake 执行时间
+    pub conn_id: u64,                // SSL* 地址
+    pub pid: u32,
+    pub tgid: u32,
+    pub version: i32,                // TLS 版本，如 0x0303、0x0304
+    pub master_key_len: u32,         // TLS 1.3 下等于摘要长度
+    pub comm: [u8; TASK_COMM_LEN],
+    pub client_random: [u8; SSL3_RANDOM_SIZE],
+    pub master_key: [u8; SSL_MAX_MASTER_KEY_LENGTH],
+    pub client_app_traffic_secret: [u8; EVP_MAX_MD_SIZE],
+    pub server_app_traffic_secret: [u8; EVP_MAX_MD_SIZE],
+    pub exporter_master_secret: [u8; EVP_MAX_MD_SIZE],
+}
+
+/// 目标 libssl 中各字段的偏移量，由用户态根据配置写入
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct SslOffsets {
+    pub version: u64,                   // ssl_st.version
+    pub s3: u64,                        // ssl_st.s3 指针，内嵌时为 OFFSET_NONE
+    pub client_random: u64,             // 相对 *s3（或 ssl_st）的 client_random
+    pub session: u64,                   // ssl_st.session
+    pub session_master_key: u64,        // ssl_session_st.master_key
+    pub session_master_key_length: u64, // ssl_session_st.master_key_length
+    pub client_app_traffic_secret: u64, // ssl_st.client_app_traffic_secret
+    pub server_app_traffic_secret: u64, // ssl_st.server_app_traffic_secret
+    pub exporter_master_secret: u64,    // ssl_st.exporter_master_secret
+}
+
+#[cfg(feature = "user")]
+unsafe impl aya::Pod for SslOffsets {}
@@ -8,15 +8,17 @@ use core::{
 use aya_ebpf::{
     helpers::{bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_get_current_uid_gid, bpf_ktime_get_ns},
     macros::{map,uprobe, uretprobe}, 
-    maps::{LruHashMap, RingBuf}, 
+    maps::{Array, LruHashMap, PerCpuArray, RingBuf}, 
     programs::ProbeContext,
 };
 use aya_log_ebpf::{info,warn};
 use aya_ebpf_bindings::helpers::bpf_probe_read_user;
 use ssl_observer_common::{
-    ProbeSslData,
-    MAX_BUF_SIZE,
+    ProbeSslData,ProbeMasterSecret,SslOffsets,
+    MAX_BUF_SIZE,SSL3_RANDOM_SIZE,SSL_MAX_MASTER_KEY_LENGTH,EVP_MAX_MD_SIZE,
+    OFFSET_NONE,TLS1_3_VERSION,
     READ,WRITE,
+    COUNTER_RINGBUF_DROPS,COUNTER_TRUNCATED,COUNTER_READ_FAILED,COUNTERS_LEN,
 };
 
 const ERROR_CODE:u32 = 0;
@@ -24,13 +26,37 @@ const SUCESS_CODE:u32 = 1;
 
 const MAX_ENTRIES :u32 = 1024 * 2 ;
 const MAX_BYTE_SIZE :u32 = 1024 * 1024 * 512;
+const MASTER_SECRETS_BYTE_SIZE :u32 = 1024 * 256;
 
 #[map]
 static mut START_NS: LruHashMap<u32, u64> = LruHashMap::<u32, u64>::with_max_entries(MAX_ENTRIES, 0);
 #[map]
 static mut BUFS: LruHashMap<u64,*const core::ffi::c_void> = LruHashMap::<u64,*const core::ffi::c_void>::with_max_entries(MAX_ENTRIES, 0);
 #[map]
+static mut CONNS: LruHashMap<u64,u64> = LruHashMap::<u64,u64>::with_max_entries(MAX_ENTRIES, 0);
+#[map]
 static mut SSL_DATA:RingBuf = RingBuf::with_byte_size(MAX_BYTE_SIZE, 0);
+// 握手期间的 SSL* 地址
+#[map]
+static mut HANDSHAKES: LruHashMap<u64,u64> = LruHashMap::<u64,u64>::with_max_entries(MAX_ENTRIES, 0);
+// SSL_do_handshake 进入的时间
+#[map]
+static mut HANDSHAKE_START: LruHashMap<u64,u64> = LruHashMap::<u64,u64>::with_max_entries(MAX_ENTRIES, 0);
+// 用户态写入的 ssl_st 偏移量
+#[map]
+static mut KEYLOG_OFFSETS: Array<SslOffsets> = Array::<SslOffsets>::with_max_entries(1, 0);
+#[map]
+static mut MASTER_SECRETS:RingBuf = RingBuf::with_byte_size(MASTER_SECRETS_BYTE_SIZE, 0);
+// 丢弃、截断等计数，由用户态汇总后输出到 /metrics
+#[map]
+static mut COUNTERS: PerCpuArray<u64> = PerCpuArray::<u64>::with_max_entries(COUNTERS_LEN, 0);
+
+#[inline(always)]
+unsafe fn bump_counter(index: u32) {
+    if let Some(counter) = COUNTERS.get_ptr_mut(index) {
+        *counter += 1;
+    }
+}
 
 static TARGET_PID: u32 = 0;
 static TARGET_UID: u32 = 0;
@@ -144,8 +170,11 @@ unsafe fn ssl_enter(ctx: ProbeContext,_rw:u8)-> Result<u32, u32>{
     // int SSL_read(SSL *ssl, void *buf, int num);
     // 返回 buf 的地址，其中 buf 未加密 
     let buf_ptr :*const core::ffi::c_void= ctx.arg(1).ok_or(1u32)?;
+    // 第一个参数 ssl 的地址，同一连接在其生命周期内不变
+    let ssl_ptr :u64 = ctx.arg(0).ok_or(1u32)?;
 
     BUFS.insert(&current_pid_tgid, &buf_ptr, 0).map_err(|x| x as u32)?;
+    CONNS.insert(&current_pid_tgid, &ssl_ptr, 0).map_err(|x| x as u32)?;
     START_NS.insert(&tgid, &timestamp , 0).map_err(|x| x as u32)?;
 
     Ok(SUCESS_CODE)
@@ -185,6 +214,7 @@ unsafe fn ssl_exit(ctx: ProbeContext,rw:u8)-> Result<u32, u32> {
             size,
             MAX_BUF_SIZE
         );
+        bump_counter(COUNTER_TRUNCATED);
     }
     // 取出 buf 的地址
     let buf_ptr: *const c_void = match BUFS.get(&current_pid_tgid) {
@@ -195,6 +225,11 @@ unsafe fn ssl_exit(ctx: ProbeContext,rw:u8)-> Result<u32, u32> {
         }
     };
 
+    let conn_id: u64 = match CONNS.get(&current_pid_tgid) {
+        Some(ptr) => *ptr,
+        None => 0,
+    };
+
     let count: usize = min(size, MAX_BUF_SIZE);
     let comm: [u8; 16] = bpf_get_current_comm().unwrap_or([0; 16]);
     
@@ -211,9 +246,13 @@ unsafe fn ssl_exit(ctx: ProbeContext,rw:u8)-> Result<u32, u32> {
 
         //  0 表示操作成功
         (*data).buf_filled = if ret == 0 { 1 } else { 0 };
+        if ret != 0 {
+            bump_counter(COUNTER_READ_FAILED);
+        }
         (*data).len = count;
         (*data).timestamp_ns = timestamp;
         (*data).delta_ns = timestamp - start_time;
+        (*data).conn_id = conn_id;
         (*data).pid = pid;
         (*data).tgid =tgid;
         (*data).uid = uid;
@@ -225,6 +264,7 @@ unsafe fn ssl_exit(ctx: ProbeContext,rw:u8)-> Result<u32, u32> {
         entry.submit(0);     
     }else {
         info!(&ctx,"Reserve SSL_DATA failed!!!");
+        bump_counter(COUNTER_RINGBUF_DROPS);
     };    
 
     // START_NS.remove(&tgid).map_err(|x| x as u32)?;
@@ -232,33 +272,6 @@ unsafe fn ssl_exit(ctx: ProbeContext,rw:u8)-> Result<u32, u32> {
     Ok(0)
 }
 
-// #[uprobe]
-// pub fn ssl_do_handshake(ctx: ProbeContext) -> u32 {
-//     match unsafe {try_ssl_do_handshake(ctx)} {
-//         Ok(ret) => ret,
-//         Err(ret) => ret,
-//     }
-// }
-
-// unsafe fn try_ssl_do_handshake(ctx: ProbeContext) -> Result<u32, u32> {
-//     info!(&ctx, "function ssl_do_handshake called by libssl");
-//     handshake(ctx,HANDSHAKE)
-// }
-
-// #[uretprobe]
-// fn ssl_do_handshake_ret(ctx: ProbeContext) -> u32 {
-//     match unsafe { try_ssl_do_handshake_ret(ctx) } {
-//         Ok(ret) => ret,
-//         Err(ret) => ret,
-//     }
-// }
-
-// unsafe fn try_ssl_do_handshake_ret(ctx: ProbeContext) -> Result<u32, u32> {
-//     info!(&ctx, "function ssl_do_handshake_ret called by libssl");
-//     handshake_ret(ctx,HANDSHAKE)
-// }
-
-
 #[uprobe]
 fn ssl_write(ctx: ProbeContext) -> u32 {
     match unsafe { try_ssl_write(ctx) } {
@@ -312,6 +325,130 @@ unsafe fn try_ssl_read_ret(ctx: ProbeContext) -> Result<u32, u32> {
 }
 
 
+// 从用户态地址读取 len 字节，成功返回 true
+#[inline(always)]
+unsafe fn read_user(dst: *mut c_void, len: usize, src: u64) -> bool {
+    bpf_probe_read_user(dst, len as u32, src as *const c_void) == 0
+}
+
+// 读取一项 TLS 1.3 密钥，偏移量未配置或读取失败时清零，只跳过这一项
+unsafe fn read_secret(dst: &mut [u8; EVP_MAX_MD_SIZE], ssl_ptr: u64, offset: u64) -> bool {
+    if offset != OFFSET_NONE && read_user(dst.as_mut_ptr() as *mut c_void, EVP_MAX_MD_SIZE, ssl_ptr + offset) {
+        return true;
+    }
+    *dst = [0; EVP_MAX_MD_SIZE];
+    offset == OFFSET_NONE
+}
+
+unsafe fn keylog_enter(ctx: ProbeContext) -> Result<u32, u32> {
+    let current_pid_tgid: u64 = bpf_get_current_pid_tgid();
+    let pid = current_pid_tgid as u32;
+    let uid: u32 = bpf_get_current_uid_gid() as u32;
+
+    if !trace_allowed(uid, pid) {
+        return Ok(ERROR_CODE);
+    }
+
+    // int SSL_do_handshake(SSL *s);
+    let ssl_ptr :u64 = ctx.arg(0).ok_or(1u32)?;
+    HANDSHAKES.insert(&current_pid_tgid, &ssl_ptr, 0).map_err(|x| x as u32)?;
+    HANDSHAKE_START.insert(&current_pid_tgid, &bpf_ktime_get_ns(), 0).map_err(|x| x as u32)?;
+
+    Ok(SUCESS_CODE)
+}
+
+unsafe fn keylog_exit(ctx: ProbeContext) -> Result<u32, u32> {
+    let current_pid_tgid: u64 = bpf_get_current_pid_tgid();
+    let (tgid, pid) = ((current_pid_tgid >> 32) as u32, current_pid_tgid as u32);
+
+    let ssl_ptr: u64 = match HANDSHAKES.get(&current_pid_tgid) {
+        Some(ptr) => *ptr,
+        None => return Ok(ERROR_CODE),
+    };
+    HANDSHAKES.remove(&current_pid_tgid).map_err(|x| x as u32)?;
+    let start_time: Option<u64> = HANDSHAKE_START.get(&current_pid_tgid).copied();
+    let _ = HANDSHAKE_START.remove(&current_pid_tgid);
+
+    // 返回 1 表示握手成功，此时密钥已经协商完成
+    let ret_value: i32 = ctx.ret().unwrap_or(0);
+    if ret_value != 1 {
+        return Ok(ERROR_CODE);
+    }
+
+    let offsets: &SslOffsets = match KEYLOG_OFFSETS.get(0) {
+        Some(offsets) => offsets,
+        None => return Ok(ERROR_CODE),
+    };
+
+    if let Some(mut entry) = MASTER_SECRETS.reserve::<ProbeMasterSecret>(0) {
+        let data: *mut ProbeMasterSecret = entry.as_mut_ptr();
+        let timestamp = bpf_ktime_get_ns();
+        (*data).timestamp_ns = timestamp;
+        (*data).delta_ns = start_time.map_or(0, |start| timestamp - start);
+        (*data).conn_id = ssl_ptr;
+        (*data).pid = pid;
+        (*data).tgid = tgid;
+        (*data).comm = bpf_get_current_comm().unwrap_or([0; 16]);
+
+        let mut version: i32 = 0;
+        // OpenSSL 1.1.1 中 s3 是指针，3.x 中是内嵌结构体
+        let mut s3_ptr: u64 = ssl_ptr;
+        let mut session_ptr: u64 = 0;
+        let mut master_key_len: u64 = 0;
+        let ok = read_user(&mut version as *mut i32 as *mut c_void, 4, ssl_ptr + offsets.version)
+            && (offsets.s3 == OFFSET_NONE
+                || read_user(&mut s3_ptr as *mut u64 as *mut c_void, 8, ssl_ptr + offsets.s3))
+            && read_user((*data).client_random.as_mut_ptr() as *mut c_void, SSL3_RANDOM_SIZE, s3_ptr + offsets.client_random)
+            && read_user(&mut session_ptr as *mut u64 as *mut c_void, 8, ssl_ptr + offsets.session)
+            && session_ptr != 0
+            && read_user(&mut master_key_len as *mut u64 as *mut c_void, 8, session_ptr + offsets.session_master_key_length)
+            && read_user((*data).master_key.as_mut_ptr() as *mut c_void, SSL_MAX_MASTER_KEY_LENGTH, session_ptr + offsets.session_master_key);
+
+        // TLS 1.3 的流量密钥直接保存在 ssl_st 中，各项分别检查偏移量
+        if ok && version == TLS1_3_VERSION {
+            let client = read_secret(&mut (*data).client_app_traffic_secret, ssl_ptr, offsets.client_app_traffic_secret);
+            let server = read_secret(&mut (*data).server_app_traffic_secret, ssl_ptr, offsets.server_app_traffic_secret);
+            let exporter = read_secret(&mut (*data).exporter_master_secret, ssl_ptr, offsets.exporter_master_secret);
+            if !(client && server && exporter) {
+                info!(&ctx, "Failed to read some TLS 1.3 secrets, check keylog offsets");
+            }
+        } else {
+            (*data).client_app_traffic_secret = [0; EVP_MAX_MD_SIZE];
+            (*data).server_app_traffic_secret = [0; EVP_MAX_MD_SIZE];
+            (*data).exporter_master_secret = [0; EVP_MAX_MD_SIZE];
+        }
+
+        if !ok {
+            info!(&ctx, "Failed to read secrets from SSL struct, check keylog offsets");
+            entry.discard(0);
+            return Ok(ERROR_CODE);
+        }
+        (*data).version = version;
+        (*data).master_key_len = min(master_key_len, SSL_MAX_MASTER_KEY_LENGTH as u64) as u32;
+        entry.submit(0);
+    } else {
+        info!(&ctx,"Reserve MASTER_SECRETS failed!!!");
+    }
+
+    Ok(0)
+}
+
+#[uprobe]
+fn ssl_do_handshake(ctx: ProbeContext) -> u32 {
+    match unsafe { keylog_enter(ctx) } {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+#[uretprobe]
+fn ssl_do_handshake_ret(ctx: ProbeContext) -> u32 {
+    match unsafe { keylog_exit(ctx) } {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
 #[panic_handler]
 fn panic(_info: &core::panic::PanicInfo) -> ! {
     unsafe { core::hint::unreachable_unchecked() }
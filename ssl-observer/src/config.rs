@@ -1,12 +1,36 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 use serde::Deserialize;
-use toml;
 use lazy_static::lazy_static;
 
+use ssl_observer_common::{SslOffsets, OFFSET_NONE};
+
 #[derive(Deserialize)]
 pub struct Config {
     pub database: Database,
+    #[serde(default)]
+    pub retention: Retention,
+    #[serde(default)]
+    pub keylog: Keylog,
+    #[serde(default)]
+    pub grpc: Grpc,
+    #[serde(default)]
+    pub decoders: Decoders,
+    #[serde(default)]
+    pub redaction: Redaction,
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+    #[serde(default)]
+    pub alerts: Alerts,
+    #[serde(default)]
+    pub yara: Yara,
+    #[serde(default)]
+    pub metrics: Metrics,
+    #[serde(default)]
+    pub otlp: Otlp,
+    #[serde(default)]
+    pub latency: Latency,
 }
 
 #[derive(Deserialize)]
@@ -21,6 +45,30 @@ struct Sqlite {
     path: String,
     file: String,
     name: String,
+    #[serde(default)]
+    rotate: Rotate,
+}
+
+/// sqlite 滚动文件周期
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotate {
+    #[default]
+    None,
+    Hourly,
+    Daily,
+}
+
+/// 采集数据保留策略，0 表示不限制
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default)]
+pub struct Retention {
+    pub max_age_secs: u64,
+    /// 每张表分别保留的最大行数，sqlite 开启滚动时包括滚动文件中的行
+    pub max_rows: u64,
+    /// 所有表合计的最大数据量，超出时从最旧的数据开始删除；sqlite 开启滚动时滚动文件按文件大小计入，超出时整个删除
+    pub max_bytes: u64,
+    pub interval_secs: u64,
 }
 
 #[derive( Deserialize)]
@@ -43,7 +91,11 @@ impl Database {
 
     /// return sqlite database name
     pub fn sqlite_db_name(&self)->String {
-        format!("{}",self.sqlite.as_ref().unwrap().name)
+        self.sqlite.as_ref().unwrap().name.clone()
+    }
+    /// return sqlite rolling file period
+    pub fn sqlite_rotate(&self)->Rotate {
+        self.sqlite.as_ref().unwrap().rotate
     }
     /// return mysql url
     pub fn mysql_url(&self)->String{
@@ -56,12 +108,12 @@ impl Database {
     }
     /// return database name
     pub fn mysql_db_name(&self)->String {
-        format!("{}",self.mysql.as_ref().unwrap().name)
+        self.mysql.as_ref().unwrap().name.clone()
     }
 }
 impl Default for Sqlite {
     fn default() -> Self {
-        Self{ path: "./".to_string(), file: "ssl_data.db".to_string(), name: "ssl_data".to_string() }
+        Self{ path: "./".to_string(), file: "ssl_data.db".to_string(), name: "ssl_data".to_string(), rotate: Rotate::None }
     }
 }
 
@@ -71,6 +123,259 @@ impl Default for Mysql {
     }
 }
 
+/// 目标 libssl 中 ssl_st 等结构体的字段偏移量，随 OpenSSL 版本与编译选项变化
+#[derive(Deserialize, Default)]
+pub struct Keylog {
+    version: Option<u64>,
+    s3: Option<u64>,
+    client_random: Option<u64>,
+    session: Option<u64>,
+    session_master_key: Option<u64>,
+    session_master_key_length: Option<u64>,
+    client_app_traffic_secret: Option<u64>,
+    server_app_traffic_secret: Option<u64>,
+    exporter_master_secret: Option<u64>,
+}
+
+/// gRPC 消息解码配置
+#[derive(Deserialize, Default)]
+pub struct Grpc {
+    /// FileDescriptorSet 文件路径，不填时按原始 wire format 显示
+    pub descriptor_set: Option<String>,
+}
+
+/// 协议解码器配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Decoders {
+    /// 按顺序尝试识别连接协议的解码器
+    pub enabled: Vec<String>,
+    /// 未识别出协议时使用的解码器
+    pub fallback: String,
+    /// WebAssembly 解码插件，名称加入 enabled 后生效
+    pub plugins: Vec<Plugin>,
+}
+
+/// 存储与输出前的敏感信息脱敏配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Redaction {
+    pub enabled: bool,
+    pub mode: RedactMode,
+    /// 整个值需要脱敏的头部名称，不区分大小写
+    pub headers: Vec<String>,
+    /// 表单与查询参数中的字段名
+    pub form_fields: Vec<String>,
+    /// JSON 报文体中的字段，如 `$.user.password`、`$..token`
+    pub json_paths: Vec<String>,
+    /// 自定义正则，有捕获组时只替换第一个捕获组
+    pub patterns: Vec<String>,
+    /// 识别 JWT、AWS、GitHub 等常见 token 格式
+    pub tokens: bool,
+}
+
+/// mask 替换为 [REDACTED]，hash 替换为 SHA-256 前缀，便于关联相同的值
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactMode {
+    #[default]
+    Mask,
+    Hash,
+}
+
+/// 告警规则，填写的条件需全部满足
+#[derive(Deserialize)]
+pub struct AlertRule {
+    pub name: String,
+    pub severity: Option<String>,
+    /// 进程名，任一相同即可
+    #[serde(default)]
+    pub comm: Vec<String>,
+    #[serde(default)]
+    pub uid: Vec<u32>,
+    /// read 或 write
+    pub direction: Option<String>,
+    /// HTTP Host 或 :authority 的正则，同一连接记住最近一次出现的值；不匹配 TLS SNI
+    pub host: Option<String>,
+    #[serde(default)]
+    pub method: Vec<String>,
+    /// 请求路径的正则
+    pub path: Option<String>,
+    #[serde(default)]
+    pub status: Vec<u16>,
+    /// Content-Type 的正则
+    pub content_type: Option<String>,
+    /// 原始数据的正则
+    pub payload: Option<String>,
+    /// 原始数据中的字节序列，十六进制，如 "de ad be ef"
+    pub bytes: Option<String>,
+    /// 覆盖 [alerts] 中的 rate_limit
+    pub rate_limit: Option<u32>,
+}
+
+/// 告警输出与限流配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Alerts {
+    /// 在标准输出打印告警
+    pub stdout: bool,
+    /// 同时写入 --output jsonl
+    pub jsonl: bool,
+    /// 每条规则在 rate_interval_secs 内最多产生的告警数，0 表示不限制
+    pub rate_limit: u32,
+    pub rate_interval_secs: u64,
+}
+
+/// YARA 规则扫描配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Yara {
+    /// .yar 规则文件，为空时不扫描
+    pub rule_files: Vec<String>,
+    /// 单个报文的扫描超时（毫秒），0 表示不限制
+    pub timeout_ms: u64,
+}
+
+/// Prometheus 指标服务配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    pub enabled: bool,
+    /// 监听地址，默认只监听本机
+    pub listen: String,
+}
+
+/// SSL 调用耗时直方图配置
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Latency {
+    /// 周期性打印直方图的间隔（秒），0 表示不打印
+    pub print_interval_secs: u64,
+    pub group_by: LatencyGroup,
+}
+
+/// 耗时直方图的分组方式
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LatencyGroup {
+    #[default]
+    Comm,
+    Pid,
+}
+
+/// OpenTelemetry span 导出配置
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct Otlp {
+    pub enabled: bool,
+    /// OTLP/HTTP JSON 接收地址，仅支持 http://
+    pub endpoint: String,
+    /// 附加的请求头，如认证或租户
+    pub headers: BTreeMap<String, String>,
+    /// 攒够该数量的 span 后立即发送
+    pub batch_size: usize,
+    pub flush_interval_ms: u64,
+    /// 等待发送的 span 上限，超过后丢弃
+    pub queue_size: usize,
+    pub timeout_ms: u64,
+}
+
+/// WebAssembly 解码插件
+#[derive(Deserialize)]
+pub struct Plugin {
+    pub name: String,
+    /// 插件 .wasm 文件路径
+    pub path: String,
+    /// 单次调用可消耗的 fuel
+    pub fuel: Option<u64>,
+    /// 插件内存上限（MB）
+    pub max_memory_mb: Option<usize>,
+}
+
+impl Keylog {
+    /// return the offsets for the eBPF map, or None when a required one is missing
+    pub fn offsets(&self)->Option<SslOffsets> {
+        Some(SslOffsets {
+            version: self.version?,
+            s3: self.s3.unwrap_or(OFFSET_NONE),
+            client_random: self.client_random?,
+            session: self.session?,
+            session_master_key: self.session_master_key?,
+            session_master_key_length: self.session_master_key_length?,
+            client_app_traffic_secret: self.client_app_traffic_secret.unwrap_or(OFFSET_NONE),
+            server_app_traffic_secret: self.server_app_traffic_secret.unwrap_or(OFFSET_NONE),
+            exporter_master_secret: self.exporter_master_secret.unwrap_or(OFFSET_NONE),
+        })
+    }
+}
+
+impl Retention {
+    /// return whether any limit is configured
+    pub fn is_enabled(&self)->bool {
+        self.max_age_secs > 0 || self.max_rows > 0 || self.max_bytes > 0
+    }
+}
+
+impl Default for Yara {
+    fn default() -> Self {
+        Self{ rule_files: Vec::new(), timeout_ms: 100 }
+    }
+}
+
+impl Default for Otlp {
+    fn default() -> Self {
+        Self{
+            enabled: false,
+            endpoint: "http://127.0.0.1:4318/v1/traces".to_string(),
+            headers: BTreeMap::new(),
+            batch_size: 512,
+            flush_interval_ms: 2000,
+            queue_size: 4096,
+            timeout_ms: 5000,
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self{ enabled: false, listen: "127.0.0.1:9464".to_string() }
+    }
+}
+
+impl Default for Alerts {
+    fn default() -> Self {
+        Self{ stdout: true, jsonl: true, rate_limit: 10, rate_interval_secs: 60 }
+    }
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self{ max_age_secs: 0, max_rows: 0, max_bytes: 0, interval_secs: 60 }
+    }
+}
+
+impl Default for Decoders {
+    fn default() -> Self {
+        let enabled = ["http", "smtp", "imap", "pop3", "postgres", "mysql", "redis"];
+        Self{ enabled: enabled.map(String::from).to_vec(), fallback: "http".to_string(), plugins: Vec::new() }
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        let headers = ["authorization", "proxy-authorization", "cookie", "set-cookie", "x-api-key", "x-auth-token"];
+        Self{
+            enabled: true,
+            mode: RedactMode::Mask,
+            headers: headers.map(String::from).to_vec(),
+            form_fields: Vec::new(),
+            json_paths: Vec::new(),
+            patterns: Vec::new(),
+            tokens: false,
+        }
+    }
+}
+
 // 添加 Default 实现来提供默认配置
 impl Default for Config {
     fn default() -> Self {
@@ -80,6 +385,17 @@ impl Default for Config {
                 sqlite:Some(Sqlite::default()),
                 mysql: Some(Mysql::default()),
             },
+            retention: Retention::default(),
+            keylog: Keylog::default(),
+            grpc: Grpc::default(),
+            decoders: Decoders::default(),
+            redaction: Redaction::default(),
+            rules: Vec::new(),
+            alerts: Alerts::default(),
+            yara: Yara::default(),
+            metrics: Metrics::default(),
+            otlp: Otlp::default(),
+            latency: Latency::default(),
         }
     }
 }
@@ -113,7 +429,7 @@ mod tests {
     use super::load_config;
 
     #[test]
-    fn test() {
+    fn loads_config_file() {
         load_config();
     }
 }
\ No newline at end of file
@@ -1,16 +1,114 @@
-use async_compression::tokio::bufread::GzipDecoder;
-use std::str;
-use tokio::io::{AsyncReadExt, BufReader};
+use async_compression::tokio::bufread::{
+    BrotliDecoder, DeflateDecoder, GzipDecoder, ZlibDecoder, ZstdDecoder,
+};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
 
+use crate::grpc::{grpc_call, is_grpc, render_body, GrpcCall};
+use crate::http1::{is_http_start, HttpExchange, HttpMessage};
+use crate::http2::{describe_frames, PREFACE};
+use crate::rules::Alert;
+use crate::websocket::WebSocketMessage;
 use crate::Opt;
-use ssl_observer_common::ProbeSslData;
+use ssl_observer_common::{ProbeSslData, WRITE};
 
-pub async fn print_buf(data: &ProbeSslData, _opt: &Opt) {
-    if (&data).is_handshake == false {
-        println!(
-            "\nv----- DATA -----v\n{}\n>----- END DATA -----<",
-            parse_http(&data.buf).await
-        );
+// 单次解压输出的上限
+pub const MAX_DECODED_SIZE: usize = 16 * 1024 * 1024;
+
+/// application protocol carried by a TLS connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Http1,
+    Http2,
+    Smtp,
+    Imap,
+    Pop3,
+    Postgres,
+    Mysql,
+    Redis,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Http1 => "http1",
+            Protocol::Http2 => "http2",
+            Protocol::Smtp => "smtp",
+            Protocol::Imap => "imap",
+            Protocol::Pop3 => "pop3",
+            Protocol::Postgres => "postgres",
+            Protocol::Mysql => "mysql",
+            Protocol::Redis => "redis",
+        }
+    }
+}
+
+/// guess the protocol from the first bytes seen on a connection, in either direction
+pub fn fingerprint(payload: &[u8]) -> Option<Protocol> {
+    let text = String::from_utf8_lossy(&payload[..payload.len().min(64)]);
+    let word = |words: &[&str]| {
+        words
+            .iter()
+            .any(|word| text.get(..word.len()).is_some_and(|head| head.eq_ignore_ascii_case(word)))
+    };
+    if payload.starts_with(PREFACE) {
+        return Some(Protocol::Http2);
+    }
+    if is_http_start(payload) {
+        return Some(Protocol::Http1);
+    }
+    // 服务端问候或客户端的第一条命令
+    if (text.starts_with("220 ") || text.starts_with("220-")) && text.to_ascii_uppercase().contains("SMTP")
+        || word(&["EHLO ", "HELO "])
+    {
+        return Some(Protocol::Smtp);
+    }
+    if word(&["* OK", "* PREAUTH", "* BYE"]) {
+        return Some(Protocol::Imap);
+    }
+    if let Some((tag, command)) = text.split_once(' ') {
+        let imap_commands = ["CAPABILITY", "LOGIN ", "AUTHENTICATE ", "ID ", "NOOP", "STARTTLS"];
+        if !tag.is_empty()
+            && tag.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.')
+            && imap_commands.iter().any(|c| command.to_ascii_uppercase().starts_with(c))
+        {
+            return Some(Protocol::Imap);
+        }
+    }
+    // "+OK\r\n" 也可能是 Redis 的应答，POP3 问候后面带文字
+    if text.starts_with("+OK ") || word(&["CAPA\r\n", "USER ", "APOP ", "STLS\r\n"]) {
+        return Some(Protocol::Pop3);
+    }
+    if text.starts_with('*')
+        && text[1..].split_once("\r\n$").is_some_and(|(count, _)| count.parse::<u32>().is_ok())
+    {
+        return Some(Protocol::Redis);
+    }
+    // PostgreSQL StartupMessage：长度 + 协议版本 3.0
+    if payload.len() >= 8 && payload[4..8] == [0, 3, 0, 0] {
+        let len = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+        if len >= 8 && len <= payload.len() {
+            return Some(Protocol::Postgres);
+        }
+    }
+    // PostgreSQL 服务端第一条消息为 AuthenticationRequest
+    if payload.len() >= 9 && payload[0] == b'R' && payload[1..3] == [0, 0] {
+        return Some(Protocol::Postgres);
+    }
+    // MySQL TLS 之后的 HandshakeResponse41，带 CLIENT_PROTOCOL_41 与 CLIENT_SSL
+    if payload.len() > 36 && matches!(payload[3], 1 | 2) {
+        let len = u32::from_le_bytes([payload[0], payload[1], payload[2], 0]) as usize;
+        let flags = u32::from_le_bytes([payload[4], payload[5], payload[6], payload[7]]);
+        if len + 4 <= payload.len() && flags & 0x200 != 0 && flags & 0x800 != 0 {
+            return Some(Protocol::Mysql);
+        }
+    }
+    None
+}
+
+pub async fn print_buf(data: &ProbeSslData, content: &str, _opt: &Opt) {
+    if !data.is_handshake {
+        println!("\nv----- DATA -----v\n{}\n>----- END DATA -----<", content);
 
         // println!(
         //     "\nv----- DATA -----v\n{}\n>----- END DATA -----<",
@@ -24,6 +122,88 @@ pub async fn print_buf(data: &ProbeSslData, _opt: &Opt) {
     }
 }
 
+// 输出一次完整的请求/响应摘要
+pub fn print_exchange(exchange: &HttpExchange) {
+    let request = exchange
+        .request
+        .as_ref()
+        .map_or("-", |message| message.start_line.as_str());
+    let response = exchange
+        .response
+        .as_ref()
+        .map_or("-", |message| message.start_line.as_str());
+    let truncated = [&exchange.request, &exchange.response]
+        .iter()
+        .any(|message| message.as_ref().is_some_and(|message| message.truncated));
+    let latency = exchange
+        .latency_ns()
+        .map_or("-".to_string(), |ns| format!("{:.3} ms", ns as f64 / 1_000_000.0));
+    let stream = exchange
+        .stream_id
+        .map_or(String::new(), |stream_id| format!(" #{}", stream_id));
+    println!(
+        "\nv----- HTTP -----v\n[{}:{}]{} {} -> {} ({}){}\n>----- END HTTP -----<",
+        exchange.comm,
+        exchange.tgid,
+        stream,
+        request,
+        response,
+        latency,
+        if truncated { " [truncated]" } else { "" }
+    );
+}
+
+// 输出一条告警
+pub fn print_alert(alert: &Alert) {
+    let mut location = String::new();
+    if let Some(stream_id) = alert.stream_id {
+        location.push_str(&format!(" #{}", stream_id));
+    }
+    if let Some(rw) = alert.rw {
+        location.push_str(if rw == WRITE { " write" } else { " read" });
+    }
+    if let Some(host) = &alert.host {
+        location.push_str(&format!(" {}", host));
+    }
+    if let Some(offset) = alert.offset {
+        location.push_str(&format!(" {}@{}", alert.scope, offset));
+    }
+    println!(
+        "\nv----- ALERT -----v\n[{}:{}] {} {} ({}){}\n{}\n>----- END ALERT -----<",
+        alert.comm,
+        alert.tgid,
+        alert.source,
+        alert.rule,
+        alert.severity,
+        location,
+        alert.excerpt
+    );
+}
+
+// 输出一条 WebSocket 消息
+pub fn print_ws_message(message: &WebSocketMessage) {
+    let mut flags = String::new();
+    if message.compressed {
+        flags.push_str(" [deflate]");
+    }
+    if message.truncated {
+        flags.push_str(" [truncated]");
+    }
+    let close = message
+        .close_code
+        .map_or(String::new(), |code| format!(" code={}", code));
+    println!(
+        "\nv----- WEBSOCKET -----v\n[{}:{}] {} {}{}{}\n{}\n>----- END WEBSOCKET -----<",
+        message.comm,
+        message.tgid,
+        if message.masked { "client" } else { "server" },
+        message.kind(),
+        close,
+        flags,
+        message.text()
+    );
+}
+
 // async fn parse_utf8_or_hex(buf: &[u8]) -> String{
 //     match str::from_utf8(buf) {
 //         Ok(str_slice) => str_slice.to_string(),
@@ -38,54 +218,319 @@ pub async fn print_buf(data: &ProbeSslData, _opt: &Opt) {
 //     buf.iter().map(|byte| format!("{:02x}", byte)).collect()
 // }
 
-async fn async_decode_gzip(content: &[u8]) -> Result<String, std::io::Error> {
-    // 使用async_compression的GzipDecoder创建一个异步解压读取器
-    let decoder = GzipDecoder::new(content);
-    let mut decoder = BufReader::new(decoder);
+// 异步读取解压后的内容，超过上限的部分丢弃，防止压缩炸弹耗尽内存
+async fn read_decoded<R: AsyncRead + Unpin>(decoder: R) -> Result<Decoded, io::Error> {
+    let mut decoder = BufReader::new(decoder).take(MAX_DECODED_SIZE as u64 + 1);
+    let mut data = Vec::new();
+    decoder.read_to_end(&mut data).await?;
+    let truncated = data.len() > MAX_DECODED_SIZE;
+    data.truncate(MAX_DECODED_SIZE);
+    Ok(Decoded { data, truncated })
+}
 
-    // 初始化一个字符串用于存储解压后的数据
-    let mut decoded_content = String::new();
+/// output of one Content-Encoding step
+pub struct Decoded {
+    pub data: Vec<u8>,
+    /// whether the output was cut at `MAX_DECODED_SIZE`
+    pub truncated: bool,
+}
 
-    // 异步读取解压后的内容到字符串
-    decoder.read_to_string(&mut decoded_content).await?;
+/// decode one Content-Encoding token
+pub async fn decode_content(encoding: &str, content: &[u8]) -> Result<Decoded, io::Error> {
+    match encoding {
+        "gzip" | "x-gzip" => read_decoded(GzipDecoder::new(content)).await,
+        "br" => read_decoded(BrotliDecoder::new(content)).await,
+        "zstd" => read_decoded(ZstdDecoder::new(content)).await,
+        // HTTP 的 deflate 应为 zlib 格式，但也有服务端直接发送裸 deflate 数据
+        "deflate" => match read_decoded(ZlibDecoder::new(content)).await {
+            Ok(decoded_content) => Ok(decoded_content),
+            Err(_) => read_decoded(DeflateDecoder::new(content)).await,
+        },
+        "identity" => Ok(Decoded {
+            data: content.to_vec(),
+            truncated: false,
+        }),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported content-encoding",
+        )),
+    }
+}
 
-    Ok(decoded_content)
+/// chunked 报文体去掉分块后的结果
+pub struct Chunked {
+    pub body: Vec<u8>,
+    pub chunks: usize,
+    /// chunk extensions, like "name=value"
+    pub extensions: Vec<String>,
+    pub trailers: Vec<(String, String)>,
+    /// whether the terminating chunk and trailer section were seen
+    pub complete: bool,
+    /// bytes consumed from the input, including trailers
+    pub consumed: usize,
 }
 
-// gzip解码转换成字符串。
+impl Chunked {
+    // 附加在解码内容之后的分块信息，仅在有扩展、trailer 或不完整时输出
+    fn summary(&self) -> String {
+        if self.extensions.is_empty() && self.trailers.is_empty() && self.complete {
+            return String::new();
+        }
+        let mut summary = format!(
+            "\r\n\r\n[chunked] {} chunks{}",
+            self.chunks,
+            if self.complete { "" } else { ", incomplete" }
+        );
+        if !self.extensions.is_empty() {
+            summary.push_str(&format!(", extensions: {}", self.extensions.join("; ")));
+        }
+        for (name, value) in &self.trailers {
+            summary.push_str(&format!("\r\n{}: {}", name, value));
+        }
+        summary
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// decode Transfer-Encoding: chunked framing, keeping whatever is available when truncated
+pub fn decode_chunked(content: &[u8]) -> Chunked {
+    let mut chunked = Chunked {
+        body: Vec::new(),
+        chunks: 0,
+        extensions: Vec::new(),
+        trailers: Vec::new(),
+        complete: false,
+        consumed: 0,
+    };
+    let mut pos = 0;
+    // chunk-size [; ext-name [= ext-value]] CRLF
+    while let Some(offset) = find(&content[pos..], b"\r\n") {
+        let line_end = pos + offset;
+        let line = String::from_utf8_lossy(&content[pos..line_end]);
+        let mut parts = line.split(';');
+        let size = match usize::from_str_radix(parts.next().unwrap_or("").trim(), 16) {
+            Ok(size) => size,
+            Err(_) => break,
+        };
+        chunked
+            .extensions
+            .extend(parts.map(|ext| ext.trim().to_string()).filter(|ext| !ext.is_empty()));
+        pos = line_end + 2;
+
+        if size == 0 {
+            // trailer 字段，以空行结束
+            while let Some(offset) = find(&content[pos..], b"\r\n") {
+                let line_end = pos + offset;
+                let line = String::from_utf8_lossy(&content[pos..line_end]).to_string();
+                pos = line_end + 2;
+                if line.is_empty() {
+                    chunked.complete = true;
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    chunked.trailers.push((name.trim().to_string(), value.trim().to_string()));
+                }
+            }
+            break;
+        }
+
+        // 长度来自抓到的流量，可能远超剩余数据甚至溢出
+        let data_end = pos + size.min(content.len() - pos);
+        chunked.body.extend_from_slice(&content[pos..data_end]);
+        chunked.chunks += 1;
+        pos = pos.saturating_add(size).saturating_add(2);
+        if pos > content.len() {
+            pos = content.len();
+            break;
+        }
+    }
+    chunked.consumed = pos;
+    chunked
+}
+
+/// decode both sides of a reassembled exchange
+pub async fn parse_exchange(exchange: &HttpExchange) -> (Option<String>, Option<String>) {
+    let grpc = grpc_call(exchange);
+    let request = match &exchange.request {
+        Some(message) => Some(parse_message(message, grpc.as_ref(), false).await),
+        None => None,
+    };
+    let response = match &exchange.response {
+        Some(message) => Some(parse_message(message, grpc.as_ref(), true).await),
+        None => None,
+    };
+    (request, response)
+}
+
+// 解码一个重组后的报文，gRPC 报文体按消息拆分，HTTP/2 的 trailer 附加在末尾
+async fn parse_message(message: &HttpMessage, grpc: Option<&GrpcCall>, is_response: bool) -> String {
+    let mut content_str = match grpc {
+        Some(call) if is_grpc(message) => {
+            let header_len = message.raw.len() - message.body().len();
+            format!(
+                "{}\r\n\r\n{}",
+                String::from_utf8_lossy(&message.raw[..header_len]).trim_end(),
+                render_body(message, &call.method, is_response).await
+            )
+        }
+        _ => parse_http(&message.raw).await,
+    };
+    if !message.trailers.is_empty() {
+        content_str.push_str("\r\n\r\n[trailers]");
+        for (name, value) in &message.trailers {
+            content_str.push_str(&format!("\r\n{}: {}", name, value));
+        }
+    }
+    content_str
+}
+
+// 先去掉 chunked 分块，再按 Content-Encoding 解压，转换成字符串。
 pub async fn parse_http(buf: &[u8]) -> String {
+    // 0. 恰好由完整 HTTP/2 帧组成时输出帧列表，头部块需在连接上下文中解码
+    if let Some(frames) = describe_frames(buf) {
+        return frames;
+    }
+
     // 1. 检查是否存在 "\r\n\r\n"，确定 header 和 content 的分界线
-    let header_end = buf
-        .windows(4)
-        .position(|window| window == b"\r\n\r\n")
-        .unwrap_or(buf.len());
-    let (header, mut content_start) = buf.split_at(header_end);
-
-    // 2. 解析 header，获取 Content-Encoding 字段
-    let content_encoding = str::from_utf8(header)
-        .ok()
-        .and_then(|header_str| {
-            header_str
-                .lines()
-                .find(|line| line.to_lowercase().starts_with("content-encoding:"))
-                .map(|line| line.trim().to_lowercase())
-        })
-        .unwrap_or_default();
-
-    // 3. 如果 Content-Encoding 为 gzip，则对 content 部分进行解码
-    if content_encoding.contains("gzip") {
-        content_start = content_start.split_at(4).1; // Skip \r\n\r\n after header
-        if let Ok(decoded_content) = async_decode_gzip(content_start).await {
-            return format!(
-                "{}{}{}",
-                str::from_utf8(header).unwrap(),
-                "\r\n\r\n",
-                decoded_content
-            );
+    let header_end = match find(buf, b"\r\n\r\n") {
+        Some(pos) => pos,
+        None => return String::from_utf8_lossy(buf).to_string(),
+    };
+    let header_str = String::from_utf8_lossy(&buf[..header_end]);
+    let content = &buf[header_end + 4..];
+
+    // 2. 解析 header，获取 Transfer-Encoding 与 Content-Encoding 字段
+    let field = |name: &str| {
+        header_str
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.trim().to_lowercase())
+            .unwrap_or_default()
+    };
+    let transfer_encoding = field("transfer-encoding");
+    let content_encoding = field("content-encoding");
+
+    // 3. chunked 分块必须在解压之前去掉，否则分块长度行会破坏 gzip 数据
+    let chunked = if transfer_encoding.contains("chunked") {
+        Some(decode_chunked(content))
+    } else {
+        None
+    };
+    let body: &[u8] = chunked.as_ref().map_or(content, |chunked| &chunked.body);
+
+    // 4. 多重编码（如 "gzip, br"）按声明的逆序解码，失败时保留当前数据并注明原因
+    let mut decoded: Vec<u8> = body.to_vec();
+    let mut decode_error: Option<String> = None;
+    for encoding in content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|encoding| !encoding.is_empty())
+        .rev()
+    {
+        match decode_content(encoding, &decoded).await {
+            // 截断的数据无法继续按外层编码解码
+            Ok(output) if output.truncated => {
+                decoded = output.data;
+                decode_error = Some(format!("{} output truncated at {} bytes", encoding, MAX_DECODED_SIZE));
+                break;
+            }
+            Ok(output) => decoded = output.data,
+            Err(e) => {
+                decode_error = Some(format!("{} decode failed: {}", encoding, e));
+                break;
+            }
         }
     }
-    // 4. 组装并返回结果
-    let header_str = String::from_utf8_lossy(header);
-    let content_str = String::from_utf8_lossy(content_start);
-    format!("{}{}", header_str, content_str)
+    let mut content_str = String::from_utf8_lossy(&decoded).to_string();
+
+    // 5. 组装并返回结果
+    if let Some(chunked) = &chunked {
+        content_str.push_str(&chunked.summary());
+    }
+    if let Some(decode_error) = decode_error {
+        content_str.push_str(&format!("\r\n\r\n[content-encoding] {}", decode_error));
+    }
+    format!("{}\r\n\r\n{}", header_str, content_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_chunked, decode_content, fingerprint, parse_http, Protocol, MAX_DECODED_SIZE};
+
+    #[test]
+    fn decodes_chunked_body() {
+        let chunked = decode_chunked(b"4;name=v\r\nWiki\r\n5\r\npedia\r\n0\r\nExpires: never\r\n\r\nnext");
+        assert_eq!(chunked.body, b"Wikipedia");
+        assert_eq!(chunked.chunks, 2);
+        assert_eq!(chunked.extensions, vec!["name=v"]);
+        assert_eq!(chunked.trailers, vec![("Expires".to_string(), "never".to_string())]);
+        assert!(chunked.complete);
+        assert_eq!(chunked.consumed, 47);
+    }
+
+    #[tokio::test]
+    async fn parses_chunked_response() {
+        let response = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(
+            parse_http(response).await,
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nabc"
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_decode_failure() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Encoding: br\r\n\r\nnot brotli";
+        assert!(parse_http(response).await.contains("[content-encoding] br decode failed"));
+    }
+
+    #[test]
+    fn fingerprints_protocols() {
+        assert_eq!(fingerprint(b"220 mx.example.com ESMTP ready\r\n"), Some(Protocol::Smtp));
+        assert_eq!(fingerprint(b"* OK IMAP4rev1 ready\r\n"), Some(Protocol::Imap));
+        assert_eq!(fingerprint(b"+OK POP3 ready\r\n"), Some(Protocol::Pop3));
+        assert_eq!(fingerprint(b"*1\r\n$4\r\nPING\r\n"), Some(Protocol::Redis));
+        assert_eq!(fingerprint(b"\0\0\0\x08\0\x03\0\0"), Some(Protocol::Postgres));
+        assert_eq!(fingerprint(b"\x16\x03\x01"), None);
+    }
+
+    #[tokio::test]
+    async fn decoded_size_is_limited() {
+        use async_compression::tokio::bufread::GzipEncoder;
+        use tokio::io::AsyncReadExt;
+
+        // 压缩后很小的 gzip 数据解压后超过上限
+        let zeros = vec![0u8; MAX_DECODED_SIZE + 1024];
+        let mut bomb = Vec::new();
+        GzipEncoder::new(zeros.as_slice()).read_to_end(&mut bomb).await.unwrap();
+        let output = decode_content("gzip", &bomb).await.unwrap();
+        assert!(output.truncated);
+        assert_eq!(output.data.len(), MAX_DECODED_SIZE);
+
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\n\r\n".to_vec();
+        response.extend_from_slice(&bomb);
+        assert!(parse_http(&response).await.ends_with("[content-encoding] gzip output truncated at 16777216 bytes"));
+    }
+
+    #[test]
+    fn chunked_size_out_of_range() {
+        // 超出剩余数据的长度只取已有的部分
+        let chunked = decode_chunked(b"ffffffffffffffff\r\nabc");
+        assert_eq!(chunked.body, b"abc");
+        assert!(!chunked.complete);
+        assert_eq!(chunked.consumed, 21);
+
+        let chunked = decode_chunked(b"3\r\nabc\r\nfffffffffffffffff\r\nabc");
+        assert_eq!(chunked.body, b"abc");
+        assert_eq!(chunked.chunks, 1);
+        assert_eq!(chunked.consumed, 8);
+
+        let chunked = decode_chunked(b"3\r\nabc\r\nzz\r\nabc\r\n0\r\n\r\n");
+        assert_eq!(chunked.body, b"abc");
+        assert!(!chunked.complete);
+        assert_eq!(chunked.consumed, 8);
+    }
 }
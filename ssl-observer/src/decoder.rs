@@ -0,0 +1,260 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use std::collections::HashMap;
+
+use ssl_observer_common::ProbeSslData;
+
+use crate::config::Decoders;
+use crate::decode::{fingerprint, parse_http, Protocol};
+use crate::plugin::WasmDecoder;
+use crate::protocols;
+
+// 识别连接协议时最多检查的事件数
+const DETECT_ATTEMPTS: u8 = 4;
+// 连接空闲超过该时间后清理
+const IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+/// label stored for connections no decoder recognized
+pub const UNKNOWN_PROTOCOL: &str = "unknown";
+
+/// a protocol parser, chosen per connection from its first bytes
+pub trait Decoder: Send + Sync {
+    /// name used in the `[decoders]` section of config.toml
+    fn name(&self) -> &'static str;
+
+    /// return the protocol label when a connection starting with `payload` belongs to this decoder
+    fn detect(&self, payload: &[u8]) -> Option<&'static str>;
+
+    /// create the state kept for one connection
+    fn connect(&self) -> Box<dyn DecoderState>;
+}
+
+/// per-connection decoding state
+#[async_trait]
+pub trait DecoderState: Send {
+    /// decode one event's payload to text
+    async fn decode(&mut self, data: &ProbeSslData) -> String;
+}
+
+/// HTTP/1.x and HTTP/2 events, with chunked and content-encoding decoding
+pub struct HttpDecoder;
+
+struct HttpState;
+
+impl Decoder for HttpDecoder {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn detect(&self, payload: &[u8]) -> Option<&'static str> {
+        match fingerprint(payload)? {
+            protocol @ (Protocol::Http1 | Protocol::Http2) => Some(protocol.as_str()),
+            _ => None,
+        }
+    }
+
+    fn connect(&self) -> Box<dyn DecoderState> {
+        Box::new(HttpState)
+    }
+}
+
+#[async_trait]
+impl DecoderState for HttpState {
+    async fn decode(&mut self, data: &ProbeSslData) -> String {
+        parse_http(&data.buf[..data.len]).await
+    }
+}
+
+/// one of the lightweight non-HTTP parsers in `protocols`
+pub struct ProtocolDecoder {
+    protocol: Protocol,
+}
+
+struct ProtocolState {
+    protocol: Protocol,
+}
+
+impl Decoder for ProtocolDecoder {
+    fn name(&self) -> &'static str {
+        self.protocol.as_str()
+    }
+
+    fn detect(&self, payload: &[u8]) -> Option<&'static str> {
+        (fingerprint(payload)? == self.protocol).then(|| self.protocol.as_str())
+    }
+
+    fn connect(&self) -> Box<dyn DecoderState> {
+        Box::new(ProtocolState {
+            protocol: self.protocol,
+        })
+    }
+}
+
+#[async_trait]
+impl DecoderState for ProtocolState {
+    async fn decode(&mut self, data: &ProbeSslData) -> String {
+        protocols::describe(self.protocol, &data.buf[..data.len])
+    }
+}
+
+/// compiled-in decoders by name, register new parsers here
+pub fn builtin(name: &str) -> Option<Box<dyn Decoder>> {
+    let protocols = [
+        Protocol::Smtp,
+        Protocol::Imap,
+        Protocol::Pop3,
+        Protocol::Postgres,
+        Protocol::Mysql,
+        Protocol::Redis,
+    ];
+    match name {
+        "http" => Some(Box::new(HttpDecoder)),
+        name => protocols
+            .into_iter()
+            .find(|protocol| protocol.as_str() == name)
+            .map(|protocol| Box::new(ProtocolDecoder { protocol }) as Box<dyn Decoder>),
+    }
+}
+
+// 单条连接选中的解码器与状态
+struct Conn {
+    // 识别前为 None，使用 fallback 的状态
+    protocol: Option<&'static str>,
+    state: Box<dyn DecoderState>,
+    attempts: u8,
+    last_ns: u64,
+}
+
+/// decoders enabled in config.toml, tried in order on each new connection
+pub struct DecoderRegistry {
+    decoders: Vec<Box<dyn Decoder>>,
+    fallback: Box<dyn Decoder>,
+    conns: HashMap<(u32, u64), Conn>,
+    events: u64,
+}
+
+impl DecoderRegistry {
+    pub fn new(config: &Decoders) -> Self {
+        // 内置解码器优先，其次是同名插件
+        let resolve = |name: &str| -> Option<Box<dyn Decoder>> {
+            builtin(name).or_else(|| {
+                let plugin = config.plugins.iter().find(|plugin| plugin.name == name)?;
+                match WasmDecoder::load(plugin) {
+                    Ok(decoder) => Some(Box::new(decoder)),
+                    Err(e) => {
+                        warn!("Failed to load decoder plugin {}: {}", name, e);
+                        None
+                    }
+                }
+            })
+        };
+        let decoders: Vec<Box<dyn Decoder>> = config
+            .enabled
+            .iter()
+            .filter_map(|name| {
+                let decoder = resolve(name);
+                if decoder.is_none() {
+                    warn!("Unknown decoder \"{}\" in config.toml", name);
+                }
+                decoder
+            })
+            .collect();
+        let fallback = resolve(&config.fallback).unwrap_or_else(|| {
+            warn!("Unknown fallback decoder \"{}\", using http", config.fallback);
+            Box::new(HttpDecoder)
+        });
+        let names: Vec<&str> = decoders.iter().map(|decoder| decoder.name()).collect();
+        info!("Decoders: {}, fallback: {}", names.join(", "), fallback.name());
+        Self {
+            decoders,
+            fallback,
+            conns: HashMap::new(),
+            events: 0,
+        }
+    }
+
+    /// decode one event, also return the connection's protocol label when this event decided it
+    pub async fn decode(&mut self, data: &ProbeSslData) -> (String, Option<&'static str>) {
+        if data.is_handshake || data.len == 0 {
+            return (self.fallback.connect().decode(data).await, None);
+        }
+        let ts = data.timestamp_ns;
+        self.events += 1;
+        if self.events.is_multiple_of(SWEEP_INTERVAL) {
+            self.conns
+                .retain(|_, conn| ts.saturating_sub(conn.last_ns) <= IDLE_TIMEOUT_NS);
+        }
+
+        let fallback = &self.fallback;
+        let conn = self
+            .conns
+            .entry((data.tgid, data.conn_id))
+            .or_insert_with(|| Conn {
+                protocol: None,
+                state: fallback.connect(),
+                attempts: 0,
+                last_ns: ts,
+            });
+        conn.last_ns = ts;
+        let mut detected = None;
+        if conn.protocol.is_none() {
+            conn.attempts += 1;
+            let payload = &data.buf[..data.len];
+            match self
+                .decoders
+                .iter()
+                .find_map(|decoder| Some((decoder.detect(payload)?, decoder)))
+            {
+                Some((protocol, decoder)) => {
+                    conn.protocol = Some(protocol);
+                    conn.state = decoder.connect();
+                    detected = Some(protocol);
+                }
+                // 多次仍未识别则一直使用 fallback
+                None if conn.attempts >= DETECT_ATTEMPTS => {
+                    conn.protocol = Some(UNKNOWN_PROTOCOL);
+                    detected = Some(UNKNOWN_PROTOCOL);
+                }
+                None => {}
+            }
+        }
+        (conn.state.decode(data).await, detected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DecoderRegistry;
+    use crate::config::Decoders;
+    use crate::test_util::event;
+    use ssl_observer_common::{ProbeSslData, WRITE};
+
+    fn write(conn_id: u64, payload: &[u8]) -> ProbeSslData {
+        ProbeSslData { conn_id, ..event(WRITE, 1, payload) }
+    }
+
+    #[tokio::test]
+    async fn reports_each_connection_once() {
+        let mut registry = DecoderRegistry::new(&Decoders::default());
+        let (_, detected) = registry.decode(&write(1, b"GET / HTTP/1.1\r\n\r\n")).await;
+        assert_eq!(detected, Some("http1"));
+        let (content, detected) = registry.decode(&write(2, b"*1\r\n$4\r\nPING\r\n")).await;
+        assert_eq!(detected, Some("redis"));
+        assert!(content.contains("PING"));
+        // 已识别的连接不再重复上报
+        let (_, detected) = registry.decode(&write(2, b"+PONG\r\n")).await;
+        assert_eq!(detected, None);
+    }
+
+    #[tokio::test]
+    async fn disabled_protocol_uses_fallback() {
+        // 只启用 http 时其他协议落到 fallback
+        let config = Decoders { enabled: vec!["http".to_string()], fallback: "http".to_string(), plugins: Vec::new() };
+        let mut registry = DecoderRegistry::new(&config);
+        for _ in 0..3 {
+            assert_eq!(registry.decode(&write(3, b"*1\r\n$4\r\nPING\r\n")).await.1, None);
+        }
+        assert_eq!(registry.decode(&write(3, b"+PONG\r\n")).await.1, Some("unknown"));
+    }
+}
@@ -0,0 +1,347 @@
+use lazy_static::lazy_static;
+use log::{info, warn};
+use prost_reflect::{DescriptorPool, DynamicMessage, MessageDescriptor};
+use std::fs;
+
+use crate::config::CONFIG;
+use crate::decode::{decode_content, MAX_DECODED_SIZE};
+use crate::http1::{HttpExchange, HttpMessage};
+
+// 消息前缀：1 字节压缩标志 + 4 字节大端长度
+const PREFIX_SIZE: usize = 5;
+// 原始 wire format 嵌套展开的最大深度
+const MAX_DEPTH: usize = 8;
+
+lazy_static! {
+    static ref DESCRIPTORS: Option<DescriptorPool> = load_descriptors();
+}
+
+fn load_descriptors() -> Option<DescriptorPool> {
+    let path = CONFIG.grpc.descriptor_set.as_ref()?;
+    let pool = fs::read(path)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| DescriptorPool::decode(bytes.as_slice()).map_err(|e| e.to_string()));
+    match pool {
+        Ok(pool) => {
+            info!("Loaded {} gRPC services from {}", pool.services().len(), path);
+            Some(pool)
+        }
+        Err(e) => {
+            warn!("Failed to load descriptor set {}: {}", path, e);
+            None
+        }
+    }
+}
+
+/// method and status of one gRPC call
+pub struct GrpcCall {
+    /// "/package.Service/Method", taken from `:path`
+    pub method: String,
+    pub status: Option<i32>,
+    pub message: Option<String>,
+}
+
+pub fn is_grpc(message: &HttpMessage) -> bool {
+    message
+        .header("content-type")
+        .is_some_and(|content_type| content_type.starts_with("application/grpc"))
+}
+
+/// return the gRPC method and status of an exchange, None when it is not gRPC
+pub fn grpc_call(exchange: &HttpExchange) -> Option<GrpcCall> {
+    let request = exchange.request.as_ref().filter(|message| is_grpc(message));
+    let response = exchange.response.as_ref().filter(|message| is_grpc(message));
+    if request.is_none() && response.is_none() {
+        return None;
+    }
+    let method = request
+        .and_then(|request| request.start_line.split(' ').nth(1))
+        .unwrap_or("-")
+        .to_string();
+    // 状态通常在 trailer 中，Trailers-Only 响应则直接放在头部
+    let field = |name: &str| {
+        let response = response?;
+        response
+            .trailers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .or_else(|| response.header(name))
+    };
+    Some(GrpcCall {
+        method,
+        status: field("grpc-status").and_then(|status| status.parse().ok()),
+        message: field("grpc-message"),
+    })
+}
+
+// 根据方法路径找到请求或响应的消息类型
+fn message_descriptor(pool: &DescriptorPool, method: &str, is_response: bool) -> Option<MessageDescriptor> {
+    let (service, name) = method.trim_start_matches('/').rsplit_once('/')?;
+    let method = pool
+        .get_service_by_name(service)?
+        .methods()
+        .find(|method| method.name() == name)?;
+    Some(if is_response { method.output() } else { method.input() })
+}
+
+/// render the length-prefixed messages of a gRPC body, as JSON when the descriptor set knows the method
+pub async fn render_body(message: &HttpMessage, method: &str, is_response: bool) -> String {
+    let descriptor = DESCRIPTORS
+        .as_ref()
+        .and_then(|pool| message_descriptor(pool, method, is_response));
+    render_messages(message, descriptor.as_ref()).await
+}
+
+async fn render_messages(message: &HttpMessage, descriptor: Option<&MessageDescriptor>) -> String {
+    let encoding = message
+        .header("grpc-encoding")
+        .unwrap_or_else(|| "identity".to_string());
+    let mut lines = Vec::new();
+    let mut rest = message.body();
+    let mut index = 0;
+    while rest.len() >= PREFIX_SIZE {
+        let compressed = rest[0] == 1;
+        let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+        if rest.len() < PREFIX_SIZE + len {
+            break;
+        }
+        let data = &rest[PREFIX_SIZE..PREFIX_SIZE + len];
+        rest = &rest[PREFIX_SIZE + len..];
+        index += 1;
+
+        lines.push(format!(
+            "[grpc] message {} ({} bytes{})",
+            index,
+            len,
+            if compressed { ", compressed" } else { "" }
+        ));
+        let data = if compressed {
+            match decode_content(&encoding, data).await {
+                Ok(output) => {
+                    if output.truncated {
+                        lines.push(format!("{} output truncated at {} bytes", encoding, MAX_DECODED_SIZE));
+                    }
+                    output.data
+                }
+                Err(e) => {
+                    lines.push(format!("{} decode failed: {}", encoding, e));
+                    continue;
+                }
+            }
+        } else {
+            data.to_vec()
+        };
+        let decoded = descriptor.and_then(|descriptor| {
+            let message = DynamicMessage::decode(descriptor.clone(), data.as_slice()).ok()?;
+            serde_json::to_string_pretty(&message).ok()
+        });
+        match decoded {
+            Some(json) => lines.push(json),
+            None => lines.push(decode_raw(&data)),
+        }
+    }
+    if !rest.is_empty() {
+        lines.push(format!("[grpc] {} bytes incomplete", rest.len()));
+    }
+    lines.join("\r\n")
+}
+
+/// show protobuf wire-format fields without a schema, like `protoc --decode_raw`
+pub fn decode_raw(data: &[u8]) -> String {
+    match wire_fields(data, 0) {
+        Some(lines) => lines.join("\r\n"),
+        None => data.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// 按 wire format 逐个字段解析，不合法时返回 None
+fn wire_fields(data: &[u8], depth: usize) -> Option<Vec<String>> {
+    let indent = "  ".repeat(depth);
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let field = key >> 3;
+        if field == 0 {
+            return None;
+        }
+        match key & 0x7 {
+            0 => lines.push(format!("{}{}: {}", indent, field, read_varint(data, &mut pos)?)),
+            1 => {
+                let bytes = data.get(pos..pos + 8)?;
+                pos += 8;
+                let value = u64::from_le_bytes(bytes.try_into().ok()?);
+                lines.push(format!("{}{}: 0x{:016x}", indent, field, value));
+            }
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let bytes = data.get(pos..pos.checked_add(len)?)?;
+                pos += len;
+                // 可打印文本优先按字符串显示，否则尝试展开为嵌套消息
+                let text = std::str::from_utf8(bytes)
+                    .ok()
+                    .filter(|text| !text.chars().any(|c| c.is_control() && c != '\n' && c != '\t'));
+                let nested = if text.is_none() && depth < MAX_DEPTH && !bytes.is_empty() {
+                    wire_fields(bytes, depth + 1)
+                } else {
+                    None
+                };
+                match (text, nested) {
+                    (Some(text), _) => lines.push(format!("{}{}: {:?}", indent, field, text)),
+                    (None, Some(nested)) => {
+                        lines.push(format!("{}{} {{", indent, field));
+                        lines.extend(nested);
+                        lines.push(format!("{}}}", indent));
+                    }
+                    (None, None) => {
+                        let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        lines.push(format!("{}{}: 0x{}", indent, field, hex));
+                    }
+                }
+            }
+            5 => {
+                let bytes = data.get(pos..pos + 4)?;
+                pos += 4;
+                let value = u32::from_le_bytes(bytes.try_into().ok()?);
+                lines.push(format!("{}{}: 0x{:08x}", indent, field, value));
+            }
+            // group 已废弃，视为不合法
+            _ => return None,
+        }
+    }
+    Some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_raw, message_descriptor, render_messages};
+    use crate::http1::HttpMessage;
+    use prost_reflect::prost_types::field_descriptor_proto::{Label, Type};
+    use prost_reflect::prost_types::{
+        DescriptorProto, FieldDescriptorProto, FileDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto,
+    };
+    use prost_reflect::DescriptorPool;
+    use ssl_observer_common::WRITE;
+
+    // echo.Echo/Say，请求与响应都是只有 string text = 1 的 echo.Ping
+    fn pool() -> DescriptorPool {
+        let file = FileDescriptorProto {
+            name: Some("echo.proto".to_string()),
+            package: Some("echo".to_string()),
+            syntax: Some("proto3".to_string()),
+            message_type: vec![DescriptorProto {
+                name: Some("Ping".to_string()),
+                field: vec![FieldDescriptorProto {
+                    name: Some("text".to_string()),
+                    json_name: Some("text".to_string()),
+                    number: Some(1),
+                    label: Some(Label::Optional as i32),
+                    r#type: Some(Type::String as i32),
+                    ..FieldDescriptorProto::default()
+                }],
+                ..DescriptorProto::default()
+            }],
+            service: vec![ServiceDescriptorProto {
+                name: Some("Echo".to_string()),
+                method: vec![MethodDescriptorProto {
+                    name: Some("Say".to_string()),
+                    input_type: Some(".echo.Ping".to_string()),
+                    output_type: Some(".echo.Ping".to_string()),
+                    ..MethodDescriptorProto::default()
+                }],
+                ..ServiceDescriptorProto::default()
+            }],
+            ..FileDescriptorProto::default()
+        };
+        let mut pool = DescriptorPool::new();
+        pool.add_file_descriptor_proto(file).unwrap();
+        pool
+    }
+
+    // 带 5 字节前缀的 gRPC 消息
+    fn frame(compressed: bool, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![u8::from(compressed)];
+        frame.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    fn message(headers: &str, body: &[u8]) -> HttpMessage {
+        let mut raw = format!(
+            "POST /echo.Echo/Say HTTP/2\r\ncontent-type: application/grpc\r\n{}\r\n",
+            headers
+        )
+        .into_bytes();
+        raw.extend_from_slice(body);
+        HttpMessage {
+            start_line: "POST /echo.Echo/Say HTTP/2".to_string(),
+            raw,
+            start_ns: 0,
+            end_ns: 0,
+            truncated: false,
+            rw: WRITE,
+            trailers: Vec::new(),
+        }
+    }
+
+    // text: "hi"
+    const PING: &[u8] = &[0x0a, 0x02, b'h', b'i'];
+
+    #[test]
+    fn decodes_raw_wire_format() {
+        // 1: 150, 2: "abc", 3 { 1: 1 }
+        let data = [0x08, 0x96, 0x01, 0x12, 0x03, b'a', b'b', b'c', 0x1a, 0x02, 0x08, 0x01];
+        assert_eq!(decode_raw(&data), "1: 150\r\n2: \"abc\"\r\n3 {\r\n  1: 1\r\n}");
+        assert_eq!(decode_raw(&[0x08]), "08");
+    }
+
+    #[tokio::test]
+    async fn renders_raw_fields_without_descriptor() {
+        let mut body = frame(false, PING);
+        body.extend_from_slice(&[0, 0, 0]);
+        let text = render_messages(&message("", &body), None).await;
+        assert_eq!(text, "[grpc] message 1 (4 bytes)\r\n1: \"hi\"\r\n[grpc] 3 bytes incomplete");
+    }
+
+    #[tokio::test]
+    async fn decompresses_gzip_messages() {
+        use async_compression::tokio::bufread::GzipEncoder;
+        use tokio::io::AsyncReadExt;
+
+        let mut gzip = Vec::new();
+        GzipEncoder::new(PING).read_to_end(&mut gzip).await.unwrap();
+        let body = frame(true, &gzip);
+        let text = render_messages(&message("grpc-encoding: gzip\r\n", &body), None).await;
+        assert_eq!(
+            text,
+            format!("[grpc] message 1 ({} bytes, compressed)\r\n1: \"hi\"", gzip.len())
+        );
+
+        let text = render_messages(&message("grpc-encoding: gzip\r\n", &frame(true, b"not gzip")), None).await;
+        assert!(text.starts_with("[grpc] message 1 (8 bytes, compressed)\r\ngzip decode failed"));
+    }
+
+    #[tokio::test]
+    async fn renders_json_with_descriptor() {
+        let pool = pool();
+        let descriptor = message_descriptor(&pool, "/echo.Echo/Say", false).unwrap();
+        assert_eq!(descriptor.full_name(), "echo.Ping");
+        assert!(message_descriptor(&pool, "/echo.Echo/Missing", false).is_none());
+        let text = render_messages(&message("", &frame(false, PING)), Some(&descriptor)).await;
+        assert_eq!(text, "[grpc] message 1 (4 bytes)\r\n{\n  \"text\": \"hi\"\n}");
+    }
+}
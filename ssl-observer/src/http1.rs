@@ -0,0 +1,627 @@
+use std::collections::{HashMap, VecDeque};
+
+use ssl_observer_common::{ProbeSslData, READ, WRITE};
+
+use crate::utils::sanitize_comm;
+
+// 头部超过该长度仍未结束，认为不是 HTTP 或已失步
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+// 单个报文的最大缓存，超过后按截断输出
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+// 连接空闲超过该时间后清理
+const IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+const METHODS: &[&str] = &[
+    "GET", "POST", "PUT", "DELETE", "HEAD", "OPTIONS", "PATCH", "CONNECT", "TRACE",
+];
+
+/// one complete HTTP/1.x request or response
+pub struct HttpMessage {
+    pub start_line: String,
+    /// full message bytes, header and body, HTTP/2 messages are rendered in HTTP/1.x form
+    pub raw: Vec<u8>,
+    pub start_ns: u64,
+    pub end_ns: u64,
+    pub truncated: bool,
+    /// READ or WRITE, as seen by the observed process
+    pub rw: u8,
+    /// HTTP/2 trailing headers, chunked trailers stay in `raw`
+    pub trailers: Vec<(String, String)>,
+}
+
+impl HttpMessage {
+    pub fn is_response(&self) -> bool {
+        self.start_line.starts_with("HTTP/")
+    }
+
+    /// return whether the observed process sent this message
+    pub fn is_sent(&self) -> bool {
+        self.rw == WRITE
+    }
+
+    /// return the request method
+    pub fn method(&self) -> Option<&str> {
+        if self.is_response() {
+            return None;
+        }
+        self.start_line.split(' ').next()
+    }
+
+    /// return the response status code
+    pub fn status(&self) -> Option<u16> {
+        if !self.is_response() {
+            return None;
+        }
+        self.start_line.split(' ').nth(1)?.parse().ok()
+    }
+
+    /// return the value of a header field, case-insensitive
+    pub fn header(&self, name: &str) -> Option<String> {
+        let header_end = find(&self.raw, b"\r\n\r\n").unwrap_or(self.raw.len());
+        let (_, headers) = parse_header(&self.raw[..header_end])?;
+        headers
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// return the message body, still in its transfer and content encoding
+    pub fn body(&self) -> &[u8] {
+        match find(&self.raw, b"\r\n\r\n") {
+            Some(pos) => &self.raw[pos + 4..],
+            None => &[],
+        }
+    }
+}
+
+/// return when the SSL call of an event started
+///
+/// Writes hand over their bytes when the call starts, so `delta_ns` is subtracted. A read may
+/// block long before any byte arrives, so it keeps the return time.
+pub fn call_start_ns(data: &ProbeSslData) -> u64 {
+    if data.rw == WRITE {
+        data.timestamp_ns.saturating_sub(data.delta_ns)
+    } else {
+        data.timestamp_ns
+    }
+}
+
+/// a request paired with its response, either side may be missing when the connection went idle
+pub struct HttpExchange {
+    pub pid: u32,
+    pub tgid: u32,
+    pub conn_id: u64,
+    /// HTTP/2 stream, None for HTTP/1.x
+    pub stream_id: Option<u32>,
+    pub comm: String,
+    pub request: Option<HttpMessage>,
+    pub response: Option<HttpMessage>,
+}
+
+impl HttpExchange {
+    /// return the time from the last request byte to the first response byte
+    pub fn latency_ns(&self) -> Option<u64> {
+        let request = self.request.as_ref()?;
+        let response = self.response.as_ref()?;
+        Some(response.start_ns.saturating_sub(request.end_ns))
+    }
+
+    /// return the timestamp of the exchange, the earliest known side
+    pub fn timestamp_ns(&self) -> u64 {
+        match (&self.request, &self.response) {
+            (Some(request), _) => request.start_ns,
+            (None, Some(response)) => response.start_ns,
+            (None, None) => 0,
+        }
+    }
+}
+
+/// structured start line and common headers of one HTTP/1.x message
+#[derive(Debug, Default, PartialEq)]
+pub struct HttpHead {
+    pub method: Option<String>,
+    /// request target as sent, usually path and query
+    pub path: Option<String>,
+    pub host: Option<String>,
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub content_length: Option<u64>,
+    pub user_agent: Option<String>,
+}
+
+impl HttpHead {
+    /// parse the start line and headers at the beginning of a payload, headers may be cut off
+    pub fn parse(payload: &[u8]) -> Option<Self> {
+        // 重组的 HTTP/2 响应以 "HTTP/2 " 开头
+        if !is_http_start(payload) && !payload.starts_with(b"HTTP/2 ") {
+            return None;
+        }
+        let header_end = find(payload, b"\r\n\r\n").unwrap_or(payload.len());
+        let text = String::from_utf8_lossy(&payload[..header_end]);
+        let mut lines = text.split("\r\n");
+        let mut parts = lines.next()?.splitn(3, ' ');
+        let mut head = HttpHead::default();
+        if text.starts_with("HTTP/") {
+            head.status = parts.nth(1).and_then(|status| status.parse().ok());
+        } else {
+            head.method = parts.next().map(str::to_string);
+            head.path = parts.next().map(str::to_string);
+        }
+        for (key, value) in lines.filter_map(|line| line.split_once(':')) {
+            let value = value.trim().to_string();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "host" => head.host = Some(value),
+                "content-type" => head.content_type = Some(value),
+                "content-length" => head.content_length = value.parse().ok(),
+                "user-agent" => head.user_agent = Some(value),
+                _ => {}
+            }
+        }
+        Some(head)
+    }
+}
+
+// 报文体长度的判定方式
+enum BodyLength {
+    Fixed(usize),
+    Chunked,
+    UntilClose,
+}
+
+// chunked 报文体的解析阶段
+#[derive(Default)]
+enum ChunkState {
+    // 等待 chunk-size 行
+    #[default]
+    Size,
+    // 等待 trailer 字段或结束的空行
+    Trailers,
+    // 分帧无法解析，只能等缓存超过上限后截断输出
+    Invalid,
+}
+
+// chunked 报文体的增量解析，只确定报文体在哪里结束，pos 之前的字节不再重复解析
+#[derive(Default)]
+struct ChunkScan {
+    state: ChunkState,
+    pos: usize,
+}
+
+impl ChunkScan {
+    // 从上次的位置继续解析，报文体完整时返回其长度
+    fn advance(&mut self, body: &[u8]) -> Option<usize> {
+        loop {
+            if matches!(self.state, ChunkState::Invalid) {
+                return None;
+            }
+            let rest = body.get(self.pos..)?;
+            let line_end = match find(rest, b"\r\n") {
+                Some(offset) => self.pos + offset,
+                None => {
+                    // 一行超过头部上限说明已失步
+                    if rest.len() > MAX_HEADER_SIZE {
+                        self.state = ChunkState::Invalid;
+                    }
+                    return None;
+                }
+            };
+            let line = &body[self.pos..line_end];
+            self.pos = line_end + 2;
+            match self.state {
+                // chunk-size [; ext-name [= ext-value]] CRLF
+                ChunkState::Size => {
+                    let size = std::str::from_utf8(line)
+                        .ok()
+                        .and_then(|line| usize::from_str_radix(line.split(';').next().unwrap_or("").trim(), 16).ok());
+                    match size {
+                        Some(0) => self.state = ChunkState::Trailers,
+                        // 长度来自抓到的流量，可能溢出；跳过数据与其后的 CRLF
+                        Some(size) => self.pos = self.pos.saturating_add(size).saturating_add(2),
+                        None => self.state = ChunkState::Invalid,
+                    }
+                }
+                ChunkState::Trailers if line.is_empty() => return Some(self.pos),
+                _ => {}
+            }
+        }
+    }
+}
+
+// 当前报文已解析的头部
+struct ParsedHead {
+    len: usize,
+    start_line: String,
+    headers: Vec<(String, String)>,
+}
+
+// 单方向的字节流
+#[derive(Default)]
+struct Http1Stream {
+    buf: Vec<u8>,
+    // 当前报文第一个字节到达的时间
+    start_ns: Option<u64>,
+    // 已查找过头部结束标记的长度，新数据到达后从这里继续查找
+    scanned: usize,
+    head: Option<ParsedHead>,
+    chunks: ChunkScan,
+}
+
+impl Http1Stream {
+    // start 为带来这些字节的 SSL 调用开始的时间
+    fn feed(&mut self, bytes: &[u8], start: u64) {
+        if self.buf.is_empty() {
+            self.start_ns = Some(start);
+        }
+        self.buf.extend_from_slice(bytes);
+    }
+
+    // 缓冲区开头变化后，之前的解析结果不再有效
+    fn reset(&mut self) {
+        self.scanned = 0;
+        self.head = None;
+        self.chunks = ChunkScan::default();
+    }
+
+    // 尝试从缓冲区中切出一个完整报文，no_body 表示响应对应 HEAD 请求
+    // 头部与 chunked 分帧的解析进度保存在流上，每个事件只解析新到达的字节
+    fn next_message(&mut self, ts: u64, rw: u8, no_body: bool) -> Option<HttpMessage> {
+        while self.head.is_none() {
+            // 结束标记可能跨两次到达，从已查找位置之前 3 个字节开始
+            let from = self.scanned.saturating_sub(3);
+            let header_end = match find(&self.buf[from..], b"\r\n\r\n") {
+                Some(pos) => from + pos + 4,
+                None => {
+                    self.scanned = self.buf.len();
+                    if self.buf.len() > MAX_HEADER_SIZE {
+                        self.buf.clear();
+                        self.reset();
+                    }
+                    return None;
+                }
+            };
+            match parse_header(&self.buf[..header_end]) {
+                Some((start_line, headers)) => {
+                    self.head = Some(ParsedHead {
+                        len: header_end,
+                        start_line,
+                        headers,
+                    })
+                }
+                // 头部无法解析时丢弃到空行为止，从之后的数据重新同步，避免缓冲区无限增长
+                None => {
+                    self.buf.drain(..header_end);
+                    self.reset();
+                    self.start_ns = (!self.buf.is_empty()).then_some(ts);
+                }
+            }
+        }
+        let ParsedHead {
+            len: header_end,
+            start_line,
+            headers,
+        } = self.head.as_ref()?;
+        let header_end = *header_end;
+        let is_response = start_line.starts_with("HTTP/");
+        let status: u16 = if is_response {
+            start_line.split(' ').nth(1).and_then(|s| s.parse().ok()).unwrap_or(0)
+        } else {
+            0
+        };
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.as_str())
+        };
+
+        let body_length = if is_response && (no_body || status / 100 == 1 || status == 204 || status == 304) {
+            BodyLength::Fixed(0)
+        } else if header("transfer-encoding").is_some_and(|te| te.to_ascii_lowercase().contains("chunked")) {
+            BodyLength::Chunked
+        } else if let Some(len) = header("content-length").and_then(|len| len.trim().parse().ok()) {
+            BodyLength::Fixed(len)
+        } else if is_response {
+            BodyLength::UntilClose
+        } else {
+            BodyLength::Fixed(0)
+        };
+
+        let body = &self.buf[header_end..];
+        let (message_len, truncated) = match body_length {
+            BodyLength::Fixed(len) if body.len() >= len => (header_end + len, false),
+            BodyLength::Chunked => match self.chunks.advance(body) {
+                Some(len) => (header_end + len, false),
+                None if self.buf.len() > MAX_MESSAGE_SIZE => (self.buf.len(), true),
+                None => return None,
+            },
+            _ if self.buf.len() > MAX_MESSAGE_SIZE => (self.buf.len(), true),
+            _ => return None,
+        };
+
+        let start_line = self.head.take()?.start_line;
+        self.reset();
+        let rest = self.buf.split_off(message_len);
+        let raw = std::mem::replace(&mut self.buf, rest);
+        let start_ns = self.start_ns.unwrap_or(ts);
+        // 流水线中紧随其后的报文从当前事件开始计时
+        self.start_ns = if self.buf.is_empty() { None } else { Some(ts) };
+        Some(HttpMessage {
+            start_line,
+            raw,
+            start_ns,
+            end_ns: ts,
+            truncated,
+            rw,
+            trailers: Vec::new(),
+        })
+    }
+
+    // 连接结束时，把读到关闭为止的报文体也当作完整报文输出
+    fn flush(&mut self, ts: u64, rw: u8) -> Option<HttpMessage> {
+        let header_end = find(&self.buf, b"\r\n\r\n")? + 4;
+        let (start_line, _) = parse_header(&self.buf[..header_end])?;
+        let raw = std::mem::take(&mut self.buf);
+        self.reset();
+        Some(HttpMessage {
+            start_line,
+            raw,
+            start_ns: self.start_ns.take().unwrap_or(ts),
+            end_ns: ts,
+            truncated: false,
+            rw,
+            trailers: Vec::new(),
+        })
+    }
+}
+
+// 一条连接上两个方向的状态
+#[derive(Default)]
+struct Http1Conn {
+    // 以读写方向区分，不假设被观测进程是客户端还是服务端
+    streams: [Http1Stream; 2],
+    // 首个报文确定方向是否为 HTTP
+    is_http: [Option<bool>; 2],
+    pending: VecDeque<HttpMessage>,
+    // 101 之后不再是 HTTP/1.x
+    upgraded: bool,
+    last_ns: u64,
+    pid: u32,
+    comm: String,
+}
+
+/// reassembles HTTP/1.x messages across SSL_read/SSL_write calls, per connection
+#[derive(Default)]
+pub struct Http1Tracker {
+    conns: HashMap<(u32, u64), Http1Conn>,
+    events: u64,
+}
+
+impl Http1Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// feed one event, return the exchanges completed by it
+    pub fn feed(&mut self, data: &ProbeSslData) -> Vec<HttpExchange> {
+        let mut exchanges = Vec::new();
+        if data.is_handshake || data.len == 0 {
+            return exchanges;
+        }
+        let key = (data.tgid, data.conn_id);
+        let ts = data.timestamp_ns;
+        let direction = if data.rw == WRITE { 1 } else { 0 };
+        let payload = &data.buf[..data.len];
+
+        let conn = self.conns.entry(key).or_default();
+        conn.last_ns = ts;
+        conn.pid = data.pid;
+        if conn.comm.is_empty() {
+            conn.comm = sanitize_comm(&data.comm);
+        }
+        if conn.upgraded {
+            return exchanges;
+        }
+        if conn.is_http[direction].is_none() {
+            conn.is_http[direction] = Some(is_http_start(payload));
+        }
+        if conn.is_http[direction] == Some(false) {
+            return exchanges;
+        }
+
+        conn.streams[direction].feed(payload, call_start_ns(data));
+        loop {
+            let no_body = conn
+                .pending
+                .front()
+                .and_then(|request| request.method())
+                .is_some_and(|method| method == "HEAD");
+            let message = match conn.streams[direction].next_message(ts, data.rw, no_body) {
+                Some(message) => message,
+                None => break,
+            };
+            if !message.is_response() {
+                conn.pending.push_back(message);
+                continue;
+            }
+            match message.status() {
+                Some(101) => conn.upgraded = true,
+                // 100 Continue 等中间响应不与请求配对
+                Some(status) if status / 100 == 1 => continue,
+                _ => {}
+            }
+            exchanges.push(HttpExchange {
+                pid: conn.pid,
+                tgid: key.0,
+                conn_id: key.1,
+                stream_id: None,
+                comm: conn.comm.clone(),
+                request: conn.pending.pop_front(),
+                response: Some(message),
+            });
+            if conn.upgraded {
+                break;
+            }
+        }
+
+        self.events += 1;
+        if self.events.is_multiple_of(SWEEP_INTERVAL) {
+            exchanges.extend(self.sweep(ts));
+        }
+        exchanges
+    }
+
+    /// take the bytes read past a 101 response, they belong to the upgraded protocol
+    pub fn take_upgraded(&mut self, tgid: u32, conn_id: u64) -> [Vec<u8>; 2] {
+        match self.conns.get_mut(&(tgid, conn_id)) {
+            Some(conn) if conn.upgraded => conn.streams.each_mut().map(|stream| {
+                stream.reset();
+                std::mem::take(&mut stream.buf)
+            }),
+            _ => Default::default(),
+        }
+    }
+
+    // 清理空闲连接，输出未配对的请求和读到关闭为止的响应
+    fn sweep(&mut self, now_ns: u64) -> Vec<HttpExchange> {
+        let idle: Vec<(u32, u64)> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| now_ns.saturating_sub(conn.last_ns) > IDLE_TIMEOUT_NS)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut exchanges = Vec::new();
+        for key in idle {
+            let mut conn = self.conns.remove(&key).unwrap();
+            let last_ns = conn.last_ns;
+            let mut responses: VecDeque<HttpMessage> = conn
+                .streams
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(direction, stream)| stream.flush(last_ns, if direction == 1 { WRITE } else { READ }))
+                .filter(|message| message.is_response())
+                .collect();
+            while !conn.pending.is_empty() || !responses.is_empty() {
+                exchanges.push(HttpExchange {
+                    pid: conn.pid,
+                    tgid: key.0,
+                    conn_id: key.1,
+                    stream_id: None,
+                    comm: conn.comm.clone(),
+                    request: conn.pending.pop_front(),
+                    response: responses.pop_front(),
+                });
+            }
+        }
+        exchanges
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// return whether a payload starts like an HTTP/1.x start line
+pub fn is_http_start(payload: &[u8]) -> bool {
+    if payload.starts_with(b"HTTP/1.") {
+        return true;
+    }
+    METHODS.iter().any(|method| {
+        payload.len() > method.len()
+            && payload.starts_with(method.as_bytes())
+            && payload[method.len()] == b' '
+    })
+}
+
+// 解析起始行与头部
+fn parse_header(header: &[u8]) -> Option<(String, Vec<(String, String)>)> {
+    let text = std::str::from_utf8(header).ok()?;
+    let mut lines = text.split("\r\n");
+    let start_line = lines.next()?.to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some((start_line, headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Http1Stream, Http1Tracker, HttpHead};
+    use crate::test_util::event;
+    use ssl_observer_common::{READ, WRITE};
+
+    #[test]
+    fn pairs_pipelined_requests() {
+        let mut tracker = Http1Tracker::new();
+        // 流水线中的两个请求
+        let requests = b"GET /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        assert!(tracker.feed(&event(WRITE, 10, requests)).is_empty());
+        // 第一个响应的报文体跨两次 SSL_read
+        assert!(tracker.feed(&event(READ, 20, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhe")).is_empty());
+        let exchanges = tracker.feed(&event(READ, 30, b"llo"));
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].request.as_ref().unwrap().method(), Some("GET"));
+        assert!(exchanges[0].response.as_ref().unwrap().raw.ends_with(b"\r\n\r\nhello"));
+        assert_eq!(exchanges[0].latency_ns(), Some(10));
+
+        let chunked = b"HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let exchanges = tracker.feed(&event(READ, 40, chunked));
+        assert_eq!(exchanges.len(), 1);
+        assert!(exchanges[0].request.as_ref().unwrap().raw.ends_with(b"\r\n\r\nabc"));
+        assert_eq!(exchanges[0].response.as_ref().unwrap().status(), Some(201));
+    }
+
+    #[test]
+    fn parses_head() {
+        let head = HttpHead::parse(b"POST /login?next=/ HTTP/1.1\r\nhost: x\r\nContent-Length: 3\r\nUser-Agent: curl/8.0").unwrap();
+        assert_eq!(head.method.as_deref(), Some("POST"));
+        assert_eq!(head.path.as_deref(), Some("/login?next=/"));
+        assert_eq!(head.host.as_deref(), Some("x"));
+        assert_eq!(head.content_length, Some(3));
+        assert_eq!(head.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(HttpHead::parse(b"HTTP/1.1 503 Service Unavailable\r\n\r\n").unwrap().status, Some(503));
+        assert_eq!(HttpHead::parse(b"\x16\x03\x01"), None);
+    }
+
+    #[test]
+    fn resyncs_after_unparsable_header() {
+        let mut stream = Http1Stream::default();
+        // 截断后剩余的报文体被当作新报文，其中的二进制数据不是合法 UTF-8
+        stream.feed(b"\xff\xfe binary\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok", 10);
+        let message = stream.next_message(20, READ, false).unwrap();
+        assert_eq!(message.status(), Some(200));
+        assert_eq!(message.start_ns, 20);
+        assert!(stream.buf.is_empty());
+
+        stream.feed(b"\xff\r\n\r\n", 30);
+        assert!(stream.next_message(30, READ, false).is_none());
+        assert!(stream.buf.is_empty());
+    }
+
+    #[test]
+    fn parses_only_new_bytes() {
+        let mut stream = Http1Stream::default();
+        let message = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\n\r\n";
+        let header_len = message.windows(4).position(|window| window == b"\r\n\r\n").unwrap() + 4;
+        // 每次只到达一个字节，头部与 chunk-size 行都会被拆开
+        for (i, byte) in message.iter().enumerate() {
+            stream.feed(&[*byte], i as u64);
+            let parsed = stream.next_message(i as u64, READ, false);
+            if i + 1 < message.len() {
+                assert!(parsed.is_none());
+                assert!(stream.scanned <= stream.buf.len());
+                // 第一个 chunk 到齐后，解析位置停在下一个 chunk-size 行
+                if i + 1 == header_len + 8 {
+                    assert!(stream.head.is_some());
+                    assert_eq!(stream.chunks.pos, 8);
+                }
+            } else {
+                let parsed = parsed.unwrap();
+                assert_eq!(parsed.raw, message);
+                assert!(!parsed.truncated);
+            }
+        }
+        assert!(stream.buf.is_empty() && stream.head.is_none() && stream.chunks.pos == 0);
+    }
+}
@@ -0,0 +1,550 @@
+use log::{debug, warn};
+use loona_hpack::Decoder;
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+
+use ssl_observer_common::{ProbeSslData, READ, WRITE};
+
+use crate::http1::{call_start_ns, HttpExchange, HttpMessage};
+use crate::utils::sanitize_comm;
+
+/// client connection preface, sent before the first frame
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+pub const FRAME_HEADER_SIZE: usize = 9;
+// 超过该长度的帧认为已失步
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+// 单个报文体的最大缓存，超过后按截断输出
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+// 连接空闲超过该时间后清理
+const IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+// 帧类型
+const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+// 帧标志
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+
+struct Frame<'a> {
+    kind: u8,
+    flags: u8,
+    stream_id: u32,
+    payload: &'a [u8],
+}
+
+// 从缓冲区开头解析一个完整的帧，返回帧与占用的字节数
+fn parse_frame(buf: &[u8]) -> Option<(Frame<'_>, usize)> {
+    if buf.len() < FRAME_HEADER_SIZE {
+        return None;
+    }
+    let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]) as usize;
+    if buf.len() < FRAME_HEADER_SIZE + len {
+        return None;
+    }
+    let frame = Frame {
+        kind: buf[3],
+        flags: buf[4],
+        stream_id: u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) & 0x7fff_ffff,
+        payload: &buf[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len],
+    };
+    Some((frame, FRAME_HEADER_SIZE + len))
+}
+
+fn frame_name(kind: u8) -> Option<&'static str> {
+    Some(match kind {
+        DATA => "DATA",
+        HEADERS => "HEADERS",
+        PRIORITY => "PRIORITY",
+        RST_STREAM => "RST_STREAM",
+        SETTINGS => "SETTINGS",
+        PUSH_PROMISE => "PUSH_PROMISE",
+        PING => "PING",
+        GOAWAY => "GOAWAY",
+        WINDOW_UPDATE => "WINDOW_UPDATE",
+        CONTINUATION => "CONTINUATION",
+        _ => return None,
+    })
+}
+
+// 去掉 PADDED 标志带来的填充，以及 HEADERS 的优先级字段
+fn frame_data<'a>(frame: &Frame<'a>) -> Option<&'a [u8]> {
+    let mut payload = frame.payload;
+    let mut pad_len = 0;
+    if frame.flags & FLAG_PADDED != 0 {
+        pad_len = *payload.first()? as usize;
+        payload = &payload[1..];
+    }
+    if frame.kind == HEADERS && frame.flags & FLAG_PRIORITY != 0 {
+        payload = payload.get(5..)?;
+    }
+    if frame.kind == PUSH_PROMISE {
+        payload = payload.get(4..)?;
+    }
+    payload.get(..payload.len().checked_sub(pad_len)?)
+}
+
+/// describe an event that holds whole HTTP/2 frames, header blocks need the connection's HPACK state
+pub fn describe_frames(buf: &[u8]) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut rest = buf;
+    if let Some(after) = rest.strip_prefix(PREFACE) {
+        lines.push("[h2] connection preface".to_string());
+        rest = after;
+    }
+    while !rest.is_empty() {
+        let (frame, consumed) = parse_frame(rest)?;
+        let name = frame_name(frame.kind)?;
+        // 连接级帧的 stream id 必须为 0，流级帧必须非 0
+        let connection_level = matches!(frame.kind, SETTINGS | PING | GOAWAY);
+        if connection_level != (frame.stream_id == 0) && frame.kind != WINDOW_UPDATE {
+            return None;
+        }
+        lines.push(format!(
+            "[h2] {} stream={} flags=0x{:02x} len={}",
+            name,
+            frame.stream_id,
+            frame.flags,
+            frame.payload.len()
+        ));
+        if frame.kind == DATA {
+            if let Some(data) = frame_data(&frame) {
+                lines.push(String::from_utf8_lossy(data).to_string());
+            }
+        }
+        rest = &rest[consumed..];
+    }
+    if lines.is_empty() {
+        return None;
+    }
+    Some(lines.join("\r\n"))
+}
+
+// 一个方向上未结束的头部块，等待 CONTINUATION
+struct HeaderBlock {
+    stream_id: u32,
+    end_stream: bool,
+    // PUSH_PROMISE 的头部块只用于维护 HPACK 状态
+    push_promise: bool,
+    fragment: Vec<u8>,
+}
+
+// 单方向的帧流与 HPACK 解码状态
+struct Http2Side {
+    buf: Vec<u8>,
+    decoder: Decoder<'static>,
+    header_block: Option<HeaderBlock>,
+    // HPACK 或帧失步后不再解析该方向
+    broken: bool,
+}
+
+impl Default for Http2Side {
+    fn default() -> Self {
+        Self {
+            buf: Vec::new(),
+            decoder: Decoder::new(),
+            header_block: None,
+            broken: false,
+        }
+    }
+}
+
+// 流上一个方向的报文
+#[derive(Default)]
+struct Http2Part {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    trailers: Vec<(String, String)>,
+    start_ns: Option<u64>,
+    end_ns: u64,
+    seen_headers: bool,
+    ended: bool,
+    truncated: bool,
+}
+
+impl Http2Part {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    // 转换成 HTTP/1.x 形式的报文，便于复用解压与存储逻辑
+    fn into_message(self, is_response: bool, rw: u8) -> Option<HttpMessage> {
+        if !self.seen_headers && self.body.is_empty() {
+            return None;
+        }
+        let start_line = if is_response {
+            format!("HTTP/2 {}", self.header(":status").unwrap_or("-"))
+        } else {
+            let target = self
+                .header(":path")
+                .or_else(|| self.header(":authority"))
+                .unwrap_or("-");
+            format!("{} {} HTTP/2", self.header(":method").unwrap_or("-"), target)
+        };
+        let mut raw = format!("{}\r\n", start_line);
+        if let Some(authority) = self.header(":authority") {
+            if self.header("host").is_none() {
+                raw.push_str(&format!("host: {}\r\n", authority));
+            }
+        }
+        for (name, value) in self.headers.iter().filter(|(name, _)| !name.starts_with(':')) {
+            raw.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        raw.push_str("\r\n");
+        let mut raw = raw.into_bytes();
+        raw.extend_from_slice(&self.body);
+        Some(HttpMessage {
+            start_line,
+            raw,
+            start_ns: self.start_ns.unwrap_or(self.end_ns),
+            end_ns: self.end_ns,
+            truncated: self.truncated || !self.ended,
+            rw,
+            trailers: self.trailers,
+        })
+    }
+}
+
+// 一个流上的请求与响应，下标 0 为请求，1 为响应
+#[derive(Default)]
+struct Http2Stream {
+    parts: [Http2Part; 2],
+}
+
+// 一条 HTTP/2 连接的状态
+struct Http2Conn {
+    // 以读写方向区分
+    sides: [Http2Side; 2],
+    // 发送连接前言的方向是客户端
+    client: usize,
+    streams: BTreeMap<u32, Http2Stream>,
+    last_ns: u64,
+    pid: u32,
+    comm: String,
+}
+
+impl Http2Conn {
+    // 处理一个方向上新到达的字节
+    fn feed(&mut self, direction: usize, ts: u64, start: u64) -> Vec<(u32, Http2Stream)> {
+        let mut finished = Vec::new();
+        let mut buf = std::mem::take(&mut self.sides[direction].buf);
+        let mut pos = 0;
+        while !self.sides[direction].broken {
+            let (frame, consumed) = match parse_frame(&buf[pos..]) {
+                Some(parsed) => parsed,
+                None => {
+                    if buf.len() - pos > MAX_FRAME_SIZE + FRAME_HEADER_SIZE {
+                        self.sides[direction].broken = true;
+                    }
+                    break;
+                }
+            };
+            finished.extend(self.handle_frame(direction, &frame, ts, start));
+            pos += consumed;
+        }
+        if self.sides[direction].broken {
+            buf.clear();
+        } else {
+            buf.drain(..pos);
+        }
+        self.sides[direction].buf = buf;
+        finished
+    }
+
+    fn handle_frame(&mut self, direction: usize, frame: &Frame, ts: u64, start: u64) -> Vec<(u32, Http2Stream)> {
+        let role = if direction == self.client { 0 } else { 1 };
+        let mut finished = Vec::new();
+        match frame.kind {
+            DATA => {
+                let data = match frame_data(frame) {
+                    Some(data) => data,
+                    None => return finished,
+                };
+                let part = &mut self.streams.entry(frame.stream_id).or_default().parts[role];
+                part.start_ns.get_or_insert(start);
+                part.end_ns = ts;
+                if part.body.len() + data.len() > MAX_BODY_SIZE {
+                    part.truncated = true;
+                } else {
+                    part.body.extend_from_slice(data);
+                }
+                if frame.flags & FLAG_END_STREAM != 0 {
+                    part.ended = true;
+                }
+            }
+            HEADERS | PUSH_PROMISE => {
+                let fragment = match frame_data(frame) {
+                    Some(fragment) => fragment,
+                    None => return finished,
+                };
+                self.sides[direction].header_block = Some(HeaderBlock {
+                    stream_id: frame.stream_id,
+                    end_stream: frame.kind == HEADERS && frame.flags & FLAG_END_STREAM != 0,
+                    push_promise: frame.kind == PUSH_PROMISE,
+                    fragment: fragment.to_vec(),
+                });
+                if frame.flags & FLAG_END_HEADERS != 0 {
+                    self.end_headers(direction, role, ts, start);
+                }
+            }
+            CONTINUATION => {
+                match self.sides[direction].header_block.as_mut() {
+                    Some(block) if block.stream_id == frame.stream_id => {
+                        block.fragment.extend_from_slice(frame.payload)
+                    }
+                    _ => return finished,
+                }
+                if frame.flags & FLAG_END_HEADERS != 0 {
+                    self.end_headers(direction, role, ts, start);
+                }
+            }
+            RST_STREAM => {
+                debug!("RST_STREAM stream {} error {:?}", frame.stream_id, frame.payload.get(..4));
+                if let Some(mut stream) = self.streams.remove(&frame.stream_id) {
+                    for part in stream.parts.iter_mut() {
+                        part.truncated |= !part.ended;
+                    }
+                    finished.push((frame.stream_id, stream));
+                }
+            }
+            SETTINGS => {
+                // 对端编码时动态表不能超过本方向通告的大小
+                for setting in frame.payload.chunks_exact(6) {
+                    let id = u16::from_be_bytes([setting[0], setting[1]]);
+                    let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+                    if id == SETTINGS_HEADER_TABLE_SIZE {
+                        self.sides[1 - direction]
+                            .decoder
+                            .set_max_allowed_table_size(value as usize);
+                    }
+                }
+            }
+            GOAWAY => {
+                // 大于 last_stream_id 的流不会被处理
+                let last_stream_id = match frame.payload.get(..4) {
+                    Some(id) => u32::from_be_bytes([id[0], id[1], id[2], id[3]]) & 0x7fff_ffff,
+                    None => return finished,
+                };
+                debug!("GOAWAY last stream {} error {:?}", last_stream_id, frame.payload.get(4..8));
+                let unprocessed = self.streams.split_off(&(last_stream_id + 1));
+                finished.extend(unprocessed);
+            }
+            _ => {}
+        }
+        if let Some(stream) = self.streams.get(&frame.stream_id) {
+            // 响应结束即为一次完整的交互
+            if stream.parts[1].ended {
+                finished.push((frame.stream_id, self.streams.remove(&frame.stream_id).unwrap()));
+            }
+        }
+        finished
+    }
+
+    // 头部块接收完整后做 HPACK 解码
+    fn end_headers(&mut self, direction: usize, role: usize, ts: u64, start: u64) {
+        let side = &mut self.sides[direction];
+        let block = match side.header_block.take() {
+            Some(block) => block,
+            None => return,
+        };
+        let headers: Vec<(String, String)> = match side.decoder.decode(&block.fragment) {
+            Ok(headers) => headers
+                .into_iter()
+                .map(|(name, value)| {
+                    (
+                        String::from_utf8_lossy(&name).to_string(),
+                        String::from_utf8_lossy(&value).to_string(),
+                    )
+                })
+                .collect(),
+            Err(e) => {
+                // 动态表状态丢失，之后的头部块都无法解码
+                warn!("HPACK decode failed, stop decoding this direction: {:?}", e);
+                side.broken = true;
+                return;
+            }
+        };
+        if block.push_promise {
+            return;
+        }
+
+        let part = &mut self.streams.entry(block.stream_id).or_default().parts[role];
+        part.start_ns.get_or_insert(start);
+        part.end_ns = ts;
+        if !part.seen_headers {
+            // 1xx 中间响应不作为最终响应头
+            let informational = role == 1
+                && headers
+                    .iter()
+                    .any(|(name, value)| name == ":status" && value.starts_with('1'));
+            if !informational {
+                part.headers = headers;
+                part.seen_headers = true;
+            }
+        } else {
+            part.trailers = headers;
+        }
+        if block.end_stream {
+            part.ended = true;
+        }
+    }
+}
+
+/// decodes HTTP/2 connections that start with the client preface, per connection
+#[derive(Default)]
+pub struct Http2Tracker {
+    conns: HashMap<(u32, u64), Http2Conn>,
+    events: u64,
+}
+
+impl Http2Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// feed one event, return the streams completed by it
+    pub fn feed(&mut self, data: &ProbeSslData) -> Vec<HttpExchange> {
+        let mut exchanges = Vec::new();
+        if data.is_handshake || data.len == 0 {
+            return exchanges;
+        }
+        let key = (data.tgid, data.conn_id);
+        let ts = data.timestamp_ns;
+        let direction = if data.rw == WRITE { 1 } else { 0 };
+        let mut payload = &data.buf[..data.len];
+
+        // 只跟踪从连接前言开始观测到的连接，中途加入时无法恢复 HPACK 状态
+        let conn = match self.conns.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                payload = match payload.strip_prefix(PREFACE) {
+                    Some(rest) => rest,
+                    None => return exchanges,
+                };
+                entry.insert(Http2Conn {
+                    sides: Default::default(),
+                    client: direction,
+                    streams: BTreeMap::new(),
+                    last_ns: ts,
+                    pid: data.pid,
+                    comm: sanitize_comm(&data.comm),
+                })
+            }
+        };
+        conn.last_ns = ts;
+        conn.pid = data.pid;
+        conn.sides[direction].buf.extend_from_slice(payload);
+        for (stream_id, stream) in conn.feed(direction, ts, call_start_ns(data)) {
+            exchanges.push(to_exchange(key, conn, stream_id, stream));
+        }
+
+        self.events += 1;
+        if self.events.is_multiple_of(SWEEP_INTERVAL) {
+            exchanges.extend(self.sweep(ts));
+        }
+        exchanges
+    }
+
+    // 清理空闲连接，未结束的流按截断输出
+    fn sweep(&mut self, now_ns: u64) -> Vec<HttpExchange> {
+        let idle: Vec<(u32, u64)> = self
+            .conns
+            .iter()
+            .filter(|(_, conn)| now_ns.saturating_sub(conn.last_ns) > IDLE_TIMEOUT_NS)
+            .map(|(key, _)| *key)
+            .collect();
+        let mut exchanges = Vec::new();
+        for key in idle {
+            let mut conn = self.conns.remove(&key).unwrap();
+            let streams = std::mem::take(&mut conn.streams);
+            for (stream_id, stream) in streams {
+                exchanges.push(to_exchange(key, &conn, stream_id, stream));
+            }
+        }
+        exchanges
+    }
+}
+
+fn to_exchange(key: (u32, u64), conn: &Http2Conn, stream_id: u32, stream: Http2Stream) -> HttpExchange {
+    let [request, response] = stream.parts;
+    // 发送连接前言的一方是客户端
+    let (sent, received) = if conn.client == 1 { (WRITE, READ) } else { (READ, WRITE) };
+    HttpExchange {
+        pid: conn.pid,
+        tgid: key.0,
+        conn_id: key.1,
+        stream_id: Some(stream_id),
+        comm: conn.comm.clone(),
+        request: request.into_message(false, sent),
+        response: response.into_message(true, received),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{describe_frames, Http2Tracker, PREFACE};
+    use crate::test_util::event;
+    use ssl_observer_common::{READ, WRITE};
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn pairs_streams() {
+        let mut tracker = Http2Tracker::new();
+        let mut client = PREFACE.to_vec();
+        client.extend(frame(0x4, 0, 0, &[]));
+        // :method GET, :scheme https, :path /, :authority "x"（加入动态表）
+        client.extend(frame(0x1, 0x5, 1, &[0x82, 0x87, 0x84, 0x41, 0x01, b'x']));
+        assert!(tracker.feed(&event(WRITE, 10, &client)).is_empty());
+
+        // 第二个请求复用动态表中的 :authority（索引 62）
+        let client = frame(0x1, 0x5, 3, &[0x82, 0x87, 0x84, 0xbe]);
+        assert!(tracker.feed(&event(WRITE, 15, &client)).is_empty());
+
+        // :status 200，报文体分两个 DATA 帧
+        let mut server = frame(0x4, 0, 0, &[]);
+        server.extend(frame(0x1, 0x4, 1, &[0x88]));
+        server.extend(frame(0x0, 0, 1, b"hel"));
+        server.extend(frame(0x0, 0x1, 1, b"lo"));
+        server.extend(frame(0x3, 0, 3, &[0, 0, 0, 8]));
+        let exchanges = tracker.feed(&event(READ, 20, &server));
+        assert_eq!(exchanges.len(), 2);
+        let request = exchanges[0].request.as_ref().unwrap();
+        assert_eq!(request.start_line, "GET / HTTP/2");
+        assert!(request.raw.starts_with(b"GET / HTTP/2\r\nhost: x\r\n"));
+        let response = exchanges[0].response.as_ref().unwrap();
+        assert_eq!(response.status(), Some(200));
+        assert!(response.raw.ends_with(b"\r\n\r\nhello"));
+        assert_eq!(exchanges[0].stream_id, Some(1));
+        // 被 RST_STREAM 取消的流
+        assert!(exchanges[1].request.as_ref().unwrap().raw.starts_with(b"GET / HTTP/2\r\nhost: x\r\n"));
+        assert!(exchanges[1].response.is_none());
+    }
+
+    #[test]
+    fn describes_frames() {
+        assert!(describe_frames(&frame(0x0, 0x1, 1, b"hi")).unwrap().ends_with("\r\nhi"));
+        assert_eq!(describe_frames(b"GET / HTTP/1.1\r\n\r\n"), None);
+    }
+}
@@ -0,0 +1,194 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use std::{
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+};
+
+use ssl_observer_common::{ProbeSslData, WRITE};
+
+use crate::rules::Alert;
+use crate::utils::{convert_timestamp_to_date, sanitize_comm};
+
+#[derive(Serialize)]
+struct JsonlEvent<'a> {
+    // 与告警区分
+    r#type: &'a str,
+    timestamp: String,
+    timestamp_ns: u64,
+    delta_ns: u64,
+    conn_id: u64,
+    pid: u32,
+    tgid: u32,
+    uid: u32,
+    buf_filled: u8,
+    rw: &'a str,
+    is_handshake: bool,
+    comm: String,
+    len: usize,
+    // utf8 或 base64
+    payload_encoding: &'a str,
+    payload: String,
+}
+
+#[derive(Serialize)]
+struct JsonlAlert<'a> {
+    // 与事件区分
+    r#type: &'a str,
+    timestamp: String,
+    timestamp_ns: u64,
+    source: &'a str,
+    rule: &'a str,
+    severity: &'a str,
+    pid: u32,
+    tgid: u32,
+    uid: Option<u32>,
+    conn_id: u64,
+    stream_id: Option<u32>,
+    rw: Option<&'a str>,
+    comm: &'a str,
+    host: Option<&'a str>,
+    scope: &'a str,
+    offset: Option<u64>,
+    excerpt: &'a str,
+}
+
+/// writes one JSON object per event, to stdout or appended to a file
+pub struct JsonlWriter {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    to_stdout: bool,
+}
+
+impl JsonlWriter {
+    /// parse `--output`, accepts "jsonl" or "jsonl=/path/to/file"
+    pub fn open(output: &str) -> Result<Self, anyhow::Error> {
+        let (format, path) = match output.split_once('=') {
+            Some((format, path)) => (format, Some(path)),
+            None => (output, None),
+        };
+        if format != "jsonl" {
+            return Err(anyhow::anyhow!("Unsupported output format \"{}\"", format));
+        }
+        let (inner, to_stdout): (Box<dyn Write + Send>, bool) = match path {
+            Some(path) if !path.is_empty() && path != "-" => (
+                Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+                false,
+            ),
+            _ => (Box::new(io::stdout()), true),
+        };
+        Ok(Self {
+            writer: BufWriter::new(inner),
+            to_stdout,
+        })
+    }
+
+    /// return whether events go to stdout, in which case the text dump is suppressed
+    pub fn to_stdout(&self) -> bool {
+        self.to_stdout
+    }
+
+    /// write one event, `content` is the decoder output after redaction
+    pub async fn write_event(&mut self, data: &ProbeSslData, content: &str) -> Result<(), anyhow::Error> {
+        let raw_buf: &[u8] = &data.buf[..data.len];
+        // 解码结果中出现替换字符说明是二进制数据，改为 base64 输出原始字节
+        let (payload_encoding, payload) = if content.contains(char::REPLACEMENT_CHARACTER) {
+            ("base64", STANDARD.encode(raw_buf))
+        } else {
+            ("utf8", content.to_string())
+        };
+        let event = JsonlEvent {
+            r#type: "event",
+            timestamp: convert_timestamp_to_date(data.timestamp_ns).await?,
+            timestamp_ns: data.timestamp_ns,
+            delta_ns: data.delta_ns,
+            conn_id: data.conn_id,
+            pid: data.pid,
+            tgid: data.tgid,
+            uid: data.uid,
+            buf_filled: data.buf_filled,
+            rw: if data.rw == WRITE { "write" } else { "read" },
+            is_handshake: data.is_handshake,
+            comm: sanitize_comm(&data.comm),
+            len: data.len,
+            payload_encoding,
+            payload,
+        };
+        serde_json::to_writer(&mut self.writer, &event)?;
+        self.writer.write_all(b"\n")?;
+        // 每行立即刷新，便于日志采集端实时读取
+        self.writer.flush()?;
+        Ok(())
+    }
+    pub async fn write_alert(&mut self, alert: &Alert) -> Result<(), anyhow::Error> {
+        let line = JsonlAlert {
+            r#type: "alert",
+            timestamp: convert_timestamp_to_date(alert.timestamp_ns).await?,
+            timestamp_ns: alert.timestamp_ns,
+            source: alert.source,
+            rule: &alert.rule,
+            severity: &alert.severity,
+            pid: alert.pid,
+            tgid: alert.tgid,
+            uid: alert.uid,
+            conn_id: alert.conn_id,
+            stream_id: alert.stream_id,
+            rw: alert.rw.map(|rw| if rw == WRITE { "write" } else { "read" }),
+            comm: &alert.comm,
+            host: alert.host.as_deref(),
+            scope: alert.scope,
+            offset: alert.offset,
+            excerpt: &alert.excerpt,
+        };
+        serde_json::to_writer(&mut self.writer, &line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonlWriter;
+    use crate::test_util::event;
+    use ssl_observer_common::{ProbeSslData, WRITE};
+
+    // 写入一个事件并读回对应的 JSON 行
+    async fn write(name: &str, content: &str) -> serde_json::Value {
+        let path = std::env::temp_dir().join(format!("ssl-observer-{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut writer = JsonlWriter::open(&format!("jsonl={}", path.display())).unwrap();
+        assert!(!writer.to_stdout());
+
+        let data = ProbeSslData {
+            delta_ns: 5,
+            conn_id: 42,
+            pid: 8,
+            ..event(WRITE, 1_000_000_000, b"\x1f\x8b\x08\x00")
+        };
+        writer.write_event(&data, content).await.unwrap();
+        drop(writer);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        serde_json::from_str(text.trim_end()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_given_content() {
+        // 输出调用方传入的解码与脱敏结果，不再从原始字节重新解码
+        let line = write("content", "Authorization: [REDACTED]").await;
+        assert_eq!(line["type"], "event");
+        assert_eq!(line["payload_encoding"], "utf8");
+        assert_eq!(line["payload"], "Authorization: [REDACTED]");
+        assert_eq!(line["rw"], "write");
+        assert_eq!(line["tgid"], 7);
+        assert_eq!(line["conn_id"], 42);
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_base64() {
+        let line = write("base64", "\u{fffd}\u{fffd}").await;
+        assert_eq!(line["payload_encoding"], "base64");
+        assert_eq!(line["payload"], "H4sIAA==");
+    }
+}
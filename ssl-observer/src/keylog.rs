@@ -0,0 +1,178 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use ssl_observer_common::{
+    ProbeMasterSecret, EVP_MAX_MD_SIZE, SSL3_RANDOM_SIZE, SSL_MAX_MASTER_KEY_LENGTH, TLS1_3_VERSION,
+};
+
+use crate::utils::sanitize_comm;
+
+// 已写入的记录保留该时间后清理，之后重复的握手最多再写入一次相同的行
+const LOGGED_TIMEOUT_NS: u64 = 10 * 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+/// writes NSS key log lines, one file per observed process
+pub struct KeylogWriter {
+    dir: PathBuf,
+    files: HashMap<u32, File>,
+    // 已写入的 (tgid, client_random) 与最后一次出现的时间，握手完成后再次调用 SSL_do_handshake 不会重复写入
+    logged: HashMap<(u32, [u8; SSL3_RANDOM_SIZE]), u64>,
+    secrets: u64,
+}
+
+impl KeylogWriter {
+    pub fn create(dir: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: PathBuf::from(dir),
+            files: HashMap::new(),
+            logged: HashMap::new(),
+            secrets: 0,
+        })
+    }
+
+    pub fn write_secret(&mut self, secret: &ProbeMasterSecret) -> io::Result<()> {
+        self.secrets += 1;
+        if self.secrets.is_multiple_of(SWEEP_INTERVAL) {
+            self.sweep(secret.timestamp_ns);
+        }
+        if self
+            .logged
+            .insert((secret.tgid, secret.client_random), secret.timestamp_ns)
+            .is_some()
+        {
+            return Ok(());
+        }
+        let lines = format_secret(secret);
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let file = match self.files.get_mut(&secret.tgid) {
+            Some(file) => file,
+            None => {
+                // <comm>-<tgid>.keylog
+                let path = self.dir.join(format!(
+                    "{}-{}.keylog",
+                    sanitize_comm(&secret.comm),
+                    secret.tgid
+                ));
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                self.files.entry(secret.tgid).or_insert(file)
+            }
+        };
+        file.write_all(lines.as_bytes())?;
+        file.flush()
+    }
+
+    // 清理长时间未再出现的握手记录
+    fn sweep(&mut self, now_ns: u64) {
+        self.logged
+            .retain(|_, last_ns| now_ns.saturating_sub(*last_ns) <= LOGGED_TIMEOUT_NS);
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// 按 NSS Key Log 格式生成文本，TLS 1.3 每个密钥一行，未读到的密钥跳过
+fn format_secret(secret: &ProbeMasterSecret) -> String {
+    let client_random = hex(&secret.client_random);
+    if secret.version == TLS1_3_VERSION {
+        // TLS 1.3 下 session 的 master_key_length 等于握手摘要长度
+        let len = (secret.master_key_len as usize).min(EVP_MAX_MD_SIZE);
+        if len == 0 {
+            return String::new();
+        }
+        [
+            ("CLIENT_TRAFFIC_SECRET_0", &secret.client_app_traffic_secret),
+            ("SERVER_TRAFFIC_SECRET_0", &secret.server_app_traffic_secret),
+            ("EXPORTER_SECRET", &secret.exporter_master_secret),
+        ]
+        .iter()
+        .filter(|(_, value)| value.iter().any(|b| *b != 0))
+        .map(|(label, value)| format!("{} {} {}\n", label, client_random, hex(&value[..len])))
+        .collect()
+    } else {
+        format!(
+            "CLIENT_RANDOM {} {}\n",
+            client_random,
+            hex(&secret.master_key[..SSL_MAX_MASTER_KEY_LENGTH])
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_secret, KeylogWriter, LOGGED_TIMEOUT_NS, SWEEP_INTERVAL};
+    use ssl_observer_common::{
+        ProbeMasterSecret, EVP_MAX_MD_SIZE, SSL3_RANDOM_SIZE, SSL_MAX_MASTER_KEY_LENGTH, TASK_COMM_LEN,
+        TLS1_3_VERSION,
+    };
+
+    fn secret(version: i32) -> ProbeMasterSecret {
+        ProbeMasterSecret {
+            timestamp_ns: 0,
+            delta_ns: 0,
+            conn_id: 1,
+            pid: 7,
+            tgid: 7,
+            comm: [0; TASK_COMM_LEN],
+            version,
+            client_random: [0xab; SSL3_RANDOM_SIZE],
+            master_key_len: 2,
+            master_key: [0x01; SSL_MAX_MASTER_KEY_LENGTH],
+            client_app_traffic_secret: [0x11; EVP_MAX_MD_SIZE],
+            server_app_traffic_secret: [0x22; EVP_MAX_MD_SIZE],
+            exporter_master_secret: [0; EVP_MAX_MD_SIZE],
+        }
+    }
+
+    #[test]
+    fn tls12_client_random() {
+        let random = "ab".repeat(SSL3_RANDOM_SIZE);
+        assert_eq!(
+            format_secret(&secret(0x0303)),
+            format!("CLIENT_RANDOM {} {}\n", random, "01".repeat(SSL_MAX_MASTER_KEY_LENGTH))
+        );
+    }
+
+    #[test]
+    fn tls13_skips_missing_secrets() {
+        let random = "ab".repeat(SSL3_RANDOM_SIZE);
+        // 导出密钥的偏移量未配置，只输出两条流量密钥
+        assert_eq!(
+            format_secret(&secret(TLS1_3_VERSION)),
+            format!("CLIENT_TRAFFIC_SECRET_0 {0} 1111\nSERVER_TRAFFIC_SECRET_0 {0} 2222\n", random)
+        );
+        let mut empty = secret(TLS1_3_VERSION);
+        empty.master_key_len = 0;
+        assert_eq!(format_secret(&empty), "");
+    }
+
+    #[test]
+    fn writes_each_handshake_once() {
+        let dir = std::env::temp_dir().join(format!("ssl-observer-keylog-{}", std::process::id()));
+        let mut writer = KeylogWriter::create(dir.to_str().unwrap()).unwrap();
+        let first = secret(0x0303);
+        writer.write_secret(&first).unwrap();
+        writer.write_secret(&ProbeMasterSecret { timestamp_ns: 1, ..first }).unwrap();
+        let path = dir.join("-7.keylog");
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+
+        // 其他握手触发清理后，长时间未出现的记录被移除
+        for i in 1..SWEEP_INTERVAL {
+            let mut other = secret(0x0303);
+            other.client_random[..8].copy_from_slice(&i.to_be_bytes());
+            other.timestamp_ns = LOGGED_TIMEOUT_NS + 2;
+            writer.write_secret(&other).unwrap();
+        }
+        assert_eq!(writer.logged.len(), SWEEP_INTERVAL as usize - 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use ssl_observer_common::{ProbeMasterSecret, ProbeSslData, WRITE};
+
+use crate::config::{Latency, LatencyGroup};
+use crate::metrics::METRICS;
+use crate::utils::sanitize_comm;
+
+// 直方图星号的最大宽度，与 bcc 一致
+const BAR_WIDTH: u64 = 40;
+// 每个周期内分组的上限，超出的进程不再单独统计
+const MAX_GROUPS: usize = 1024;
+
+// 事件对应的 SSL 调用
+fn operation(data: &ProbeSslData) -> &'static str {
+    if data.rw == WRITE {
+        "write"
+    } else {
+        "read"
+    }
+}
+
+/// power-of-two histogram of latencies in microseconds, printed like bcc's tools
+#[derive(Clone, Default)]
+pub struct Log2Histogram {
+    slots: Vec<u64>,
+}
+
+impl Log2Histogram {
+    pub fn record(&mut self, ns: u64) {
+        let usecs = ns / 1000;
+        // 0 在第 0 格，[2^(i-1), 2^i) 在第 i 格
+        let slot = (u64::BITS - usecs.leading_zeros()) as usize;
+        if self.slots.len() <= slot {
+            self.slots.resize(slot + 1, 0);
+        }
+        self.slots[slot] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.slots.iter().sum()
+    }
+
+    /// render the histogram, `name` is the value column header
+    pub fn render(&self, name: &str) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{:>19} : count     distribution", name);
+        let Some(first) = self.slots.iter().position(|&count| count > 0) else {
+            return out;
+        };
+        let max = self.slots.iter().copied().max().unwrap_or(1);
+        for (slot, &count) in self.slots.iter().enumerate().skip(first) {
+            let (low, high) = match slot {
+                0 => (0, 0),
+                _ => (1u64 << (slot - 1), (1u64 << slot) - 1),
+            };
+            let stars = (count * BAR_WIDTH).div_ceil(max) as usize;
+            let _ = writeln!(
+                out,
+                "{:>10} -> {:<10} : {:<8} |{:<40}|",
+                low,
+                high,
+                count,
+                "*".repeat(stars)
+            );
+        }
+        out
+    }
+}
+
+/// latency of SSL reads, writes and handshakes per process, from `delta_ns`
+///
+/// Every call is also counted in the cumulative `/metrics` histograms. The histograms kept
+/// here cover one print interval and are cleared after printing. Shared by the event and
+/// keylog readers, so it locks internally.
+pub struct LatencyTracker {
+    group: LatencyGroup,
+    // (操作, 进程名, pid)，按进程名分组时 pid 为 0
+    histograms: Mutex<BTreeMap<(&'static str, String, u32), Log2Histogram>>,
+}
+
+impl LatencyTracker {
+    pub fn new(config: &Latency) -> Self {
+        Self {
+            group: config.group_by,
+            histograms: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn record(&self, operation: &'static str, comm: String, pid: u32, ns: u64) {
+        METRICS.latency(operation, &comm, ns);
+        let pid = match self.group {
+            LatencyGroup::Comm => 0,
+            LatencyGroup::Pid => pid,
+        };
+        let key = (operation, comm, pid);
+        let mut histograms = self.histograms.lock().unwrap();
+        if histograms.len() >= MAX_GROUPS && !histograms.contains_key(&key) {
+            return;
+        }
+        histograms.entry(key).or_default().record(ns);
+    }
+
+    /// record the duration of the SSL_read or SSL_write call behind an event
+    pub fn feed(&self, data: &ProbeSslData) {
+        // 握手事件没有调用耗时
+        if data.is_handshake || data.delta_ns == 0 {
+            return;
+        }
+        self.record(operation(data), sanitize_comm(&data.comm), data.tgid, data.delta_ns);
+    }
+
+    /// record the duration of a successful SSL_do_handshake call
+    pub fn feed_handshake(&self, secret: &ProbeMasterSecret) {
+        if secret.delta_ns == 0 {
+            return;
+        }
+        self.record("handshake", sanitize_comm(&secret.comm), secret.tgid, secret.delta_ns);
+    }
+
+    /// render and clear the histograms of the last interval
+    pub fn take_report(&self) -> String {
+        let mut out = String::new();
+        for ((operation, comm, pid), histogram) in std::mem::take(&mut *self.histograms.lock().unwrap()) {
+            match self.group {
+                LatencyGroup::Comm => {
+                    let _ = writeln!(out, "\nSSL {} latency, comm = {}", operation, comm);
+                }
+                LatencyGroup::Pid => {
+                    let _ = writeln!(out, "\nSSL {} latency, pid = {} ({})", operation, pid, comm);
+                }
+            }
+            out.push_str(&histogram.render("usecs"));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LatencyTracker, Log2Histogram};
+    use crate::config::{Latency, LatencyGroup};
+    use crate::test_util::{comm, event};
+    use ssl_observer_common::{ProbeSslData, READ};
+
+    #[test]
+    fn renders_log2_buckets() {
+        let mut histogram = Log2Histogram::default();
+        for ns in [500, 1_500, 2_500, 3_999, 5_000] {
+            histogram.record(ns);
+        }
+        assert_eq!(histogram.count(), 5);
+        let lines: Vec<String> = histogram.render("usecs").lines().map(str::to_string).collect();
+        assert_eq!(lines[0], "              usecs : count     distribution");
+        assert_eq!(lines[1], format!("{:>10} -> {:<10} : {:<8} |{:<40}|", 0, 0, 1, "*".repeat(20)));
+        assert_eq!(lines[3], format!("{:>10} -> {:<10} : {:<8} |{:<40}|", 2, 3, 2, "*".repeat(40)));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn groups_report_by_pid() {
+        let tracker = LatencyTracker::new(&Latency {
+            group_by: LatencyGroup::Pid,
+            ..Latency::default()
+        });
+        tracker.feed(&ProbeSslData {
+            delta_ns: 8_000_000,
+            pid: 8,
+            comm: comm("curl"),
+            ..event(READ, 10_000_000, b"")
+        });
+        let report = tracker.take_report();
+        assert!(report.contains("SSL read latency, pid = 7 (curl)"));
+        assert!(report.contains("      4096 -> 8191       : 1"));
+        assert!(tracker.take_report().is_empty());
+    }
+}
@@ -1,22 +1,64 @@
-use aya::{include_bytes_aligned, maps::RingBuf, programs::UProbe, Bpf,};
+use aya::{
+    include_bytes_aligned,
+    maps::{Array, MapData, PerCpuArray, RingBuf},
+    programs::UProbe,
+    Bpf,
+};
 use aya_log::BpfLogger;
 use clap::Parser;
 use log::{debug, info, warn};
-use sqlx::{MySql, Pool};
-use std::{ops::Deref, str};
-use tokio::{io::unix::AsyncFd, signal};
+use std::{ops::Deref, str, sync::Arc, time::SystemTime};
+use tokio::{io::unix::AsyncFd, signal, sync::Notify};
 
-use ssl_observer_common::ProbeSslData;
+use ssl_observer_common::{ProbeMasterSecret, ProbeSslData, SslOffsets};
 mod decode;
+mod decoder;
+mod grpc;
+mod http1;
+mod http2;
+mod jsonl;
+mod keylog;
+mod latency;
+mod metrics;
+mod migrate;
+mod otlp;
 mod mysql_db;
-// mod sqlite_db;
+mod pcap;
+mod plugin;
+mod protocols;
+mod redact;
+mod rules;
+mod sqlite_db;
+mod storage;
+#[cfg(test)]
+mod test_util;
+mod tui;
 mod ui;
 mod utils;
+mod view_filter;
+mod websocket;
+mod yara;
 mod config;
 
-use decode::print_buf;
-use mysql_db::{init_db, insert_data};
-use ui::display_data_async;
+use decode::{parse_exchange, print_alert, print_buf, print_exchange, print_ws_message};
+use decoder::DecoderRegistry;
+use http1::{Http1Tracker, HttpHead};
+use http2::Http2Tracker;
+use config::CONFIG;
+use jsonl::JsonlWriter;
+use keylog::KeylogWriter;
+use latency::LatencyTracker;
+use metrics::METRICS;
+use otlp::OtlpExporter;
+use pcap::PcapWriter;
+use redact::{redact, redact_exchange, redact_ws, StreamRedactor};
+use rules::{Alert, RuleEngine};
+use storage::{spawn_retention, SslDataRow, Storage};
+use tui::{Tui, TuiEvent};
+use ui::{LiveFeed, LiveViewer};
+use utils::{calculate_specific_time, sanitize_comm};
+use websocket::WebSocketTracker;
+use yara::YaraScanner;
 
 #[derive(Debug, Parser)]
 #[clap(name = "SSL-Observer", long_about = "SSL Traffic Monitoring and Analysis Tool")]
@@ -33,37 +75,97 @@ struct Opt {
     /// Observe the specified library with the path,like "openssl:/path/libssl.so.1.1"
     #[clap(short , default_value_t = String::from("libssl"))]
     lib: String,
+    /// Stream events as JSON Lines, "jsonl" for stdout or "jsonl=/path/events.jsonl"
+    #[clap(long)]
+    output: Option<String>,
+    /// Write live events to a pcapng file with synthesized TCP/IP frames
+    #[clap(long)]
+    pcap: Option<String>,
+    /// Export stored events to a pcapng file and exit
+    #[clap(long)]
+    export_pcap: Option<String>,
+    /// Write TLS session keys in SSLKEYLOGFILE format, one file per process in the directory
+    #[clap(long)]
+    keylog: Option<String>,
+    /// Show a live terminal UI instead of printing events
+    #[clap(long)]
+    tui: bool,
+}
+
+// 除数据库外的各类输出
+struct Outputs {
+    jsonl: Option<JsonlWriter>,
+    pcap: Option<PcapWriter>,
+    otlp: Option<OtlpExporter>,
+    tui: Option<Tui>,
+    viewer: Option<LiveFeed>,
+}
+
+impl Outputs {
+    // JSON Lines 输出到 stdout 或终端界面打开时不再打印文本
+    fn print(&self) -> bool {
+        self.tui.is_none() && !self.jsonl.as_ref().is_some_and(|writer| writer.to_stdout())
+    }
+}
+
+// 跨多次 SSL 调用重组协议的状态
+struct Trackers {
+    decoders: DecoderRegistry,
+    rules: RuleEngine,
+    yara: YaraScanner,
+    http1: Http1Tracker,
+    http2: Http2Tracker,
+    websocket: WebSocketTracker,
+    redaction: StreamRedactor,
 }
 
 fn attach_openssl(bpf: &mut Bpf, lib: &String) -> Result<(), anyhow::Error> {
-    // let ssl_do_handshake_program: &mut UProbe =
-    //     bpf.program_mut("ssl_do_handshake").unwrap().try_into()?;
-    // ssl_do_handshake_program.load()?;
-    // ssl_do_handshake_program.attach(Some("SSL_do_handshake"), 0, lib, None)?;
-
-    // let ssl_do_handshake_ret_program: &mut UProbe = bpf
-    //     .program_mut("ssl_do_handshake_ret")
-    //     .unwrap()
-    //     .try_into()?;
-    // ssl_do_handshake_ret_program.load()?;
-    // ssl_do_handshake_ret_program.attach(Some("SSL_do_handshake"), 0, lib, None)?;
     // SSL_write
     let ssl_write_program: &mut UProbe = bpf.program_mut("ssl_write").unwrap().try_into()?;
     ssl_write_program.load()?;
     ssl_write_program.attach(Some("SSL_write"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_write", "SSL_write", lib);
 
     let ssl_write_ret_program: &mut UProbe =
         bpf.program_mut("ssl_write_ret").unwrap().try_into()?;
     ssl_write_ret_program.load()?;
     ssl_write_ret_program.attach(Some("SSL_write"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_write_ret", "SSL_write", lib);
     // SSL_read
     let ssl_read_program: &mut UProbe = bpf.program_mut("ssl_read").unwrap().try_into()?;
     ssl_read_program.load()?;
     ssl_read_program.attach(Some("SSL_read"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_read", "SSL_read", lib);
 
     let ssl_read_ret_program: &mut UProbe = bpf.program_mut("ssl_read_ret").unwrap().try_into()?;
     ssl_read_ret_program.load()?;
     ssl_read_ret_program.attach(Some("SSL_read"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_read_ret", "SSL_read", lib);
+    Ok(())
+}
+
+fn attach_keylog(bpf: &mut Bpf, lib: &String) -> Result<(), anyhow::Error> {
+    let offsets: SslOffsets = CONFIG.keylog.offsets().ok_or_else(|| {
+        anyhow::anyhow!("Keylog offsets are not configured, see [keylog] in config.toml")
+    })?;
+    let mut offsets_map: Array<&mut MapData, SslOffsets> =
+        Array::try_from(bpf.map_mut("KEYLOG_OFFSETS").unwrap())?;
+    offsets_map.set(0, offsets, 0)?;
+
+    // SSL_do_handshake
+    let ssl_do_handshake_program: &mut UProbe =
+        bpf.program_mut("ssl_do_handshake").unwrap().try_into()?;
+    ssl_do_handshake_program.load()?;
+    ssl_do_handshake_program.attach(Some("SSL_do_handshake"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_do_handshake", "SSL_do_handshake", lib);
+
+    let ssl_do_handshake_ret_program: &mut UProbe = bpf
+        .program_mut("ssl_do_handshake_ret")
+        .unwrap()
+        .try_into()?;
+    ssl_do_handshake_ret_program.load()?;
+    ssl_do_handshake_ret_program.attach(Some("SSL_do_handshake"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_do_handshake_ret", "SSL_do_handshake", lib);
     Ok(())
 }
 
@@ -72,11 +174,13 @@ fn attach_nss(bpf: &mut Bpf, lib: &String) -> Result<(), anyhow::Error> {
     let nss_write_program: &mut UProbe = bpf.program_mut("ssl_write").unwrap().try_into()?;
     nss_write_program.load()?;
     nss_write_program.attach(Some("PR_Write"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_write", "PR_Write", lib);
 
     let nss_write_ret_program: &mut UProbe =
         bpf.program_mut("ssl_write_ret").unwrap().try_into()?;
     nss_write_ret_program.load()?;
     nss_write_ret_program.attach(Some("PR_Write"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_write_ret", "PR_Write", lib);
 
     // PR_Send
     // let nss_send_program: &mut UProbe = bpf.program_mut("ssl_write").unwrap().try_into()?;
@@ -92,10 +196,12 @@ fn attach_nss(bpf: &mut Bpf, lib: &String) -> Result<(), anyhow::Error> {
     let nss_read_program: &mut UProbe = bpf.program_mut("ssl_read").unwrap().try_into()?;
     nss_read_program.load()?;
     nss_read_program.attach(Some("PR_Read"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_read", "PR_Read", lib);
 
     let nss_read_ret_program: &mut UProbe = bpf.program_mut("ssl_read_ret").unwrap().try_into()?;
     nss_read_ret_program.load()?;
     nss_read_ret_program.attach(Some("PR_Read"), 0, lib, None)?;
+    METRICS.probe_attached("ssl_read_ret", "PR_Read", lib);
 
     // PR_Recv
     // let nss_recv_program: &mut UProbe = bpf.program_mut("ssl_read").unwrap().try_into()?;
@@ -113,7 +219,10 @@ fn prepare_programs(bpf: &mut Bpf, opt: &Opt) -> Result<(), anyhow::Error> {
     let lib = &opt.lib;
     if lib == "libssl" {
         // default
-        attach_openssl(bpf, &lib)?;
+        attach_openssl(bpf, lib)?;
+        if opt.keylog.is_some() {
+            attach_keylog(bpf, lib)?;
+        }
     } else {
         // 尝试找到冒号 ':' 的位置
         match lib.find(':') {
@@ -128,8 +237,18 @@ fn prepare_programs(bpf: &mut Bpf, opt: &Opt) -> Result<(), anyhow::Error> {
 
                 // 根据 library_name 调用相应的函数
                 match library_name.as_str() {
-                    "openssl" => attach_openssl(bpf, &file_path)?,
-                    "nss" => attach_nss(bpf, &file_path)?,
+                    "openssl" => {
+                        attach_openssl(bpf, &file_path)?;
+                        if opt.keylog.is_some() {
+                            attach_keylog(bpf, &file_path)?;
+                        }
+                    }
+                    "nss" => {
+                        attach_nss(bpf, &file_path)?;
+                        if opt.keylog.is_some() {
+                            warn!("Keylog is only supported for openssl");
+                        }
+                    }
                     _ => return Err(anyhow::anyhow!("Unsupported library type")),
                 }
             }
@@ -146,7 +265,18 @@ fn prepare_programs(bpf: &mut Bpf, opt: &Opt) -> Result<(), anyhow::Error> {
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
     let opt = Opt::parse();
-    env_logger::init();
+    // 终端界面占用屏幕，日志显示在界面底部
+    let mut tui = opt.tui.then(Tui::new);
+    match &tui {
+        Some(tui) => env_logger::Builder::from_default_env()
+            .target(env_logger::Target::Pipe(Box::new(tui.logger())))
+            .init(),
+        None => env_logger::init(),
+    }
+    // 导出已存储的数据，不需要加载 eBPF 程序
+    if let Some(path) = &opt.export_pcap {
+        return export_pcap(path).await;
+    }
     // 内存限制提升
     bump_memlock_rlimit()?;
     // 加载eBPF程序
@@ -159,26 +289,134 @@ async fn main() -> Result<(), anyhow::Error> {
     // Hook 事件
     prepare_programs(&mut bpf, &opt)?;
     // 异步数据库连接池初始化
-    let pool = init_db().await?;
-    let events: RingBuf<&mut aya::maps::MapData> =
-        RingBuf::try_from(bpf.map_mut("SSL_DATA").unwrap())?;
-    // 建立异步的RingBuf，自动实现了epoll
-    let mut events_fd: AsyncFd<RingBuf<&mut aya::maps::MapData>> = AsyncFd::new(events).unwrap();
-    println!("Waiting for Ctrl-C...");
-    loop {
-        tokio::select! {
-            _ = signal::ctrl_c() => {
-                info!("Exiting...");
-                break;
-            },
-            // 读取用户缓冲区中的 ProbeSslData 数据
-            _ = async {
-                read_event(&pool, &mut events_fd,&opt).await.unwrap();
-                // read_event_batch(&pool, &mut events_fd,&opt).await.unwrap();
-            }=>{}
+    let storage = Storage::init().await?;
+    spawn_retention(storage.clone());
+    if CONFIG.metrics.enabled {
+        // 旧的 eBPF 程序没有计数器时只输出用户态指标
+        let counters = match bpf.take_map("COUNTERS") {
+            Some(map) => Some(PerCpuArray::try_from(map)?),
+            None => {
+                warn!("eBPF COUNTERS map not found, kernel counters are not exported");
+                None
+            }
         };
+        metrics::spawn_server(&CONFIG.metrics.listen, storage.clone(), counters).await?;
+    }
+    let boot_time: SystemTime = calculate_specific_time(0).await?;
+    let mut trackers = Trackers {
+        decoders: DecoderRegistry::new(&CONFIG.decoders),
+        rules: RuleEngine::new(&CONFIG.rules, &CONFIG.alerts),
+        yara: YaraScanner::new(&CONFIG.yara, &CONFIG.alerts),
+        http1: Http1Tracker::new(),
+        http2: Http2Tracker::new(),
+        websocket: WebSocketTracker::new(),
+        redaction: StreamRedactor::new(),
+    };
+    let mut outputs = Outputs {
+        jsonl: opt.output.as_deref().map(JsonlWriter::open).transpose()?,
+        pcap: opt
+            .pcap
+            .as_deref()
+            .map(|path| PcapWriter::create(path, boot_time))
+            .transpose()?,
+        otlp: CONFIG
+            .otlp
+            .enabled
+            .then(|| OtlpExporter::spawn(&CONFIG.otlp, boot_time))
+            .transpose()?,
+        tui: None,
+        viewer: None,
+    };
+    let events: RingBuf<MapData> = RingBuf::try_from(bpf.take_map("SSL_DATA").unwrap())?;
+    // 建立异步的RingBuf，自动实现了epoll
+    let mut events_fd: AsyncFd<RingBuf<MapData>> = AsyncFd::new(events).unwrap();
+    // 会话密钥
+    let keylog: Option<(KeylogWriter, AsyncFd<RingBuf<MapData>>)> = match &opt.keylog {
+        Some(dir) => {
+            let secrets: RingBuf<MapData> =
+                RingBuf::try_from(bpf.take_map("MASTER_SECRETS").unwrap())?;
+            Some((KeylogWriter::create(dir)?, AsyncFd::new(secrets)?))
+        }
+        None => None,
+    };
+    let latency = Arc::new(LatencyTracker::new(&CONFIG.latency));
+    // 终端界面或查看窗口退出时结束采集
+    let quit = Arc::new(Notify::new());
+    let mut viewer = None;
+    match tui.as_mut() {
+        Some(tui) => tui.start(quit.clone())?,
+        None => {
+            let (feed, live) = LiveViewer::open(&storage).await;
+            outputs.viewer = Some(feed);
+            viewer = Some(live);
+            // JSON Lines 写到标准输出时不能混入提示
+            if outputs.print() {
+                println!("Waiting for Ctrl-C...");
+            }
+        }
+    }
+    outputs.tui = tui;
+    // 握手完成后的密钥在独立的任务中读取，不与事件读取竞争
+    if let Some((mut writer, mut secrets_fd)) = keylog {
+        let latency = latency.clone();
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = read_master_secrets(&mut secrets_fd, &mut writer, &latency).await {
+                    warn!("Failed to read master secrets: {}", e);
+                    break;
+                }
+            }
+        });
+    }
+    // 周期性打印各进程的 SSL 调用耗时直方图
+    if CONFIG.latency.print_interval_secs > 0 && outputs.print() {
+        let latency = latency.clone();
+        tokio::spawn(async move {
+            let period = std::time::Duration::from_secs(CONFIG.latency.print_interval_secs);
+            let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            loop {
+                interval.tick().await;
+                print!("{}", latency.take_report());
+            }
+        });
+    }
+    let stop = quit.clone();
+    let capture = tokio::spawn(async move {
+        loop {
+            // 各分支只等待就绪，可以安全取消；事件在选中分支的处理体中读取，此时其他分支已不再轮询，处理不会被打断
+            tokio::select! {
+                _ = signal::ctrl_c() => {
+                    info!("Exiting...");
+                    break;
+                },
+                // 终端界面中按 q 或关闭查看窗口
+                _ = stop.notified() => {
+                    break;
+                },
+                guard = events_fd.readable_mut() => {
+                    let mut guard = guard?;
+                    read_event(&storage, guard.get_inner_mut(), &mut trackers, &latency, &mut outputs, &opt).await;
+                    guard.clear_ready();
+                },
+            };
+        }
+        if let Some(pcap) = outputs.pcap.as_mut() {
+            pcap.close(SystemTime::now())?;
+        }
+        if let Some(mut tui) = outputs.tui.take() {
+            tui.stop();
+        }
+        Ok::<(), anyhow::Error>(())
+    });
+    if let Some(viewer) = viewer {
+        // 窗口需要在主线程运行，采集在其他工作线程上继续
+        match tokio::task::block_in_place(|| viewer.run()) {
+            Ok(()) => quit.notify_one(),
+            // 没有图形环境时继续采集，直到 Ctrl-C
+            Err(e) => warn!("Failed to open the data viewer: {}", e),
+        }
     }
-    display_data_async(&pool).await;
+    capture.await??;
     Ok(())
 }
 
@@ -217,14 +455,32 @@ fn load_bpf_program() -> Result<Bpf, anyhow::Error> {
     Ok(bpf)
 }
 
+// 告警写入数据库，并按配置输出到 JSON Lines 与终端
+async fn raise_alerts(storage: &Storage, outputs: &mut Outputs, alerts: Vec<Alert>, print: bool) {
+    for alert in alerts {
+        if let Err(e) = storage.insert_alert(&alert).await {
+            warn!("Failed to insert alert: {}", e);
+        }
+        if let Some(writer) = outputs.jsonl.as_mut().filter(|_| CONFIG.alerts.jsonl) {
+            if let Err(e) = writer.write_alert(&alert).await {
+                warn!("Failed to write jsonl alert: {}", e);
+            }
+        }
+        if print && CONFIG.alerts.stdout {
+            print_alert(&alert);
+        }
+    }
+}
+
 async fn read_event(
-    pool: &Pool<MySql>,
-    events_fd: &mut AsyncFd<RingBuf<&mut aya::maps::MapData>>,
+    storage: &Storage,
+    events: &mut RingBuf<MapData>,
+    trackers: &mut Trackers,
+    latency: &LatencyTracker,
+    outputs: &mut Outputs,
     opt: &Opt,
-) -> Result<(), anyhow::Error> {
-    // 检测这个RingBuf是否异步可读
-    let mut guard = events_fd.readable_mut().await?;
-    let events: &mut RingBuf<&mut aya::maps::MapData> = guard.get_inner_mut();
+) {
+    let mut batch = 0;
 
     while let Some(ring_event) = events.next() {
         let data: ProbeSslData = unsafe {
@@ -233,10 +489,149 @@ async fn read_event(
             // let data_ptr: *const ProbeSslData = ring_event.deref().as_ptr() as *const ProbeSslData;
             *data_ptr
         };
+        batch += 1;
+        // 查看界面中停止采集时丢弃收到的事件
+        if outputs.viewer.as_ref().is_some_and(|viewer| !viewer.is_capturing()) {
+            continue;
+        }
+        METRICS.event(data.rw, &sanitize_comm(&data.comm), data.len, data.is_handshake);
+        latency.feed(&data);
 
-        insert_data(pool, &data).await.unwrap();
-        print_buf(&data, &opt).await;
+        // 按连接的前几个事件选择解码器，识别结果单独记录
+        let (content, detected) = trackers.decoders.decode(&data).await;
+        if let Some(protocol) = detected {
+            if let Err(e) = storage.insert_connection(&data, protocol).await {
+                warn!("Failed to insert connection protocol: {}", e);
+            }
+        }
+        let print = outputs.print();
+        // 规则按原始数据匹配，告警中的片段已脱敏
+        raise_alerts(storage, outputs, trackers.rules.evaluate(&data), print).await;
+        // 存储与输出前脱敏，协议重组仍使用原始数据
+        let (redacted, content) = trackers.redaction.redact(&data, &content);
+        // 单个事件写入失败只记录，不影响后续采集，失败次数计入 db_errors
+        let id = match storage.insert_data(&redacted, &content).await {
+            Ok(id) => Some(id),
+            Err(e) => {
+                warn!("Failed to insert event: {}", e);
+                None
+            }
+        };
+        // 握手事件不在查看界面中显示
+        if let (Some(viewer), Some(id)) = (outputs.viewer.as_ref().filter(|_| !redacted.is_handshake), id) {
+            match SslDataRow::from_probe(id, &redacted, &content).await {
+                Ok(row) => viewer.send(row),
+                Err(e) => warn!("Failed to send event to the data viewer: {}", e),
+            }
+        }
+        if let Some(writer) = outputs.jsonl.as_mut() {
+            if let Err(e) = writer.write_event(&redacted, &content).await {
+                warn!("Failed to write jsonl event: {}", e);
+            }
+        }
+        if let Some(pcap) = outputs.pcap.as_mut() {
+            if let Err(e) = pcap.write_probe(&redacted) {
+                warn!("Failed to write pcap event: {}", e);
+            }
+        }
+        if let Some(tui) = outputs.tui.as_ref() {
+            tui.send(TuiEvent {
+                timestamp_ns: redacted.timestamp_ns,
+                pid: redacted.pid,
+                tgid: redacted.tgid,
+                conn_id: redacted.conn_id,
+                comm: sanitize_comm(&redacted.comm),
+                rw: redacted.rw,
+                content: content.clone(),
+                raw: redacted.buf[..redacted.len].to_vec(),
+            });
+        }
+        // WebSocket 连接上的帧按消息输出，不再逐次打印
+        if print && !trackers.websocket.is_tracked(&data) {
+            print_buf(&data, &content, opt).await;
+        }
+        // 跨多次 SSL 调用重组的 HTTP/1.x 请求与响应，以及 HTTP/2 的各个流
+        let mut exchanges = trackers.http1.feed(&data);
+        exchanges.extend(trackers.http2.feed(&data));
+        let mut ws_messages = trackers.websocket.feed(&data);
+        for mut exchange in exchanges {
+            // 101 之后已读到的字节交给 WebSocket 解析
+            if exchange.response.as_ref().is_some_and(|response| response.status() == Some(101)) {
+                let remainder = trackers.http1.take_upgraded(exchange.tgid, exchange.conn_id);
+                ws_messages.extend(trackers.websocket.upgrade(&exchange, remainder));
+            }
+            let host = exchange.request.as_ref().and_then(|request| HttpHead::parse(&request.raw)?.host);
+            if exchange.request.is_some() {
+                METRICS.http_request(host.as_deref());
+            }
+            // 跨多次调用或 HTTP/2 的字段只有在重组后才能匹配
+            let mut alerts = trackers.rules.evaluate_exchange(&exchange);
+            alerts.extend(trackers.yara.scan_exchange(&exchange));
+            raise_alerts(storage, outputs, alerts, print).await;
+            // 先从原始报文解码，再对文本和原始字节分别脱敏
+            let (request, response) = parse_exchange(&exchange).await;
+            let request = request.map(|text| redact(&text));
+            let response = response.map(|text| redact(&text));
+            redact_exchange(&mut exchange);
+            if let Some(otlp) = outputs.otlp.as_mut() {
+                otlp.export(&exchange);
+            }
+            if let Err(e) = storage.insert_exchange(&exchange, request.as_deref(), response.as_deref()).await {
+                warn!("Failed to insert http exchange: {}", e);
+            }
+            if print {
+                print_exchange(&exchange);
+            }
+        }
+        for mut message in ws_messages {
+            let alerts = trackers.yara.scan_ws(&message);
+            raise_alerts(storage, outputs, alerts, print).await;
+            redact_ws(&mut message);
+            if let Err(e) = storage.insert_ws_message(&message).await {
+                warn!("Failed to insert websocket message: {}", e);
+            }
+            if print {
+                print_ws_message(&message);
+            }
+        }
+    }
+    if batch > 0 {
+        METRICS.batch(batch);
     }
+}
+
+async fn read_master_secrets(
+    secrets_fd: &mut AsyncFd<RingBuf<MapData>>,
+    writer: &mut KeylogWriter,
+    latency: &LatencyTracker,
+) -> Result<(), anyhow::Error> {
+    let mut guard = secrets_fd.readable_mut().await?;
+    let secrets: &mut RingBuf<MapData> = guard.get_inner_mut();
 
+    while let Some(ring_event) = secrets.next() {
+        let secret: ProbeMasterSecret = unsafe {
+            let item: &[u8] = ring_event.deref();
+            *(item.as_ptr() as *const ProbeMasterSecret)
+        };
+        latency.feed_handshake(&secret);
+        if let Err(e) = writer.write_secret(&secret) {
+            warn!("Failed to write keylog: {}", e);
+        }
+    }
+    guard.clear_ready();
+
+    Ok(())
+}
+
+// 将数据库中的记录导出为 pcapng
+async fn export_pcap(path: &str) -> Result<(), anyhow::Error> {
+    let storage = Storage::init().await?;
+    let rows = storage.query_data().await?;
+    let mut pcap = PcapWriter::create(path, SystemTime::now())?;
+    for row in &rows {
+        pcap.write_row(row)?;
+    }
+    pcap.close(SystemTime::now())?;
+    println!("Exported {} events to {}", rows.len(), path);
     Ok(())
 }
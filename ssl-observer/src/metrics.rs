@@ -0,0 +1,385 @@
+use aya::maps::{MapData, PerCpuArray};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use ssl_observer_common::{COUNTER_READ_FAILED, COUNTER_RINGBUF_DROPS, COUNTER_TRUNCATED, WRITE};
+
+use crate::storage::Storage;
+
+// 每个指标的标签组合上限，超出的进程名或 host 计入 "other"
+const MAX_SERIES: usize = 256;
+const OTHER: &str = "other";
+// 数据库写入耗时的分桶（秒）
+const DB_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+// 每次 RingBuf 唤醒读出的事件数分桶
+const BATCH_BUCKETS: &[f64] = &[1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0];
+// SSL 调用耗时的分桶，1us 到约 16s 的 2 的幂
+const LATENCY_BUCKETS: &[f64] = &{
+    let mut buckets = [0.0; 25];
+    let mut i = 0;
+    while i < buckets.len() {
+        buckets[i] = (1u64 << i) as f64 / 1e6;
+        i += 1;
+    }
+    buckets
+};
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    /// process wide metrics, rendered by the `/metrics` endpoint
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            let _ = writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, sep, bound, count);
+        }
+        let _ = writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, sep, self.count);
+        let braces = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // (rw, comm) -> 事件数
+    events: BTreeMap<(&'static str, String), u64>,
+    bytes: BTreeMap<&'static str, u64>,
+    handshakes: u64,
+    batches: Option<Histogram>,
+    db_latency: BTreeMap<&'static str, Histogram>,
+    db_errors: BTreeMap<&'static str, u64>,
+    http_requests: BTreeMap<String, u64>,
+    // (操作, 进程名) -> SSL 调用耗时
+    latency: BTreeMap<(&'static str, String), Histogram>,
+    // (program, symbol, library)
+    probes: Vec<(String, String, String)>,
+}
+
+/// counters and histograms describing the observer itself
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+// 超过上限的新标签值归入 other，已有的继续计数
+fn capped<K: Ord, V>(map: &mut BTreeMap<K, V>, key: K, other: K, new: impl FnOnce() -> V) -> &mut V {
+    if map.len() >= MAX_SERIES && !map.contains_key(&key) {
+        return map.entry(other).or_insert_with(new);
+    }
+    map.entry(key).or_insert_with(new)
+}
+
+fn direction(rw: u8) -> &'static str {
+    if rw == WRITE {
+        "write"
+    } else {
+        "read"
+    }
+}
+
+// 标签值中的 \、" 与换行需要转义
+fn escape(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n")
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    /// count one captured event
+    pub fn event(&self, rw: u8, comm: &str, len: usize, is_handshake: bool) {
+        let mut state = self.state.lock().unwrap();
+        if is_handshake {
+            state.handshakes += 1;
+            return;
+        }
+        let rw = direction(rw);
+        *capped(&mut state.events, (rw, comm.to_string()), (rw, OTHER.to_string()), u64::default) += 1;
+        *state.bytes.entry(rw).or_default() += len as u64;
+    }
+
+    /// number of events drained from the ring buffer in one wakeup
+    pub fn batch(&self, events: usize) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .batches
+            .get_or_insert_with(|| Histogram::new(BATCH_BUCKETS))
+            .observe(events as f64);
+    }
+
+    /// duration of one database insert, `ok` false when it failed
+    pub fn db_insert(&self, table: &'static str, elapsed: Duration, ok: bool) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .db_latency
+            .entry(table)
+            .or_insert_with(|| Histogram::new(DB_BUCKETS))
+            .observe(elapsed.as_secs_f64());
+        if !ok {
+            *state.db_errors.entry(table).or_default() += 1;
+        }
+    }
+
+    /// count one reassembled HTTP request, by Host or :authority when known
+    pub fn http_request(&self, host: Option<&str>) {
+        let mut state = self.state.lock().unwrap();
+        let host = host.unwrap_or("unknown").to_ascii_lowercase();
+        *capped(&mut state.http_requests, host, OTHER.to_string(), u64::default) += 1;
+    }
+
+    /// duration of one SSL read, write or handshake call
+    pub fn latency(&self, operation: &'static str, comm: &str, ns: u64) {
+        let mut state = self.state.lock().unwrap();
+        let key = (operation, comm.to_string());
+        let other = (operation, OTHER.to_string());
+        capped(&mut state.latency, key, other, || Histogram::new(LATENCY_BUCKETS)).observe(ns as f64 / 1e9);
+    }
+
+    /// record a uprobe attached at startup
+    pub fn probe_attached(&self, program: &str, symbol: &str, library: &str) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .probes
+            .push((program.to_string(), symbol.to_string(), library.to_string()));
+    }
+
+    /// render all metrics in the Prometheus text format
+    ///
+    /// `kernel` holds the eBPF counters summed over all CPUs, `pool` the size and idle
+    /// connections of the database pool.
+    pub fn render(&self, kernel: Option<&HashMap<u32, u64>>, pool: Option<(u32, usize)>) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        header(&mut out, "ssl_observer_events_total", "counter", "Captured SSL read and write events.");
+        for ((rw, comm), count) in &state.events {
+            let _ = writeln!(
+                out,
+                "ssl_observer_events_total{{rw=\"{}\",comm=\"{}\"}} {}",
+                rw,
+                escape(comm),
+                count
+            );
+        }
+        header(&mut out, "ssl_observer_captured_bytes_total", "counter", "Payload bytes captured.");
+        for (rw, bytes) in &state.bytes {
+            let _ = writeln!(out, "ssl_observer_captured_bytes_total{{rw=\"{}\"}} {}", rw, bytes);
+        }
+        header(&mut out, "ssl_observer_handshakes_total", "counter", "Captured TLS handshakes.");
+        let _ = writeln!(out, "ssl_observer_handshakes_total {}", state.handshakes);
+
+        if let Some(kernel) = kernel {
+            let counters = [
+                ("ssl_observer_ringbuf_drops_total", COUNTER_RINGBUF_DROPS, "Events dropped because the ring buffer was full."),
+                ("ssl_observer_truncated_events_total", COUNTER_TRUNCATED, "Events truncated to MAX_BUF_SIZE."),
+                ("ssl_observer_read_failures_total", COUNTER_READ_FAILED, "Events whose user buffer could not be read."),
+            ];
+            for (name, index, help) in counters {
+                header(&mut out, name, "counter", help);
+                let _ = writeln!(out, "{} {}", name, kernel.get(&index).copied().unwrap_or(0));
+            }
+        }
+
+        if let Some(batches) = &state.batches {
+            header(&mut out, "ssl_observer_ringbuf_batch_events", "histogram", "Events pending in the ring buffer per wakeup.");
+            batches.render(&mut out, "ssl_observer_ringbuf_batch_events", "");
+        }
+
+        header(&mut out, "ssl_observer_ssl_call_seconds", "histogram", "Duration of SSL read, write and handshake calls.");
+        for ((operation, comm), histogram) in &state.latency {
+            let labels = format!("op=\"{}\",comm=\"{}\"", operation, escape(comm));
+            histogram.render(&mut out, "ssl_observer_ssl_call_seconds", &labels);
+        }
+
+        header(&mut out, "ssl_observer_db_insert_seconds", "histogram", "Database insert latency.");
+        for (table, histogram) in &state.db_latency {
+            histogram.render(&mut out, "ssl_observer_db_insert_seconds", &format!("table=\"{}\"", table));
+        }
+        header(&mut out, "ssl_observer_db_insert_errors_total", "counter", "Failed database inserts.");
+        for (table, count) in &state.db_errors {
+            let _ = writeln!(out, "ssl_observer_db_insert_errors_total{{table=\"{}\"}} {}", table, count);
+        }
+        if let Some((size, idle)) = pool {
+            header(&mut out, "ssl_observer_db_connections", "gauge", "Database pool connections by state.");
+            let _ = writeln!(out, "ssl_observer_db_connections{{state=\"idle\"}} {}", idle);
+            let _ = writeln!(
+                out,
+                "ssl_observer_db_connections{{state=\"busy\"}} {}",
+                (size as usize).saturating_sub(idle)
+            );
+        }
+
+        header(&mut out, "ssl_observer_http_requests_total", "counter", "Reassembled HTTP requests by host.");
+        for (host, count) in &state.http_requests {
+            let _ = writeln!(out, "ssl_observer_http_requests_total{{host=\"{}\"}} {}", escape(host), count);
+        }
+
+        header(&mut out, "ssl_observer_attached_probes", "gauge", "Uprobes attached at startup.");
+        for (program, symbol, library) in &state.probes {
+            let _ = writeln!(
+                out,
+                "ssl_observer_attached_probes{{program=\"{}\",symbol=\"{}\",library=\"{}\"}} 1",
+                escape(program),
+                escape(symbol),
+                escape(library)
+            );
+        }
+        out
+    }
+}
+
+// 各 CPU 的计数求和
+fn read_counters(counters: &PerCpuArray<MapData, u64>) -> HashMap<u32, u64> {
+    [COUNTER_RINGBUF_DROPS, COUNTER_TRUNCATED, COUNTER_READ_FAILED]
+        .into_iter()
+        .filter_map(|index| {
+            let values = counters.get(&index, 0).ok()?;
+            Some((index, values.iter().sum()))
+        })
+        .collect()
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    storage: &Storage,
+    counters: Option<&PerCpuArray<MapData, u64>>,
+) -> Result<(), anyhow::Error> {
+    let mut request = [0u8; 1024];
+    let len = tokio::time::timeout(READ_TIMEOUT, stream.read(&mut request)).await??;
+    let line = String::from_utf8_lossy(&request[..len]);
+    let target = line.lines().next().unwrap_or("").split(' ').take(2).collect::<Vec<_>>();
+    let (status, body) = match target.as_slice() {
+        ["GET", path] if path.split('?').next() == Some("/metrics") => {
+            let kernel = counters.map(read_counters);
+            ("200 OK", METRICS.render(kernel.as_ref(), storage.pool_stats().await))
+        }
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// serve `/metrics` on `listen` in the background
+pub async fn spawn_server(
+    listen: &str,
+    storage: Storage,
+    counters: Option<PerCpuArray<MapData, u64>>,
+) -> Result<(), anyhow::Error> {
+    let listener = TcpListener::bind(listen).await?;
+    info!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept metrics connection: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = serve(stream, &storage, counters.as_ref()).await {
+                warn!("Failed to serve metrics: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Metrics, MAX_SERIES};
+    use ssl_observer_common::{COUNTER_RINGBUF_DROPS, READ, WRITE};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    #[test]
+    fn renders_recorded_metrics() {
+        let metrics = Metrics::default();
+        metrics.event(WRITE, "curl", 100, false);
+        metrics.event(WRITE, "curl", 20, false);
+        metrics.event(READ, "say \"hi\"", 5, false);
+        metrics.event(READ, "curl", 0, true);
+        metrics.db_insert("data", Duration::from_millis(3), true);
+        metrics.db_insert("data", Duration::from_secs(5), false);
+        metrics.http_request(Some("API.example.com"));
+        metrics.probe_attached("ssl_write", "SSL_write", "libssl");
+        metrics.latency("read", "curl", 3_000);
+        let kernel = HashMap::from([(COUNTER_RINGBUF_DROPS, 7)]);
+
+        let out = metrics.render(Some(&kernel), Some((4, 3)));
+        for line in [
+            "ssl_observer_events_total{rw=\"write\",comm=\"curl\"} 2",
+            "ssl_observer_events_total{rw=\"read\",comm=\"say \\\"hi\\\"\"} 1",
+            "ssl_observer_captured_bytes_total{rw=\"write\"} 120",
+            "ssl_observer_handshakes_total 1",
+            "ssl_observer_ringbuf_drops_total 7",
+            "ssl_observer_truncated_events_total 0",
+            "ssl_observer_db_insert_seconds_bucket{table=\"data\",le=\"0.005\"} 1",
+            "ssl_observer_db_insert_seconds_bucket{table=\"data\",le=\"+Inf\"} 2",
+            "ssl_observer_db_insert_errors_total{table=\"data\"} 1",
+            "ssl_observer_db_connections{state=\"busy\"} 1",
+            "ssl_observer_http_requests_total{host=\"api.example.com\"} 1",
+            "ssl_observer_ssl_call_seconds_bucket{op=\"read\",comm=\"curl\",le=\"0.000004\"} 1",
+            "ssl_observer_attached_probes{program=\"ssl_write\",symbol=\"SSL_write\",library=\"libssl\"} 1",
+        ] {
+            assert!(out.lines().any(|l| l == line), "missing {}", line);
+        }
+    }
+
+    #[test]
+    fn caps_comm_labels() {
+        let metrics = Metrics::default();
+        // 超过上限的进程名计入 other
+        for i in 0..MAX_SERIES + 44 {
+            metrics.event(WRITE, &format!("worker-{}", i), 1, false);
+        }
+        let out = metrics.render(None, None);
+        assert!(out.contains("comm=\"other\"} 44"));
+        // 没有内核计数器时不输出对应指标
+        assert!(!out.contains("ringbuf_drops"));
+    }
+}
@@ -0,0 +1,85 @@
+use sqlx::migrate::MigrateError;
+
+/// 记录已执行迁移版本的表
+pub const SCHEMA_VERSION_TABLE: &str = "schema_version";
+
+/// one versioned schema change, `{table}` is replaced with the configured table name
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub statements: &'static [&'static str],
+}
+
+impl Migration {
+    /// return the statements with the table name filled in
+    pub fn render(&self, table: &str) -> Vec<String> {
+        self.statements
+            .iter()
+            .map(|sql| sql.replace("{table}", table))
+            .collect()
+    }
+}
+
+/// return the latest version known to this build
+pub fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+// 数据库中的版本比程序认识的还新，说明是新版本程序建的库，拒绝继续写入
+pub fn check_version(current: i64, migrations: &[Migration]) -> Result<(), sqlx::Error> {
+    if current > latest_version(migrations) {
+        return Err(sqlx::Error::Migrate(Box::new(
+            MigrateError::VersionMissing(current),
+        )));
+    }
+    Ok(())
+}
+
+/// return the migrations that still need to run, in version order
+pub fn pending(current: i64, migrations: &[Migration]) -> Vec<&Migration> {
+    let mut pending: Vec<&Migration> = migrations
+        .iter()
+        .filter(|m| m.version > current)
+        .collect();
+    pending.sort_by_key(|m| m.version);
+    pending
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_version, pending, Migration};
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration { version: 2, description: "b", statements: &["ALTER TABLE {table} ADD x"] },
+        Migration { version: 1, description: "a", statements: &["CREATE TABLE {table} (id INTEGER)"] },
+    ];
+
+    #[test]
+    fn pending_in_version_order() {
+        let versions: Vec<i64> = pending(0, MIGRATIONS).iter().map(|m| m.version).collect();
+        assert_eq!(versions, vec![1, 2]);
+        assert!(pending(2, MIGRATIONS).is_empty());
+    }
+
+    #[test]
+    fn renders_table_name() {
+        assert_eq!(MIGRATIONS[1].render("ssl_data"), vec!["CREATE TABLE ssl_data (id INTEGER)"]);
+    }
+
+    #[test]
+    fn rejects_newer_schema() {
+        assert!(check_version(2, MIGRATIONS).is_ok());
+        assert!(check_version(3, MIGRATIONS).is_err());
+    }
+
+    #[test]
+    fn backends_share_versions() {
+        // 两个后端同一版本号代表同样的表结构
+        let versions = |migrations: &[Migration]| {
+            let mut versions: Vec<i64> = migrations.iter().map(|m| m.version).collect();
+            versions.sort();
+            versions
+        };
+        assert_eq!(versions(crate::mysql_db::MIGRATIONS), versions(crate::sqlite_db::MIGRATIONS));
+    }
+}
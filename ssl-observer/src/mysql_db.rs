@@ -1,25 +1,26 @@
-use sqlx::migrate::MigrateDatabase;
+use chrono::Local;
+use log::info;
+use sqlx::migrate::{MigrateDatabase, MigrateError};
+use sqlx::mysql::MySqlDatabaseError;
 use sqlx::{pool::PoolOptions, MySql, MySqlPool};
 
 use ssl_observer_common::ProbeSslData;
 
-use crate::decode::parse_http;
+use crate::config::{Retention, CONFIG};
+use crate::grpc::grpc_call;
+use crate::http1::{HttpExchange, HttpHead};
+use crate::migrate::{check_version, pending, Migration, SCHEMA_VERSION_TABLE};
+use crate::rules::Alert;
+use crate::storage::{retention_cutoff, retention_sizes, SslDataRow, RETENTION_TABLES};
 use crate::utils::{convert_timestamp_to_date, sanitize_comm};
-use crate::config::CONFIG;
-
-#[derive(sqlx::FromRow)]
-pub struct SslDataRow {
-    pub id: i64,
-    pub timestamp: String,
-    pub pid: i32,
-    pub comm: String,
-    pub buf: String,
-}
+use crate::websocket::WebSocketMessage;
 
-pub async fn init_db() -> Result<MySqlPool, sqlx::Error> {
-    let database_url = CONFIG.database.mysql_url();
-    let database_name = CONFIG.database.mysql_db_name();
-    let create_table_query: String =format!(r#"CREATE TABLE IF NOT EXISTS {} (
+// 按版本顺序执行的表结构变更，已发布的条目不要修改，只能追加
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create table",
+        statements: &[r#"CREATE TABLE IF NOT EXISTS {table} (
         id INTEGER PRIMARY KEY AUTO_INCREMENT,
         timestamp TEXT,
         delta_ns INTEGER,
@@ -32,10 +33,167 @@ pub async fn init_db() -> Result<MySqlPool, sqlx::Error> {
         is_handshake INTEGER,
         len INTEGER,
         buf TEXT
-    )"#,database_name);
-    
+    )"#],
+    },
+    Migration {
+        version: 2,
+        description: "add raw_buf",
+        statements: &["ALTER TABLE {table} ADD COLUMN raw_buf BLOB"],
+    },
+    Migration {
+        version: 3,
+        description: "add indexes",
+        statements: &[
+            // TEXT 列在 MySQL 中只能建前缀索引
+            "CREATE INDEX idx_{table}_timestamp ON {table} (timestamp(19))",
+            "CREATE INDEX idx_{table}_pid ON {table} (pid)",
+            "CREATE INDEX idx_{table}_comm ON {table} (comm(16))",
+            "CREATE INDEX idx_{table}_rw ON {table} (rw)",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "add conn_id",
+        statements: &["ALTER TABLE {table} ADD COLUMN conn_id BIGINT"],
+    },
+    Migration {
+        version: 5,
+        description: "create http exchange table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_http (
+        id INTEGER PRIMARY KEY AUTO_INCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        latency_ns BIGINT,
+        request MEDIUMTEXT,
+        response MEDIUMTEXT,
+        request_raw MEDIUMBLOB,
+        response_raw MEDIUMBLOB
+    )"#,
+            "CREATE INDEX idx_{table}_http_timestamp ON {table}_http (timestamp(19))",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "add structured http columns",
+        statements: &[
+            "ALTER TABLE {table} ADD COLUMN method TEXT",
+            "ALTER TABLE {table} ADD COLUMN path TEXT",
+            "ALTER TABLE {table} ADD COLUMN host TEXT",
+            "ALTER TABLE {table} ADD COLUMN status INTEGER",
+            "ALTER TABLE {table} ADD COLUMN content_type TEXT",
+            "ALTER TABLE {table} ADD COLUMN content_length BIGINT",
+            "ALTER TABLE {table} ADD COLUMN user_agent TEXT",
+            "CREATE INDEX idx_{table}_method ON {table} (method(8))",
+            "CREATE INDEX idx_{table}_status ON {table} (status)",
+            "CREATE INDEX idx_{table}_host ON {table} (host(64))",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "add http2 stream_id",
+        statements: &["ALTER TABLE {table}_http ADD COLUMN stream_id BIGINT"],
+    },
+    Migration {
+        version: 8,
+        description: "add grpc columns",
+        statements: &[
+            "ALTER TABLE {table}_http ADD COLUMN grpc_method TEXT",
+            "ALTER TABLE {table}_http ADD COLUMN grpc_status INTEGER",
+            "ALTER TABLE {table}_http ADD COLUMN grpc_message TEXT",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "create websocket message table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_ws (
+        id INTEGER PRIMARY KEY AUTO_INCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        rw INTEGER,
+        opcode INTEGER,
+        compressed INTEGER,
+        close_code INTEGER,
+        payload MEDIUMTEXT,
+        payload_raw MEDIUMBLOB
+    )"#,
+            "CREATE INDEX idx_{table}_ws_timestamp ON {table}_ws (timestamp(19))",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "create connection protocol table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_conn (
+        id INTEGER PRIMARY KEY AUTO_INCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        protocol TEXT
+    )"#,
+            "CREATE INDEX idx_{table}_conn_conn_id ON {table}_conn (tgid, conn_id)",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "create alert table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_alert (
+        id INTEGER PRIMARY KEY AUTO_INCREMENT,
+        timestamp TEXT,
+        rule TEXT,
+        severity TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        uid INTEGER,
+        conn_id BIGINT,
+        rw INTEGER,
+        host TEXT,
+        excerpt MEDIUMTEXT
+    )"#,
+            "CREATE INDEX idx_{table}_alert_timestamp ON {table}_alert (timestamp(19))",
+            "CREATE INDEX idx_{table}_alert_rule ON {table}_alert (rule(64))",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "add alert source, scope, stream and offset columns",
+        statements: &[
+            "ALTER TABLE {table}_alert ADD COLUMN source TEXT",
+            "ALTER TABLE {table}_alert ADD COLUMN scope TEXT",
+            "ALTER TABLE {table}_alert ADD COLUMN stream_id BIGINT",
+            "ALTER TABLE {table}_alert ADD COLUMN match_offset BIGINT",
+        ],
+    },
+    // INT 只能存约 2 秒的纳秒数，阻塞较久的 SSL_read 会溢出
+    Migration {
+        version: 13,
+        description: "widen delta_ns",
+        statements: &["ALTER TABLE {table} MODIFY delta_ns BIGINT"],
+    },
+    // InnoDB 会复用删除后空闲的页，只占位与 SQLite 保持相同的版本号
+    Migration {
+        version: 14,
+        description: "enable incremental vacuum",
+        statements: &[],
+    },
+];
+
+pub async fn init_db() -> Result<MySqlPool, sqlx::Error> {
+    let database_url = CONFIG.database.mysql_url();
+
     // 初始化数据库
-    if let Err(_) = MySql::create_database(&database_url).await {}
+    let _ = MySql::create_database(&database_url).await;
 
     // 设置连接池选项，包括连接池的大小
     let pool = PoolOptions::<MySql>::new()
@@ -43,33 +201,99 @@ pub async fn init_db() -> Result<MySqlPool, sqlx::Error> {
         .connect(&database_url)
         .await?;
 
-
-    sqlx::query(&create_table_query)
-    .execute(&pool)
-    .await?;
+    migrate(&pool).await?;
 
     Ok(pool)
 }
 
+// 列或索引已存在，说明上次迁移执行到一半失败，这条语句已经生效
+fn already_applied(e: &sqlx::Error) -> bool {
+    const ER_DUP_FIELDNAME: u16 = 1060;
+    const ER_DUP_KEYNAME: u16 = 1061;
+    match e {
+        sqlx::Error::Database(e) => e
+            .try_downcast_ref::<MySqlDatabaseError>()
+            .is_some_and(|e| matches!(e.number(), ER_DUP_FIELDNAME | ER_DUP_KEYNAME)),
+        _ => false,
+    }
+}
+
+// 检查 schema_version 并执行未应用的迁移
+// MySQL 的 DDL 会隐式提交，无法放进事务，重试时跳过已经生效的语句
+async fn migrate(pool: &MySqlPool) -> Result<(), sqlx::Error> {
+    let database_name = CONFIG.database.mysql_db_name();
+    let create_version_query = format!(
+        "CREATE TABLE IF NOT EXISTS {} (version BIGINT PRIMARY KEY, description TEXT, applied_at TEXT)",
+        SCHEMA_VERSION_TABLE
+    );
+    sqlx::query(&create_version_query).execute(pool).await?;
+
+    let select_version_query = format!(
+        "SELECT COALESCE(MAX(version), 0) FROM {}",
+        SCHEMA_VERSION_TABLE
+    );
+    let current: i64 = sqlx::query_scalar(&select_version_query)
+        .fetch_one(pool)
+        .await?;
+    check_version(current, MIGRATIONS)?;
+
+    for migration in pending(current, MIGRATIONS) {
+        info!(
+            "Applying migration {} ({}) to {}",
+            migration.version, migration.description, database_name
+        );
+        for statement in migration.render(&database_name) {
+            match sqlx::query(&statement).execute(pool).await {
+                Ok(_) => {}
+                Err(e) if already_applied(&e) => {
+                    info!("Skipping already applied statement: {}", statement);
+                }
+                Err(e) => {
+                    return Err(sqlx::Error::Migrate(Box::new(
+                        MigrateError::ExecuteMigration(e, migration.version),
+                    )));
+                }
+            }
+        }
+        let insert_version_query = format!(
+            "INSERT INTO {} (version, description, applied_at) VALUES (?, ?, ?)",
+            SCHEMA_VERSION_TABLE
+        );
+        sqlx::query(&insert_version_query)
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(())
+}
+
 pub async fn query_data(pool: &MySqlPool) -> Result<Vec<SslDataRow>, sqlx::Error> {
-    let select_table_query = format!("SELECT id, timestamp, pid, comm, buf FROM {} WHERE is_handshake = 0",&CONFIG.database.mysql_db_name()
+    let select_table_query = format!("SELECT id, timestamp, delta_ns, pid, tgid, rw, conn_id, comm, buf, raw_buf, method, path, host, status, content_type, content_length, user_agent FROM {} WHERE is_handshake = 0",&CONFIG.database.mysql_db_name()
 );
-    let rows: Vec<SslDataRow> = sqlx::query_as::<MySql, _>(
-        &select_table_query,
-    )
-    .fetch_all(pool)
-    .await?;
+    let rows: Vec<SslDataRow> = sqlx::query_as::<MySql, _>(&select_table_query)
+        .fetch_all(pool)
+        .await?;
 
     Ok(rows)
 }
 
-pub async fn insert_data(pool: &MySqlPool, data: &ProbeSslData) -> Result<(), sqlx::Error> {
+pub async fn insert_data(
+    pool: &MySqlPool,
+    data: &ProbeSslData,
+    content: &str,
+) -> Result<i64, sqlx::Error> {
     let date: String = convert_timestamp_to_date(data.timestamp_ns).await?;
     let comm_cleaned: String = sanitize_comm(&data.comm);
-    let content = parse_http(&data.buf[..data.len]).await;
+    let raw_buf: &[u8] = &data.buf[..data.len];
+    // buf 存解码器输出的文本，raw_buf 保留原始字节
+    // 起始行与常用头部单独成列，便于按方法、状态码等查询
+    let head = HttpHead::parse(raw_buf).unwrap_or_default();
 
-    let insert_table_query = format!("INSERT INTO {} (timestamp, delta_ns, comm, pid, tgid, uid, buf_filled, rw, is_handshake, len, buf) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
-    let _res = sqlx::query(&insert_table_query)
+    let insert_table_query = format!("INSERT INTO {} (timestamp, delta_ns, comm, pid, tgid, uid, buf_filled, rw, is_handshake, len, buf, raw_buf, conn_id, method, path, host, status, content_type, content_length, user_agent) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
+    let res = sqlx::query(&insert_table_query)
         .bind(date)
         .bind(data.delta_ns as i64)
         .bind(comm_cleaned)
@@ -81,8 +305,197 @@ pub async fn insert_data(pool: &MySqlPool, data: &ProbeSslData) -> Result<(), sq
         .bind(data.is_handshake as i32)
         .bind(data.len as i32)
         .bind(content)
+        .bind(raw_buf)
+        .bind(data.conn_id as i64)
+        .bind(head.method)
+        .bind(head.path)
+        .bind(head.host)
+        .bind(head.status.map(i32::from))
+        .bind(head.content_type)
+        .bind(head.content_length.map(|len| len as i64))
+        .bind(head.user_agent)
+        .execute(pool)
+        .await?;
+
+    Ok(res.last_insert_id() as i64)
+}
+
+pub async fn insert_exchange(
+    pool: &MySqlPool,
+    exchange: &HttpExchange,
+    request: Option<&str>,
+    response: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(exchange.timestamp_ns()).await?;
+    let grpc = grpc_call(exchange);
+
+    let insert_table_query = format!("INSERT INTO {}_http (timestamp, comm, pid, tgid, conn_id, stream_id, latency_ns, request, response, request_raw, response_raw, grpc_method, grpc_status, grpc_message) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(&exchange.comm)
+        .bind(exchange.pid)
+        .bind(exchange.tgid)
+        .bind(exchange.conn_id as i64)
+        .bind(exchange.stream_id.map(i64::from))
+        .bind(exchange.latency_ns().map(|ns| ns as i64))
+        .bind(request)
+        .bind(response)
+        .bind(exchange.request.as_ref().map(|message| &message.raw))
+        .bind(exchange.response.as_ref().map(|message| &message.raw))
+        .bind(grpc.as_ref().map(|call| call.method.as_str()))
+        .bind(grpc.as_ref().and_then(|call| call.status))
+        .bind(grpc.as_ref().and_then(|call| call.message.as_deref()))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_connection(
+    pool: &MySqlPool,
+    data: &ProbeSslData,
+    protocol: &str,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(data.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_conn (timestamp, comm, pid, tgid, conn_id, protocol) VALUES (?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(sanitize_comm(&data.comm))
+        .bind(data.pid)
+        .bind(data.tgid)
+        .bind(data.conn_id as i64)
+        .bind(protocol)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_alert(pool: &MySqlPool, alert: &Alert) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(alert.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_alert (timestamp, source, rule, severity, comm, pid, tgid, uid, conn_id, stream_id, rw, host, scope, match_offset, excerpt) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(alert.source)
+        .bind(&alert.rule)
+        .bind(&alert.severity)
+        .bind(&alert.comm)
+        .bind(alert.pid)
+        .bind(alert.tgid)
+        .bind(alert.uid)
+        .bind(alert.conn_id as i64)
+        .bind(alert.stream_id.map(i64::from))
+        .bind(alert.rw)
+        .bind(&alert.host)
+        .bind(alert.scope)
+        .bind(alert.offset.map(|offset| offset as i64))
+        .bind(&alert.excerpt)
         .execute(pool)
         .await?;
 
     Ok(())
 }
+
+pub async fn insert_ws_message(
+    pool: &MySqlPool,
+    message: &WebSocketMessage,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(message.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_ws (timestamp, comm, pid, tgid, conn_id, rw, opcode, compressed, close_code, payload, payload_raw) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.mysql_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(&message.comm)
+        .bind(message.pid)
+        .bind(message.tgid)
+        .bind(message.conn_id as i64)
+        .bind(message.rw)
+        .bind(message.opcode)
+        .bind(message.compressed as i32)
+        .bind(message.close_code)
+        .bind(message.text())
+        .bind(&message.payload)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// 按保留策略删除最旧的数据，返回删除的行数
+pub async fn enforce_retention(
+    pool: &MySqlPool,
+    retention: &Retention,
+) -> Result<u64, sqlx::Error> {
+    let database_name = CONFIG.database.mysql_db_name();
+    let mut deleted: u64 = 0;
+
+    for (suffix, _) in RETENTION_TABLES {
+        let table = format!("{}{}", database_name, suffix);
+        // 1. 按时间，timestamp 为 "%Y-%m-%d %H:%M:%S" 字符串，可直接比较
+        if let Some(cutoff) = retention_cutoff(retention) {
+            let delete_query = format!("DELETE FROM {} WHERE timestamp < ?", table);
+            deleted += sqlx::query(&delete_query)
+                .bind(cutoff)
+                .execute(pool)
+                .await?
+                .rows_affected();
+        }
+
+        // 2. 按行数，每张表分别保留最新的 max_rows 行
+        if retention.max_rows > 0 {
+            let select_id_query =
+                format!("SELECT id FROM {} ORDER BY id DESC LIMIT 1 OFFSET ?", table);
+            let oldest_kept: Option<i64> = sqlx::query_scalar(&select_id_query)
+                .bind(retention.max_rows as i64)
+                .fetch_optional(pool)
+                .await?;
+            if let Some(id) = oldest_kept {
+                let delete_query = format!("DELETE FROM {} WHERE id <= ?", table);
+                deleted += sqlx::query(&delete_query)
+                    .bind(id)
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+            }
+        }
+    }
+
+    // 3. 按字节数，所有表合计；只统计一次超出的部分，再按时间确定删除到哪里
+    if retention.max_bytes > 0 {
+        let sizes = retention_sizes(&database_name);
+        let sum_query = format!(
+            "SELECT CAST(COALESCE(SUM(size), 0) AS SIGNED) FROM ({}) AS sizes",
+            sizes
+        );
+        let total: i64 = sqlx::query_scalar(&sum_query).fetch_one(pool).await?;
+        if total as u64 > retention.max_bytes {
+            // 按时间累计各秒的数据量，累计达到超出部分的那一秒及更早的数据全部删除
+            let cutoff_query = format!(
+                "SELECT timestamp FROM (SELECT timestamp, SUM(SUM(size)) OVER (ORDER BY timestamp) AS freed FROM ({}) AS sizes GROUP BY timestamp) AS running WHERE freed >= ? ORDER BY timestamp LIMIT 1",
+                sizes
+            );
+            let cutoff: Option<String> = sqlx::query_scalar(&cutoff_query)
+                .bind(total - retention.max_bytes as i64)
+                .fetch_optional(pool)
+                .await?;
+            if let Some(cutoff) = cutoff {
+                for (suffix, _) in RETENTION_TABLES {
+                    let delete_query = format!(
+                        "DELETE FROM {}{} WHERE timestamp <= ?",
+                        database_name, suffix
+                    );
+                    deleted += sqlx::query(&delete_query)
+                        .bind(&cutoff)
+                        .execute(pool)
+                        .await?
+                        .rows_affected();
+                }
+            }
+        }
+    }
+
+    // InnoDB 会复用删除后空闲的页，不需要像 SQLite 那样手动回收
+    Ok(deleted)
+}
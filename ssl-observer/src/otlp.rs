@@ -0,0 +1,444 @@
+use log::{info, warn};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use crate::config::Otlp;
+use crate::http1::{HttpExchange, HttpHead};
+
+// OTLP 中的 SpanKind 与 StatusCode
+const SPAN_KIND_SERVER: u32 = 2;
+const SPAN_KIND_CLIENT: u32 = 3;
+const STATUS_CODE_ERROR: u32 = 2;
+// 容器 id 缓存的进程数上限，超过后清空
+const MAX_CACHED_PROCESSES: usize = 4096;
+
+// 同一进程的 span 放在同一个 resource 下
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct Resource {
+    service: String,
+    pid: u32,
+    container: Option<String>,
+}
+
+struct Span {
+    resource: Resource,
+    span: Value,
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        // OTLP/JSON 中 64 位整数写成字符串
+        Value::Number(number) => json!({ "intValue": number.to_string() }),
+        Value::Bool(flag) => json!({ "boolValue": flag }),
+        other => json!({ "stringValue": other.as_str().unwrap_or_default() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// 从 /proc/<pid>/cgroup 中找出 64 位十六进制的容器 id（docker、containerd、cri-o）
+fn container_id(tgid: u32) -> Option<String> {
+    let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", tgid)).ok()?;
+    cgroup.lines().find_map(|line| {
+        let name = line.rsplit('/').next()?.trim_end_matches(".scope");
+        let id = name.rsplit(['-', ':']).next()?;
+        (id.len() == 64 && id.chars().all(|c| c.is_ascii_hexdigit())).then(|| id.to_string())
+    })
+}
+
+/// exports reassembled HTTP exchanges as OTLP spans, one span per request and response pair
+///
+/// Spans are sent in batches by a background task as OTLP/HTTP JSON. Each process becomes
+/// a resource named after its comm, so uninstrumented services show up in the trace UI.
+pub struct OtlpExporter {
+    sender: mpsc::Sender<Span>,
+    boot_time: SystemTime,
+    containers: HashMap<u32, Option<String>>,
+    dropped: u64,
+}
+
+impl OtlpExporter {
+    /// start the background sender, `boot_time` converts event timestamps to wall clock time
+    pub fn spawn(config: &Otlp, boot_time: SystemTime) -> Result<Self, anyhow::Error> {
+        let endpoint = Endpoint::parse(&config.endpoint)?;
+        let (sender, receiver) = mpsc::channel(config.queue_size.max(1));
+        let batcher = Batcher {
+            endpoint,
+            headers: config.headers.clone(),
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname")
+                .ok()
+                .map(|name| name.trim().to_string()),
+            batch_size: config.batch_size.max(1),
+            flush_interval: Duration::from_millis(config.flush_interval_ms.max(1)),
+            timeout: Duration::from_millis(config.timeout_ms.max(1)),
+        };
+        info!("Exporting spans to {}", config.endpoint);
+        tokio::spawn(batcher.run(receiver));
+        Ok(Self {
+            sender,
+            boot_time,
+            containers: HashMap::new(),
+            dropped: 0,
+        })
+    }
+
+    fn unix_ns(&self, ns: u64) -> u64 {
+        (self.boot_time + Duration::from_nanos(ns))
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos() as u64)
+            .unwrap_or_default()
+    }
+
+    /// queue one exchange, dropped when the collector falls behind
+    pub fn export(&mut self, exchange: &HttpExchange) {
+        let Some(span) = self.build_span(exchange) else {
+            return;
+        };
+        if self.sender.try_send(span).is_err() {
+            self.dropped += 1;
+            if self.dropped.is_power_of_two() {
+                warn!("OTLP queue is full, {} spans dropped", self.dropped);
+            }
+        }
+    }
+
+    fn build_span(&mut self, exchange: &HttpExchange) -> Option<Span> {
+        let first = exchange.request.as_ref().or(exchange.response.as_ref())?;
+        let last = exchange.response.as_ref().or(exchange.request.as_ref())?;
+        let head = exchange
+            .request
+            .as_ref()
+            .and_then(|request| HttpHead::parse(&request.raw))
+            .unwrap_or_default();
+        let status = exchange.response.as_ref().and_then(|response| response.status());
+        // 请求由被观测进程发出时它是客户端
+        let is_client = exchange.request.as_ref().map_or(!first.is_sent(), |request| request.is_sent());
+        let method = head.method.clone().unwrap_or_else(|| "HTTP".to_string());
+
+        let mut attributes = vec![attribute("http.request.method", json!(method))];
+        if let Some(path) = &head.path {
+            attributes.push(attribute("url.path", json!(path.split('?').next().unwrap_or(path))));
+            if let Some(host) = &head.host {
+                attributes.push(attribute("url.full", json!(format!("https://{}{}", host, path))));
+            }
+        }
+        if let Some(host) = &head.host {
+            attributes.push(attribute("server.address", json!(host)));
+        }
+        if let Some(status) = status {
+            attributes.push(attribute("http.response.status_code", json!(status)));
+        }
+        let version = first.start_line.rsplit(' ').next().filter(|_| !first.is_response());
+        let version = version.or_else(|| last.start_line.split(' ').next());
+        if let Some(version) = version.and_then(|version| version.strip_prefix("HTTP/")) {
+            attributes.push(attribute("network.protocol.version", json!(version)));
+        }
+        if let Some(user_agent) = &head.user_agent {
+            attributes.push(attribute("user_agent.original", json!(user_agent)));
+        }
+        attributes.push(attribute("ssl_observer.conn_id", json!(exchange.conn_id)));
+        if let Some(stream_id) = exchange.stream_id {
+            attributes.push(attribute("ssl_observer.stream_id", json!(stream_id)));
+        }
+        if let Some(latency) = exchange.latency_ns() {
+            attributes.push(attribute("ssl_observer.time_to_first_byte_ns", json!(latency)));
+        }
+        if first.truncated || last.truncated {
+            attributes.push(attribute("ssl_observer.truncated", json!(true)));
+        }
+
+        // 客户端的 4xx 与双方的 5xx 为错误，缺少响应时同样标记
+        let error = match status {
+            Some(status) => status >= 500 || (is_client && status >= 400),
+            None => true,
+        };
+        let status = match (error, status) {
+            (false, _) => json!({}),
+            (true, Some(status)) => json!({ "code": STATUS_CODE_ERROR, "message": format!("HTTP {}", status) }),
+            (true, None) => json!({ "code": STATUS_CODE_ERROR, "message": "no response observed" }),
+        };
+
+        // 由连接与时间生成确定的 id，重复导出同一交换时 id 相同
+        let digest = Sha256::new()
+            .chain_update(exchange.tgid.to_le_bytes())
+            .chain_update(exchange.conn_id.to_le_bytes())
+            .chain_update(exchange.stream_id.unwrap_or(0).to_le_bytes())
+            .chain_update(first.start_ns.to_le_bytes())
+            .chain_update(self.unix_ns(0).to_le_bytes())
+            .finalize();
+        let span = json!({
+            "traceId": hex(&digest[..16]),
+            "spanId": hex(&digest[16..24]),
+            "name": method,
+            "kind": if is_client { SPAN_KIND_CLIENT } else { SPAN_KIND_SERVER },
+            "startTimeUnixNano": self.unix_ns(first.start_ns).to_string(),
+            "endTimeUnixNano": self.unix_ns(last.end_ns.max(first.start_ns)).to_string(),
+            "attributes": attributes,
+            "status": status,
+        });
+
+        if self.containers.len() >= MAX_CACHED_PROCESSES {
+            self.containers.clear();
+        }
+        let container = self
+            .containers
+            .entry(exchange.tgid)
+            .or_insert_with(|| container_id(exchange.tgid))
+            .clone();
+        Some(Span {
+            resource: Resource {
+                service: exchange.comm.clone(),
+                pid: exchange.tgid,
+                container,
+            },
+            span,
+        })
+    }
+}
+
+// http://host:port/path 形式的 collector 地址
+struct Endpoint {
+    authority: String,
+    path: String,
+}
+
+impl Endpoint {
+    fn parse(url: &str) -> Result<Self, anyhow::Error> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| anyhow::anyhow!("OTLP endpoint must be plain http://, got {}", url))?;
+        let (authority, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/v1/traces"),
+        };
+        let authority = if authority.contains(':') {
+            authority.to_string()
+        } else {
+            format!("{}:80", authority)
+        };
+        Ok(Self {
+            authority,
+            path: path.to_string(),
+        })
+    }
+}
+
+struct Batcher {
+    endpoint: Endpoint,
+    headers: BTreeMap<String, String>,
+    hostname: Option<String>,
+    batch_size: usize,
+    flush_interval: Duration,
+    timeout: Duration,
+}
+
+impl Batcher {
+    async fn run(self, mut receiver: mpsc::Receiver<Span>) {
+        let mut batch = Vec::new();
+        let mut interval = tokio::time::interval(self.flush_interval);
+        loop {
+            tokio::select! {
+                span = receiver.recv() => match span {
+                    Some(span) => {
+                        batch.push(span);
+                        if batch.len() < self.batch_size {
+                            continue;
+                        }
+                    }
+                    // 发送端已关闭，发出剩余的 span 后退出
+                    None => {
+                        self.flush(&mut batch).await;
+                        return;
+                    }
+                },
+                _ = interval.tick() => {}
+            }
+            self.flush(&mut batch).await;
+        }
+    }
+
+    async fn flush(&self, batch: &mut Vec<Span>) {
+        if batch.is_empty() {
+            return;
+        }
+        let count = batch.len();
+        let body = self.payload(std::mem::take(batch));
+        match tokio::time::timeout(self.timeout, self.post(&body)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => warn!("Failed to export {} spans: {}", count, e),
+            Err(_) => warn!("Failed to export {} spans: timed out", count),
+        }
+    }
+
+    fn payload(&self, batch: Vec<Span>) -> String {
+        let mut resources: BTreeMap<Resource, Vec<Value>> = BTreeMap::new();
+        for span in batch {
+            resources.entry(span.resource).or_default().push(span.span);
+        }
+        let resource_spans: Vec<Value> = resources
+            .into_iter()
+            .map(|(resource, spans)| {
+                let mut attributes = vec![
+                    attribute("service.name", json!(resource.service)),
+                    attribute("process.pid", json!(resource.pid)),
+                    attribute("process.executable.name", json!(resource.service)),
+                ];
+                if let Some(container) = resource.container {
+                    attributes.push(attribute("container.id", json!(container)));
+                }
+                if let Some(hostname) = &self.hostname {
+                    attributes.push(attribute("host.name", json!(hostname)));
+                }
+                json!({
+                    "resource": { "attributes": attributes },
+                    "scopeSpans": [{
+                        "scope": { "name": "ssl-observer", "version": env!("CARGO_PKG_VERSION") },
+                        "spans": spans,
+                    }],
+                })
+            })
+            .collect();
+        json!({ "resourceSpans": resource_spans }).to_string()
+    }
+
+    async fn post(&self, body: &str) -> Result<(), anyhow::Error> {
+        let mut stream = TcpStream::connect(&self.endpoint.authority).await?;
+        let mut request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.endpoint.path,
+            self.endpoint.authority,
+            body.len()
+        );
+        for (name, value) in &self.headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        stream.write_all(request.as_bytes()).await?;
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let head = HttpHead::parse(&response).unwrap_or_default();
+        match head.status {
+            Some(status) if status / 100 == 2 => Ok(()),
+            Some(status) => Err(anyhow::anyhow!("collector returned {}", status)),
+            None => Err(anyhow::anyhow!("invalid collector response")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Endpoint, OtlpExporter};
+    use crate::config::Otlp;
+    use crate::http1::{HttpExchange, HttpMessage};
+    use serde_json::Value;
+    use ssl_observer_common::{READ, WRITE};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn message(start_line: &str, raw: &str, start_ns: u64, end_ns: u64, rw: u8) -> HttpMessage {
+        HttpMessage {
+            start_line: start_line.to_string(),
+            raw: raw.as_bytes().to_vec(),
+            start_ns,
+            end_ns,
+            truncated: false,
+            rw,
+            trailers: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parses_endpoint() {
+        let endpoint = Endpoint::parse("http://collector:4318/v1/traces").unwrap();
+        assert_eq!((endpoint.authority.as_str(), endpoint.path.as_str()), ("collector:4318", "/v1/traces"));
+        // 省略端口与路径时使用默认值
+        let endpoint = Endpoint::parse("http://collector").unwrap();
+        assert_eq!((endpoint.authority.as_str(), endpoint.path.as_str()), ("collector:80", "/v1/traces"));
+        assert!(Endpoint::parse("https://collector:4318").is_err());
+    }
+
+    #[tokio::test]
+    async fn exports_exchange_as_span() {
+        // 本地 collector 替身，读取一个请求后返回 200
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = Otlp {
+            enabled: true,
+            endpoint: format!("http://{}/v1/traces", listener.local_addr().unwrap()),
+            batch_size: 1,
+            ..Otlp::default()
+        };
+        let boot_time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut exporter = OtlpExporter::spawn(&config, boot_time).unwrap();
+        exporter.export(&HttpExchange {
+            pid: 7,
+            tgid: 7,
+            conn_id: 3,
+            stream_id: None,
+            comm: "checkout".to_string(),
+            request: Some(message(
+                "GET /cart?id=1 HTTP/1.1",
+                "GET /cart?id=1 HTTP/1.1\r\nHost: shop.example\r\nUser-Agent: curl/8\r\n\r\n",
+                1_000,
+                1_500,
+                WRITE,
+            )),
+            response: Some(message("HTTP/1.1 404 Not Found", "HTTP/1.1 404 Not Found\r\n\r\n", 4_000, 9_000, READ)),
+        });
+
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        let body = loop {
+            let len = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..len]);
+            let text = String::from_utf8_lossy(&request).into_owned();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length: usize = head
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length: "))
+                    .unwrap()
+                    .parse()
+                    .unwrap();
+                if body.len() >= length {
+                    assert!(head.starts_with("POST /v1/traces HTTP/1.1"));
+                    break body.to_string();
+                }
+            }
+        };
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").await.unwrap();
+
+        let payload: Value = serde_json::from_str(&body).unwrap();
+        let resource = &payload["resourceSpans"][0];
+        assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "checkout");
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["name"], "GET");
+        assert_eq!(span["kind"], 3);
+        assert_eq!(span["startTimeUnixNano"], "1700000000000001000");
+        assert_eq!(span["endTimeUnixNano"], "1700000000000009000");
+        assert_eq!(span["status"]["code"], 2);
+        assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+        let attributes: Vec<(String, Value)> = span["attributes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|attribute| (attribute["key"].as_str().unwrap().to_string(), attribute["value"].clone()))
+            .collect();
+        let get = |key: &str| attributes.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone());
+        assert_eq!(get("url.full").unwrap()["stringValue"], "https://shop.example/cart?id=1");
+        assert_eq!(get("url.path").unwrap()["stringValue"], "/cart");
+        assert_eq!(get("http.response.status_code").unwrap()["intValue"], "404");
+        assert_eq!(get("network.protocol.version").unwrap()["stringValue"], "1.1");
+        assert_eq!(get("ssl_observer.time_to_first_byte_ns").unwrap()["intValue"], "2500");
+    }
+}
@@ -0,0 +1,409 @@
+use chrono::{Local, NaiveDateTime, TimeZone};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use ssl_observer_common::{ProbeSslData, WRITE};
+
+use crate::storage::SslDataRow;
+use crate::utils::sanitize_comm;
+
+// pcapng 块类型
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+const LINKTYPE_ETHERNET: u16 = 1;
+
+// 选项类型
+const OPT_END: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const SHB_USERAPPL: u16 = 4;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+// 单个合成报文的最大载荷，保证 IPv4 总长度不溢出
+const MAX_SEGMENT: usize = 65000;
+// 对端固定为 TEST-NET-1 地址，端口 80 便于 Wireshark 按 HTTP 解析明文
+const PEER_IP: [u8; 4] = [192, 0, 2, 1];
+const PEER_PORT: u16 = 80;
+// 本地端口从这里开始按连接依次分配
+const FIRST_LOCAL_PORT: u16 = 1024;
+
+/// one plaintext chunk to be written as a synthesized TCP segment
+pub struct PcapEvent<'a> {
+    pub time: SystemTime,
+    pub pid: u32,
+    pub tgid: u32,
+    pub conn_id: u64,
+    pub rw: u8,
+    pub comm: &'a str,
+    pub payload: &'a [u8],
+}
+
+// 一条合成的 TCP 连接，被观测进程一侧记为 local
+struct TcpStream {
+    local_ip: [u8; 4],
+    local_port: u16,
+    local_seq: u32,
+    peer_seq: u32,
+}
+
+/// writes plaintext events into a pcapng file as Ethernet/IPv4/TCP frames
+pub struct PcapWriter {
+    writer: BufWriter<File>,
+    boot_time: SystemTime,
+    streams: HashMap<(u32, u64), TcpStream>,
+    // 每个进程下一个分配的本地端口，同一进程的连接四元组互不相同
+    next_port: HashMap<u32, u16>,
+    ip_id: u16,
+}
+
+impl PcapWriter {
+    /// create the file and write the section and interface headers
+    pub fn create(path: &str, boot_time: SystemTime) -> io::Result<Self> {
+        let mut pcap = Self {
+            writer: BufWriter::new(File::create(path)?),
+            boot_time,
+            streams: HashMap::new(),
+            next_port: HashMap::new(),
+            ip_id: 0,
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        // 段长度未知
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        push_option(&mut body, SHB_USERAPPL, b"ssl-observer");
+        push_option(&mut body, OPT_END, &[]);
+        pcap.write_block(SECTION_HEADER_BLOCK, &body)?;
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        push_option(&mut body, IF_NAME, b"ssl-observer");
+        // 时间戳精度为纳秒
+        push_option(&mut body, IF_TSRESOL, &[9]);
+        push_option(&mut body, OPT_END, &[]);
+        pcap.write_block(INTERFACE_DESCRIPTION_BLOCK, &body)?;
+        pcap.writer.flush()?;
+
+        Ok(pcap)
+    }
+
+    /// write a live event, `timestamp_ns` is relative to boot
+    pub fn write_probe(&mut self, data: &ProbeSslData) -> io::Result<()> {
+        let comm = sanitize_comm(&data.comm);
+        self.write_event(&PcapEvent {
+            time: self.boot_time + Duration::from_nanos(data.timestamp_ns),
+            pid: data.pid,
+            tgid: data.tgid,
+            conn_id: data.conn_id,
+            rw: data.rw,
+            comm: &comm,
+            payload: &data.buf[..data.len],
+        })
+    }
+
+    /// write a stored row, skipped when it has no raw bytes
+    pub fn write_row(&mut self, row: &SslDataRow) -> io::Result<()> {
+        let payload = match &row.raw_buf {
+            Some(raw_buf) => raw_buf,
+            None => return Ok(()),
+        };
+        let time = NaiveDateTime::parse_from_str(&row.timestamp, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .and_then(|dt| Local.from_local_datetime(&dt).single())
+            .map(SystemTime::from)
+            .unwrap_or(UNIX_EPOCH);
+        self.write_event(&PcapEvent {
+            time,
+            pid: row.pid as u32,
+            tgid: row.tgid as u32,
+            conn_id: row.conn_id.unwrap_or(0) as u64,
+            rw: row.rw as u8,
+            comm: &row.comm,
+            payload,
+        })
+    }
+
+    pub fn write_event(&mut self, event: &PcapEvent) -> io::Result<()> {
+        let key = (event.tgid, event.conn_id);
+        let comment = format!(
+            "pid={} tgid={} comm={} rw={}",
+            event.pid,
+            event.tgid,
+            event.comm,
+            if event.rw == WRITE { "write" } else { "read" }
+        );
+
+        // 首次出现的连接先合成三次握手
+        let mut stream = match self.streams.remove(&key) {
+            Some(stream) => stream,
+            None => {
+                let port = self.next_port.entry(event.tgid).or_insert(FIRST_LOCAL_PORT);
+                let local_port = *port;
+                // 超过 64512 个连接后回绕
+                *port = port.checked_add(1).unwrap_or(FIRST_LOCAL_PORT);
+                let stream = TcpStream {
+                    local_ip: local_ip(event.tgid),
+                    local_port,
+                    local_seq: 1,
+                    peer_seq: 1,
+                };
+                self.write_segment(&stream, true, TCP_SYN, 0, 0, &[], event.time, &comment)?;
+                self.write_segment(&stream, false, TCP_SYN | TCP_ACK, 0, 1, &[], event.time, &comment)?;
+                self.write_segment(&stream, true, TCP_ACK, 1, 1, &[], event.time, &comment)?;
+                stream
+            }
+        };
+
+        let from_local = event.rw == WRITE;
+        for chunk in event.payload.chunks(MAX_SEGMENT) {
+            let (seq, ack) = if from_local {
+                (stream.local_seq, stream.peer_seq)
+            } else {
+                (stream.peer_seq, stream.local_seq)
+            };
+            self.write_segment(
+                &stream,
+                from_local,
+                TCP_PSH | TCP_ACK,
+                seq,
+                ack,
+                chunk,
+                event.time,
+                &comment,
+            )?;
+            if from_local {
+                stream.local_seq = stream.local_seq.wrapping_add(chunk.len() as u32);
+            } else {
+                stream.peer_seq = stream.peer_seq.wrapping_add(chunk.len() as u32);
+            }
+        }
+        self.streams.insert(key, stream);
+        self.writer.flush()
+    }
+
+    // 写入一个 Enhanced Packet Block
+    #[allow(clippy::too_many_arguments)]
+    fn write_segment(
+        &mut self,
+        stream: &TcpStream,
+        from_local: bool,
+        flags: u8,
+        seq: u32,
+        ack: u32,
+        payload: &[u8],
+        time: SystemTime,
+        comment: &str,
+    ) -> io::Result<()> {
+        let (src_ip, src_port, dst_ip, dst_port) = if from_local {
+            (stream.local_ip, stream.local_port, PEER_IP, PEER_PORT)
+        } else {
+            (PEER_IP, PEER_PORT, stream.local_ip, stream.local_port)
+        };
+        self.ip_id = self.ip_id.wrapping_add(1);
+        let frame = build_frame(src_ip, src_port, dst_ip, dst_port, flags, seq, ack, self.ip_id, payload);
+
+        let ts = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+        let mut body = Vec::with_capacity(frame.len() + comment.len() + 32);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(ts as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        body.extend_from_slice(&frame);
+        pad(&mut body);
+        if !comment.is_empty() {
+            push_option(&mut body, OPT_COMMENT, comment.as_bytes());
+        }
+        push_option(&mut body, OPT_END, &[]);
+        self.write_block(ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    /// synthesize FIN segments for every open stream
+    pub fn close(&mut self, time: SystemTime) -> io::Result<()> {
+        let streams: Vec<TcpStream> = self.streams.drain().map(|(_, stream)| stream).collect();
+        for stream in streams {
+            self.write_segment(&stream, true, TCP_FIN | TCP_ACK, stream.local_seq, stream.peer_seq, &[], time, "")?;
+        }
+        self.writer.flush()
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let total_len = (body.len() + 12) as u32;
+        self.writer.write_all(&block_type.to_le_bytes())?;
+        self.writer.write_all(&total_len.to_le_bytes())?;
+        self.writer.write_all(body)?;
+        self.writer.write_all(&total_len.to_le_bytes())
+    }
+}
+
+// 按 tgid 生成本地地址 10.x.y.z，同一进程始终一致
+fn local_ip(tgid: u32) -> [u8; 4] {
+    [10, (tgid >> 16) as u8, (tgid >> 8) as u8, tgid as u8]
+}
+
+fn pad(buf: &mut Vec<u8>) {
+    while !buf.len().is_multiple_of(4) {
+        buf.push(0);
+    }
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_le_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+fn checksum(data: &[u8], initial: u32) -> u16 {
+    let mut sum = initial;
+    for chunk in data.chunks(2) {
+        let word = if chunk.len() == 2 {
+            u16::from_be_bytes([chunk[0], chunk[1]])
+        } else {
+            u16::from_be_bytes([chunk[0], 0])
+        };
+        sum += word as u32;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// 拼装 Ethernet + IPv4 + TCP 报文
+#[allow(clippy::too_many_arguments)]
+fn build_frame(
+    src_ip: [u8; 4],
+    src_port: u16,
+    dst_ip: [u8; 4],
+    dst_port: u16,
+    flags: u8,
+    seq: u32,
+    ack: u32,
+    ip_id: u16,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(54 + payload.len());
+    // Ethernet，MAC 由 IP 派生
+    frame.extend_from_slice(&[0x02, 0x00, dst_ip[0], dst_ip[1], dst_ip[2], dst_ip[3]]);
+    frame.extend_from_slice(&[0x02, 0x00, src_ip[0], src_ip[1], src_ip[2], src_ip[3]]);
+    frame.extend_from_slice(&0x0800u16.to_be_bytes());
+
+    // IPv4
+    let ip_start = frame.len();
+    let total_len = (20 + 20 + payload.len()) as u16;
+    frame.extend_from_slice(&[0x45, 0x00]);
+    frame.extend_from_slice(&total_len.to_be_bytes());
+    frame.extend_from_slice(&ip_id.to_be_bytes());
+    frame.extend_from_slice(&0x4000u16.to_be_bytes());
+    frame.extend_from_slice(&[64, 6, 0, 0]);
+    frame.extend_from_slice(&src_ip);
+    frame.extend_from_slice(&dst_ip);
+    let ip_checksum = checksum(&frame[ip_start..], 0);
+    frame[ip_start + 10..ip_start + 12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    // TCP
+    let tcp_start = frame.len();
+    frame.extend_from_slice(&src_port.to_be_bytes());
+    frame.extend_from_slice(&dst_port.to_be_bytes());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(&ack.to_be_bytes());
+    frame.extend_from_slice(&[5 << 4, flags]);
+    frame.extend_from_slice(&0xFFFFu16.to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0]);
+    frame.extend_from_slice(payload);
+
+    // 伪首部参与 TCP 校验和
+    let tcp_len = (frame.len() - tcp_start) as u32;
+    let mut pseudo: u32 = 0;
+    for ip in [src_ip, dst_ip] {
+        pseudo += u16::from_be_bytes([ip[0], ip[1]]) as u32;
+        pseudo += u16::from_be_bytes([ip[2], ip[3]]) as u32;
+    }
+    pseudo += 6 + tcp_len;
+    let tcp_checksum = checksum(&frame[tcp_start..], pseudo);
+    frame[tcp_start + 16..tcp_start + 18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{checksum, PcapEvent, PcapWriter, ENHANCED_PACKET_BLOCK, FIRST_LOCAL_PORT};
+    use ssl_observer_common::{READ, WRITE};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn event(conn_id: u64, rw: u8, payload: &[u8]) -> PcapEvent<'_> {
+        PcapEvent {
+            time: SystemTime::now(),
+            pid: 42,
+            tgid: 42,
+            conn_id,
+            rw,
+            comm: "curl",
+            payload,
+        }
+    }
+
+    #[test]
+    fn writes_valid_blocks() {
+        let path = std::env::temp_dir().join(format!("ssl-observer-{}.pcapng", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut pcap = PcapWriter::create(path, UNIX_EPOCH).unwrap();
+        pcap.write_event(&event(0x5555_0000, WRITE, b"GET / HTTP/1.1\r\n\r\n")).unwrap();
+        pcap.write_event(&event(0x5555_0000, READ, b"HTTP/1.1 200 OK\r\n\r\n")).unwrap();
+        pcap.close(SystemTime::now()).unwrap();
+        drop(pcap);
+
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let (mut offset, mut packets) = (0, 0);
+        while offset < bytes.len() {
+            let block_len = u32_at(offset + 4) as usize;
+            assert_eq!(u32_at(offset + block_len - 4) as usize, block_len);
+            if u32_at(offset) == ENHANCED_PACKET_BLOCK {
+                // 以太网头之后是 IPv4 头，校验和应自洽
+                let ip = offset + 28 + 14;
+                assert_eq!(checksum(&bytes[ip..ip + 20], 0), 0);
+                packets += 1;
+            }
+            offset += block_len;
+        }
+        // 三次握手 + 两个数据段 + FIN
+        assert_eq!(packets, 6);
+    }
+
+    #[test]
+    fn assigns_distinct_ports() {
+        let path = std::env::temp_dir().join(format!("ssl-observer-ports-{}.pcapng", std::process::id()));
+        let mut pcap = PcapWriter::create(path.to_str().unwrap(), UNIX_EPOCH).unwrap();
+        for conn_id in 0..1000 {
+            pcap.write_event(&event(conn_id, WRITE, b"x")).unwrap();
+        }
+        // 同一连接沿用已分配的端口
+        pcap.write_event(&event(0, READ, b"y")).unwrap();
+        let mut ports: Vec<u16> = pcap.streams.values().map(|stream| stream.local_port).collect();
+        ports.sort_unstable();
+        ports.dedup();
+        assert_eq!(ports.len(), 1000);
+        assert_eq!(pcap.streams[&(42, 0)].local_port, FIRST_LOCAL_PORT);
+        drop(pcap);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+use async_trait::async_trait;
+use log::{info, warn};
+use std::fs;
+use std::sync::Mutex;
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use ssl_observer_common::{ProbeSslData, WRITE};
+
+use crate::config::Plugin;
+use crate::decoder::{Decoder, DecoderState};
+use crate::utils::sanitize_comm;
+
+// 单次调用可消耗的 fuel，防止插件死循环
+const DEFAULT_FUEL: u64 = 10_000_000;
+const DEFAULT_MAX_MEMORY_MB: usize = 16;
+
+/// a decoder backed by a sandboxed wasm module
+///
+/// A plugin is a wasm module without imports, so it has no access to the host. It exports:
+/// - `memory`
+/// - `alloc(len: i32) -> i32`, a buffer the host copies arguments into
+/// - `detect(ptr: i32, len: i32) -> i32`, optional, non-zero when a connection starting with
+///   these bytes belongs to the plugin
+/// - `decode(meta_ptr: i32, meta_len: i32, ptr: i32, len: i32) -> i64`, called for every event
+///   with the event metadata as JSON, returns `(out_ptr << 32) | out_len` of UTF-8 output,
+///   a JSON array of records or plain text
+///
+/// Each connection gets its own instance, so a plugin may keep state across events.
+pub struct WasmDecoder {
+    name: &'static str,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    max_memory: usize,
+    has_detect: bool,
+    // detect 只在连接的前几个事件调用，共用一个实例
+    detector: Mutex<Option<WasmInstance>>,
+}
+
+struct WasmInstance {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    fuel: u64,
+}
+
+impl WasmDecoder {
+    /// compile the module listed in `[[decoders.plugins]]`
+    pub fn load(plugin: &Plugin) -> Result<Self, anyhow::Error> {
+        let bytes = fs::read(&plugin.path)?;
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes)?;
+        if module.imports().len() > 0 {
+            anyhow::bail!("plugins must not import anything");
+        }
+        let has_detect = module.exports().any(|export| export.name() == "detect");
+        let decoder = Self {
+            // 插件只在启动时加载一次
+            name: Box::leak(plugin.name.clone().into_boxed_str()),
+            engine,
+            module,
+            fuel: plugin.fuel.unwrap_or(DEFAULT_FUEL),
+            max_memory: plugin.max_memory_mb.unwrap_or(DEFAULT_MAX_MEMORY_MB) << 20,
+            has_detect,
+            detector: Mutex::new(None),
+        };
+        // 先实例化一次，尽早发现缺少导出等问题
+        *decoder.detector.lock().unwrap() = Some(decoder.instantiate()?);
+        info!("Loaded decoder plugin {} from {}", plugin.name, plugin.path);
+        Ok(decoder)
+    }
+
+    fn instantiate(&self) -> Result<WasmInstance, anyhow::Error> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.max_memory)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(self.fuel)?;
+        let linker = Linker::new(&self.engine);
+        let instance = linker.instantiate(&mut store, &self.module)?.start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("plugin does not export memory"))?;
+        Ok(WasmInstance {
+            store,
+            instance,
+            memory,
+            fuel: self.fuel,
+        })
+    }
+}
+
+impl WasmInstance {
+    // 在插件内存中分配并写入参数
+    fn write(&mut self, bytes: &[u8]) -> Result<i32, anyhow::Error> {
+        let alloc = self.instance.get_typed_func::<i32, i32>(&self.store, "alloc")?;
+        let ptr = alloc.call(&mut self.store, bytes.len() as i32)?;
+        self.memory.write(&mut self.store, ptr as u32 as usize, bytes)?;
+        Ok(ptr)
+    }
+
+    fn detect(&mut self, payload: &[u8]) -> Result<bool, anyhow::Error> {
+        self.store.set_fuel(self.fuel)?;
+        let ptr = self.write(payload)?;
+        let detect = self.instance.get_typed_func::<(i32, i32), i32>(&self.store, "detect")?;
+        Ok(detect.call(&mut self.store, (ptr, payload.len() as i32))? != 0)
+    }
+
+    fn decode(&mut self, meta: &[u8], payload: &[u8]) -> Result<String, anyhow::Error> {
+        self.store.set_fuel(self.fuel)?;
+        let meta_ptr = self.write(meta)?;
+        let ptr = self.write(payload)?;
+        let decode = self
+            .instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&self.store, "decode")?;
+        let packed = decode.call(
+            &mut self.store,
+            (meta_ptr, meta.len() as i32, ptr, payload.len() as i32),
+        )? as u64;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let out = self
+            .memory
+            .data(&self.store)
+            .get(out_ptr..out_ptr + out_len)
+            .ok_or_else(|| anyhow::anyhow!("output out of bounds"))?;
+        Ok(render(out))
+    }
+}
+
+// JSON 记录逐条格式化输出，其他内容按文本输出
+fn render(out: &[u8]) -> String {
+    match serde_json::from_slice::<serde_json::Value>(out) {
+        Ok(serde_json::Value::Array(records)) => records
+            .iter()
+            .map(|record| serde_json::to_string_pretty(record).unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\r\n"),
+        Ok(record) => serde_json::to_string_pretty(&record).unwrap_or_default(),
+        Err(_) => String::from_utf8_lossy(out).into_owned(),
+    }
+}
+
+impl Decoder for WasmDecoder {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn detect(&self, payload: &[u8]) -> Option<&'static str> {
+        if !self.has_detect {
+            return None;
+        }
+        let mut detector = self.detector.lock().unwrap();
+        if detector.is_none() {
+            *detector = self.instantiate().ok();
+        }
+        match detector.as_mut()?.detect(payload) {
+            Ok(matched) => matched.then_some(self.name),
+            Err(e) => {
+                warn!("Decoder plugin {} detect failed: {}", self.name, e);
+                // 出错后实例状态不可信，下次重新实例化
+                *detector = None;
+                None
+            }
+        }
+    }
+
+    fn connect(&self) -> Box<dyn DecoderState> {
+        Box::new(WasmState {
+            name: self.name,
+            instance: self.instantiate().map_err(|e| e.to_string()),
+        })
+    }
+}
+
+struct WasmState {
+    name: &'static str,
+    instance: Result<WasmInstance, String>,
+}
+
+#[async_trait]
+impl DecoderState for WasmState {
+    async fn decode(&mut self, data: &ProbeSslData) -> String {
+        let meta = serde_json::json!({
+            "timestamp_ns": data.timestamp_ns,
+            "pid": data.pid,
+            "tgid": data.tgid,
+            "conn_id": data.conn_id,
+            "comm": sanitize_comm(&data.comm),
+            "rw": if data.rw == WRITE { "write" } else { "read" },
+        });
+        let result = match self.instance.as_mut() {
+            Ok(instance) => instance
+                .decode(meta.to_string().as_bytes(), &data.buf[..data.len])
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.clone()),
+        };
+        result.unwrap_or_else(|e| format!("[plugin] {} decode failed: {}", self.name, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WasmDecoder;
+    use crate::config::Plugin;
+    use crate::decoder::Decoder;
+    use crate::test_util::event;
+    use ssl_observer_common::WRITE;
+
+    // 识别以 "PING" 开头的连接，decode 原样返回数据
+    const ECHO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "detect") (param $ptr i32) (param $len i32) (result i32)
+            (i32.and
+              (i32.ge_u (local.get $len) (i32.const 4))
+              (i32.eq (i32.load (local.get $ptr)) (i32.const 0x474e4950))))
+          (func (export "decode") (param i32 i32) (param $ptr i32) (param $len i32) (result i64)
+            (global.set $next (i32.const 1024))
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "spin") (loop (br 0))))
+    "#;
+
+    fn load(name: &str) -> WasmDecoder {
+        let path = std::env::temp_dir().join(format!("ssl-observer-plugin-{}-{}.wasm", name, std::process::id()));
+        std::fs::write(&path, wat::parse_str(ECHO).unwrap()).unwrap();
+        let plugin = Plugin {
+            name: "echo".to_string(),
+            path: path.to_string_lossy().into_owned(),
+            fuel: None,
+            max_memory_mb: None,
+        };
+        let decoder = WasmDecoder::load(&plugin).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        decoder
+    }
+
+    #[test]
+    fn fuel_stops_runaway_call() {
+        // fuel 用尽时调用失败而不是卡住
+        let decoder = load("fuel");
+        let mut wasm = decoder.instantiate().unwrap();
+        let spin = wasm.instance.get_typed_func::<(), ()>(&wasm.store, "spin").unwrap();
+        assert!(spin.call(&mut wasm.store, ()).is_err());
+    }
+
+    #[tokio::test]
+    async fn detects_and_decodes() {
+        let decoder = load("decode");
+        assert_eq!(decoder.detect(b"PING\r\n"), Some("echo"));
+        assert_eq!(decoder.detect(b"GET / HTTP/1.1\r\n"), None);
+
+        let mut state = decoder.connect();
+        let data = event(WRITE, 1, br#"[{"cmd":"PING"}]"#);
+        assert_eq!(state.decode(&data).await, "{\n  \"cmd\": \"PING\"\n}");
+    }
+}
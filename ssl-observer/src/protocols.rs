@@ -0,0 +1,370 @@
+use crate::decode::Protocol;
+
+// RESP 嵌套展开的最大深度
+const MAX_RESP_DEPTH: usize = 4;
+
+/// summarize one event of a non-HTTP protocol, the payload text is kept after the summary for text protocols
+pub fn describe(protocol: Protocol, buf: &[u8]) -> String {
+    let lines = match protocol {
+        Protocol::Smtp => describe_smtp(buf),
+        Protocol::Imap => describe_imap(buf),
+        Protocol::Pop3 => describe_pop3(buf),
+        Protocol::Postgres => return describe_postgres(buf).join("\r\n"),
+        Protocol::Mysql => return describe_mysql(buf).join("\r\n"),
+        Protocol::Redis => return describe_redis(buf).join("\r\n"),
+        _ => Vec::new(),
+    };
+    // 文本协议保留原文
+    let text = String::from_utf8_lossy(buf);
+    if lines.is_empty() {
+        return text.to_string();
+    }
+    format!("{}\r\n{}", lines.join("\r\n"), text)
+}
+
+fn first_line(buf: &[u8]) -> String {
+    let text = String::from_utf8_lossy(buf);
+    text.split("\r\n").next().unwrap_or("").to_string()
+}
+
+// "220 mx ESMTP" 为应答，"MAIL FROM:<a@b>" 为命令
+fn describe_smtp(buf: &[u8]) -> Vec<String> {
+    let line = first_line(buf);
+    let code = line.get(..3).filter(|code| code.bytes().all(|b| b.is_ascii_digit()));
+    match code {
+        Some(code) => vec![format!("[smtp] reply={}", code)],
+        None => match line.split([' ', ':']).next() {
+            Some(verb) if !verb.is_empty() && verb.bytes().all(|b| b.is_ascii_alphabetic()) => {
+                vec![format!("[smtp] command={}", verb.to_ascii_uppercase())]
+            }
+            _ => Vec::new(),
+        },
+    }
+}
+
+// "* 3 EXISTS" 为未标记应答，"a1 OK ..." 为标记应答，"a1 LOGIN ..." 为命令
+fn describe_imap(buf: &[u8]) -> Vec<String> {
+    let line = first_line(buf);
+    let mut parts = line.split(' ');
+    let (tag, word) = match (parts.next(), parts.next()) {
+        (Some(tag), Some(word)) if !tag.is_empty() => (tag, word),
+        _ => return Vec::new(),
+    };
+    if tag == "*" {
+        return vec![format!("[imap] untagged {}", word)];
+    }
+    if tag == "+" {
+        return vec!["[imap] continuation".to_string()];
+    }
+    let word = word.to_ascii_uppercase();
+    if matches!(word.as_str(), "OK" | "NO" | "BAD") {
+        vec![format!("[imap] tag={} status={}", tag, word)]
+    } else {
+        vec![format!("[imap] tag={} command={}", tag, word)]
+    }
+}
+
+fn describe_pop3(buf: &[u8]) -> Vec<String> {
+    let line = first_line(buf);
+    if line.starts_with("+OK") || line.starts_with("-ERR") {
+        let status = line.split(' ').next().unwrap_or("");
+        return vec![format!("[pop3] status={}", status)];
+    }
+    match line.split(' ').next() {
+        Some(verb) if !verb.is_empty() && verb.bytes().all(|b| b.is_ascii_alphabetic()) => {
+            vec![format!("[pop3] command={}", verb.to_ascii_uppercase())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn be_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+// 以 \0 结尾的字符串
+fn cstrings(buf: &[u8]) -> Vec<String> {
+    buf.split(|b| *b == 0)
+        .filter(|part| !part.is_empty())
+        .map(|part| String::from_utf8_lossy(part).to_string())
+        .collect()
+}
+
+/// PostgreSQL v3 wire protocol, one line per message
+fn describe_postgres(buf: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = buf;
+    // StartupMessage 没有类型字节：长度 + 协议版本 3.0 + 参数
+    if let (Some(len), Some(0x0003_0000)) = (be_u32(rest, 0), be_u32(rest, 4)) {
+        let len = len as usize;
+        if let Some(body) = rest.get(8..len) {
+            let params = cstrings(body);
+            let params: Vec<String> = params
+                .chunks(2)
+                .map(|pair| pair.join("="))
+                .collect();
+            lines.push(format!("[pgsql] Startup: {}", params.join(" ")));
+            rest = &rest[len..];
+        }
+    }
+    while rest.len() >= 5 {
+        let kind = rest[0];
+        let len = be_u32(rest, 1).unwrap_or(0) as usize;
+        let body = match rest.get(5..1 + len) {
+            Some(body) if len >= 4 => body,
+            _ => break,
+        };
+        rest = &rest[1 + len..];
+        let line = match kind {
+            b'Q' => format!("Query: {}", cstrings(body).join("")),
+            b'P' => {
+                let strings = cstrings(body);
+                format!("Parse: {}", strings.get(1).or(strings.first()).map_or("", String::as_str))
+            }
+            b'B' => "Bind".to_string(),
+            b'E' if body.first().is_some_and(|b| b.is_ascii_uppercase()) => {
+                // ErrorResponse：字段类型字节 + 字符串
+                let fields: Vec<String> = body
+                    .split(|b| *b == 0)
+                    .filter(|field| field.len() > 1 && matches!(field[0], b'S' | b'C' | b'M'))
+                    .map(|field| String::from_utf8_lossy(&field[1..]).to_string())
+                    .collect();
+                format!("ErrorResponse: {}", fields.join(" "))
+            }
+            b'E' => "Execute".to_string(),
+            b'S' if body.is_empty() => "Sync".to_string(),
+            b'S' => format!("ParameterStatus: {}", cstrings(body).join("=")),
+            b'X' => "Terminate".to_string(),
+            b'R' => format!("Authentication: {}", be_u32(body, 0).unwrap_or(0)),
+            b'T' => {
+                // RowDescription：字段数 + 每个字段名后跟 18 字节属性
+                let mut columns = Vec::new();
+                let mut pos = 2;
+                while let Some(end) = body.get(pos..).and_then(|field| field.iter().position(|b| *b == 0)) {
+                    columns.push(String::from_utf8_lossy(&body[pos..pos + end]).to_string());
+                    pos += end + 1 + 18;
+                }
+                format!("RowDescription: {}", columns.join(", "))
+            }
+            b'D' => {
+                // DataRow：字段数 + 每个字段的长度与内容，-1 为 NULL
+                let mut values = Vec::new();
+                let mut pos = 2;
+                while let Some(len) = be_u32(body, pos) {
+                    pos += 4;
+                    if len == u32::MAX {
+                        values.push("NULL".to_string());
+                        continue;
+                    }
+                    let value = match body.get(pos..pos + len as usize) {
+                        Some(value) => value,
+                        None => break,
+                    };
+                    values.push(String::from_utf8_lossy(value).to_string());
+                    pos += len as usize;
+                }
+                format!("DataRow: {}", values.join(", "))
+            }
+            b'C' => format!("CommandComplete: {}", cstrings(body).join("")),
+            b'Z' => format!("ReadyForQuery: {}", body.first().map_or('?', |b| *b as char)),
+            kind => format!("'{}' ({} bytes)", kind as char, body.len()),
+        };
+        lines.push(format!("[pgsql] {}", line));
+    }
+    if !rest.is_empty() {
+        lines.push(format!("[pgsql] {} bytes incomplete", rest.len()));
+    }
+    lines
+}
+
+/// MySQL client/server protocol, one line per packet
+fn describe_mysql(buf: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = buf;
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes([rest[0], rest[1], rest[2], 0]) as usize;
+        let seq = rest[3];
+        let payload = match rest.get(4..4 + len) {
+            Some(payload) if len > 0 => payload,
+            _ => break,
+        };
+        rest = &rest[4 + len..];
+        let text = |from: usize| String::from_utf8_lossy(payload.get(from..).unwrap_or(&[])).to_string();
+        let line = match (seq, payload[0]) {
+            // 序号为 0 的是客户端命令
+            (0, 0x01) => "COM_QUIT".to_string(),
+            (0, 0x02) => format!("COM_INIT_DB: {}", text(1)),
+            (0, 0x03) => format!("COM_QUERY: {}", text(1)),
+            (0, 0x0e) => "COM_PING".to_string(),
+            (0, 0x16) => format!("COM_STMT_PREPARE: {}", text(1)),
+            (0, 0x17) => format!(
+                "COM_STMT_EXECUTE: stmt={}",
+                payload.get(1..5).map_or(0, |id| u32::from_le_bytes(id.try_into().unwrap()))
+            ),
+            (0, 0x19) => "COM_STMT_CLOSE".to_string(),
+            (0, command) => format!("command 0x{:02x} ({} bytes)", command, len),
+            // TLS 之后的 HandshakeResponse41：能力标志、最大包长、字符集、23 字节保留，然后是用户名
+            (1 | 2, _) if len > 32 && payload[1] & 0x02 != 0 => {
+                format!("HandshakeResponse: user={}", cstrings(&payload[32..]).first().map_or("", String::as_str))
+            }
+            (_, 0x00) if len < 0xfb => "OK".to_string(),
+            (_, 0xfe) if len < 9 => "EOF".to_string(),
+            (_, 0xff) => {
+                // ERR：错误码 + '#' + 5 字节 SQLSTATE + 消息
+                let code = payload.get(1..3).map_or(0, |code| u16::from_le_bytes([code[0], code[1]]));
+                let message = if payload.get(3) == Some(&b'#') { text(9) } else { text(3) };
+                format!("ERR {}: {}", code, message)
+            }
+            (seq, _) => format!("packet seq={} ({} bytes): {}", seq, len, text(0).replace(|c: char| c.is_control(), ".")),
+        };
+        lines.push(format!("[mysql] {}", line));
+    }
+    if !rest.is_empty() {
+        lines.push(format!("[mysql] {} bytes incomplete", rest.len()));
+    }
+    lines
+}
+
+enum Resp {
+    Simple(String),
+    Error(String),
+    Integer(String),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Resp>>),
+}
+
+impl Resp {
+    fn render(&self) -> String {
+        match self {
+            Resp::Simple(text) => text.clone(),
+            Resp::Error(text) => format!("(error) {}", text),
+            Resp::Integer(text) => format!("(integer) {}", text),
+            Resp::Bulk(Some(data)) => format!("{:?}", String::from_utf8_lossy(data)),
+            Resp::Bulk(None) | Resp::Array(None) => "(nil)".to_string(),
+            Resp::Array(Some(items)) => {
+                // 客户端命令是批量字符串数组，按单词显示
+                let words: Option<Vec<String>> = items
+                    .iter()
+                    .map(|item| match item {
+                        Resp::Bulk(Some(data)) => Some(String::from_utf8_lossy(data).to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                match words {
+                    Some(words) if !words.is_empty() => words.join(" "),
+                    _ => format!(
+                        "[{}]",
+                        items.iter().map(Resp::render).collect::<Vec<_>>().join(", ")
+                    ),
+                }
+            }
+        }
+    }
+}
+
+// 解析一个 RESP 值，返回值与占用的字节数
+fn parse_resp(buf: &[u8], depth: usize) -> Option<(Resp, usize)> {
+    let kind = *buf.first()?;
+    let line_end = buf.windows(2).position(|window| window == b"\r\n")?;
+    // 以 "\r\n" 开头时没有类型字节，不是合法的 RESP 值
+    let line = String::from_utf8_lossy(buf.get(1..line_end)?).to_string();
+    let mut pos = line_end + 2;
+    let value = match kind {
+        b'+' => Resp::Simple(line),
+        b'-' => Resp::Error(line),
+        b':' => Resp::Integer(line),
+        b'$' => {
+            let len: i64 = line.parse().ok()?;
+            if len < 0 {
+                Resp::Bulk(None)
+            } else {
+                let data_end = pos.checked_add(usize::try_from(len).ok()?)?;
+                let data = buf.get(pos..data_end)?;
+                pos = data_end + 2;
+                Resp::Bulk(Some(data.to_vec()))
+            }
+        }
+        b'*' if depth < MAX_RESP_DEPTH => {
+            let count: i64 = line.parse().ok()?;
+            if count < 0 {
+                Resp::Array(None)
+            } else {
+                let mut items = Vec::new();
+                for _ in 0..count {
+                    let (item, consumed) = parse_resp(buf.get(pos..)?, depth + 1)?;
+                    items.push(item);
+                    pos += consumed;
+                }
+                Resp::Array(Some(items))
+            }
+        }
+        _ => return None,
+    };
+    Some((value, pos.min(buf.len())))
+}
+
+/// Redis RESP, one line per command or reply
+fn describe_redis(buf: &[u8]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = buf;
+    while !rest.is_empty() {
+        match parse_resp(rest, 0) {
+            Some((value, consumed)) => {
+                lines.push(format!("[redis] {}", value.render()));
+                rest = &rest[consumed..];
+            }
+            None => {
+                lines.push(format!("[redis] {}", String::from_utf8_lossy(rest)));
+                break;
+            }
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::describe;
+    use crate::decode::Protocol;
+
+    #[test]
+    fn redis_commands_and_replies() {
+        assert_eq!(
+            describe(Protocol::Redis, b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$1\r\nv\r\n"),
+            "[redis] SET k v"
+        );
+        assert_eq!(describe(Protocol::Redis, b"+OK\r\n:1\r\n"), "[redis] OK\r\n[redis] (integer) 1");
+    }
+
+    #[test]
+    fn redis_leading_crlf() {
+        // 批量字符串跨两次 SSL_read 时，第二个事件以 "\r\n" 开头
+        assert_eq!(describe(Protocol::Redis, b"\r\n"), "[redis] \r\n");
+        assert_eq!(describe(Protocol::Redis, b"+OK\r\n\r\n"), "[redis] OK\r\n[redis] \r\n");
+    }
+
+    #[test]
+    fn redis_oversized_bulk_length() {
+        assert_eq!(describe(Protocol::Redis, b"$9223372036854775807\r\nab"), "[redis] $9223372036854775807\r\nab");
+    }
+
+    #[test]
+    fn postgres_query() {
+        let mut query = vec![b'Q', 0, 0, 0, 13];
+        query.extend_from_slice(b"SELECT 1\0");
+        assert_eq!(describe(Protocol::Postgres, &query), "[pgsql] Query: SELECT 1");
+    }
+
+    #[test]
+    fn mysql_query() {
+        let mut packet = vec![9, 0, 0, 0, 0x03];
+        packet.extend_from_slice(b"SELECT 1");
+        assert_eq!(describe(Protocol::Mysql, &packet), "[mysql] COM_QUERY: SELECT 1");
+    }
+
+    #[test]
+    fn text_protocols_keep_payload() {
+        assert!(describe(Protocol::Imap, b"a1 LOGIN u p\r\n").starts_with("[imap] tag=a1 command=LOGIN\r\n"));
+        assert!(describe(Protocol::Smtp, b"250-mx hello\r\n").starts_with("[smtp] reply=250\r\n"));
+    }
+}
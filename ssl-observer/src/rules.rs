@@ -0,0 +1,511 @@
+use log::warn;
+use regex::{bytes, Regex};
+use std::collections::HashMap;
+
+use ssl_observer_common::{ProbeSslData, READ, WRITE};
+
+use crate::config::{AlertRule, Alerts};
+use crate::http1::{HttpExchange, HttpHead};
+use crate::redact::redact;
+use crate::utils::sanitize_comm;
+
+// 告警中保留的匹配位置前后字节数
+const EXCERPT_CONTEXT: usize = 32;
+// 没有 payload 条件时截取的开头字节数
+const EXCERPT_HEAD: usize = 128;
+// 连接空闲超过该时间后清理记住的状态
+const IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+/// one fired rule
+pub struct Alert {
+    pub timestamp_ns: u64,
+    /// "rule" for `[[rules]]`, "yara" for signature matches
+    pub source: &'static str,
+    pub rule: String,
+    pub severity: String,
+    pub comm: String,
+    pub pid: u32,
+    pub tgid: u32,
+    /// not known for reassembled messages
+    pub uid: Option<u32>,
+    pub conn_id: u64,
+    pub stream_id: Option<u32>,
+    /// None when the direction of a reassembled message is unknown
+    pub rw: Option<u8>,
+    pub host: Option<String>,
+    /// what `offset` refers to: "event", "request", "response" or "websocket"
+    pub scope: &'static str,
+    pub offset: Option<u64>,
+    /// payload around the match, redacted
+    pub excerpt: String,
+}
+
+/// redacted text around `offset..offset+len`, or the start of the payload
+pub fn excerpt(payload: &[u8], found: Option<(usize, usize)>) -> String {
+    let (start, end) = match found {
+        Some((start, end)) => (
+            start.saturating_sub(EXCERPT_CONTEXT),
+            (end + EXCERPT_CONTEXT).min(payload.len()),
+        ),
+        None => (0, payload.len().min(EXCERPT_HEAD)),
+    };
+    redact(&String::from_utf8_lossy(&payload[start..end]))
+}
+
+/// fixed-window rate limit of one rule
+#[derive(Default)]
+pub struct RateWindow {
+    // 当前限流窗口的起点、已触发与被抑制的次数
+    start_ns: u64,
+    fired: u32,
+    suppressed: u64,
+}
+
+impl RateWindow {
+    /// count one alert of `rule`, return whether it should be raised
+    pub fn allow(&mut self, rule: &str, limit: u32, now_ns: u64, interval_ns: u64) -> bool {
+        if now_ns.saturating_sub(self.start_ns) >= interval_ns {
+            if self.suppressed > 0 {
+                warn!("Rule {} suppressed {} alerts", rule, self.suppressed);
+            }
+            self.start_ns = now_ns;
+            self.fired = 0;
+            self.suppressed = 0;
+        }
+        self.fired += 1;
+        if limit > 0 && self.fired > limit {
+            self.suppressed += 1;
+            return false;
+        }
+        true
+    }
+}
+
+// 规则匹配的对象，单个事件或重组后的一条 HTTP 消息
+struct Subject<'a> {
+    comm: &'a str,
+    // 重组消息沿用连接上最近一次事件的 uid
+    uid: Option<u32>,
+    rw: u8,
+    head: &'a HttpHead,
+    host: Option<&'a str>,
+    payload: &'a [u8],
+}
+
+struct CompiledRule {
+    name: String,
+    severity: String,
+    comm: Vec<String>,
+    uid: Vec<u32>,
+    direction: Option<u8>,
+    host: Option<Regex>,
+    method: Vec<String>,
+    path: Option<Regex>,
+    status: Vec<u16>,
+    content_type: Option<Regex>,
+    payload: Vec<bytes::Regex>,
+    limit: u32,
+    window: RateWindow,
+}
+
+impl CompiledRule {
+    fn new(rule: &AlertRule, alerts: &Alerts) -> Result<Self, String> {
+        let regex = |pattern: &Option<String>| {
+            pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| e.to_string())
+        };
+        let direction = match rule.direction.as_deref() {
+            None => None,
+            Some("read") => Some(READ),
+            Some("write") => Some(WRITE),
+            Some(other) => return Err(format!("unknown direction \"{}\"", other)),
+        };
+        let mut payload = Vec::new();
+        if let Some(pattern) = &rule.payload {
+            payload.push(bytes::Regex::new(pattern).map_err(|e| e.to_string())?);
+        }
+        if let Some(hex) = &rule.bytes {
+            payload.push(hex_pattern(hex)?);
+        }
+        Ok(Self {
+            name: rule.name.clone(),
+            severity: rule.severity.clone().unwrap_or_else(|| "medium".to_string()),
+            comm: rule.comm.clone(),
+            uid: rule.uid.clone(),
+            direction,
+            host: regex(&rule.host)?,
+            method: rule.method.clone(),
+            path: regex(&rule.path)?,
+            status: rule.status.clone(),
+            content_type: regex(&rule.content_type)?,
+            payload,
+            limit: rule.rate_limit.unwrap_or(alerts.rate_limit),
+            window: RateWindow::default(),
+        })
+    }
+
+    // 条件全部满足时返回匹配到的字节范围，没有 payload 条件时为 None
+    fn matches(&self, subject: &Subject) -> Option<Option<(usize, usize)>> {
+        let text = |regex: &Option<Regex>, value: Option<&str>| match regex {
+            Some(regex) => value.is_some_and(|value| regex.is_match(value)),
+            None => true,
+        };
+        let head = subject.head;
+        let matched = (self.comm.is_empty() || self.comm.iter().any(|name| name == subject.comm))
+            && (self.uid.is_empty() || subject.uid.is_some_and(|uid| self.uid.contains(&uid)))
+            && self.direction.is_none_or(|rw| rw == subject.rw)
+            && text(&self.host, subject.host)
+            && (self.method.is_empty()
+                || head
+                    .method
+                    .as_ref()
+                    .is_some_and(|method| self.method.iter().any(|m| m.eq_ignore_ascii_case(method))))
+            && text(&self.path, head.path.as_deref())
+            && (self.status.is_empty() || head.status.is_some_and(|status| self.status.contains(&status)))
+            && text(&self.content_type, head.content_type.as_deref());
+        if !matched {
+            return None;
+        }
+        let mut range = None;
+        for regex in &self.payload {
+            let found = regex.find(subject.payload)?;
+            range.get_or_insert((found.start(), found.end()));
+        }
+        Some(range)
+    }
+}
+
+// "de ad be ef" 或 "deadbeef" 转为逐字节匹配的正则
+fn hex_pattern(hex: &str) -> Result<bytes::Regex, String> {
+    let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex bytes \"{}\"", hex));
+    }
+    let pattern: String = (0..digits.len())
+        .step_by(2)
+        .map(|i| format!(r"\x{}", &digits[i..i + 2]))
+        .collect();
+    bytes::Regex::new(&format!("(?-u){}", pattern)).map_err(|e| e.to_string())
+}
+
+// 一条连接上记住的状态
+struct ConnState {
+    // 最近一次出现的 Host，响应和后续数据也能按 host 匹配
+    host: Option<String>,
+    uid: u32,
+    // 按 (规则下标, 方向) 记录最近一次在单个事件上匹配的时间，重组后的消息不再重复告警
+    fired: HashMap<(usize, u8), u64>,
+    last_ns: u64,
+}
+
+/// evaluates the `[[rules]]` of config.toml against each event and each reassembled HTTP message
+pub struct RuleEngine {
+    rules: Vec<CompiledRule>,
+    interval_ns: u64,
+    conns: HashMap<(u32, u64), ConnState>,
+    events: u64,
+}
+
+impl RuleEngine {
+    pub fn new(rules: &[AlertRule], alerts: &Alerts) -> Self {
+        let rules = rules
+            .iter()
+            .filter_map(|rule| match CompiledRule::new(rule, alerts) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    warn!("Skipping rule {}: {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self {
+            rules,
+            interval_ns: alerts.rate_interval_secs.max(1) * 1_000_000_000,
+            conns: HashMap::new(),
+            events: 0,
+        }
+    }
+
+    /// return the alerts raised by one event, after rate limiting
+    pub fn evaluate(&mut self, data: &ProbeSslData) -> Vec<Alert> {
+        if self.rules.is_empty() || data.is_handshake || data.len == 0 {
+            return Vec::new();
+        }
+        let ts = data.timestamp_ns;
+        self.events += 1;
+        if self.events.is_multiple_of(SWEEP_INTERVAL) {
+            self.conns
+                .retain(|_, conn| ts.saturating_sub(conn.last_ns) <= IDLE_TIMEOUT_NS);
+        }
+
+        let payload = &data.buf[..data.len];
+        let head = HttpHead::parse(payload).unwrap_or_default();
+        let conn = self.conns.entry((data.tgid, data.conn_id)).or_insert_with(|| ConnState {
+            host: None,
+            uid: data.uid,
+            fired: HashMap::new(),
+            last_ns: ts,
+        });
+        if head.host.is_some() {
+            conn.host = head.host.clone();
+        }
+        conn.uid = data.uid;
+        conn.last_ns = ts;
+        let host = conn.host.clone();
+        let comm = sanitize_comm(&data.comm);
+        let subject = Subject {
+            comm: &comm,
+            uid: Some(data.uid),
+            rw: data.rw,
+            head: &head,
+            host: host.as_deref(),
+            payload,
+        };
+
+        let mut alerts = Vec::new();
+        for (index, rule) in self.rules.iter_mut().enumerate() {
+            let Some(range) = rule.matches(&subject) else {
+                continue;
+            };
+            conn.fired.insert((index, data.rw), ts);
+            if !rule.window.allow(&rule.name, rule.limit, ts, self.interval_ns) {
+                continue;
+            }
+            alerts.push(Alert {
+                timestamp_ns: ts,
+                source: "rule",
+                rule: rule.name.clone(),
+                severity: rule.severity.clone(),
+                comm: comm.clone(),
+                pid: data.pid,
+                tgid: data.tgid,
+                uid: Some(data.uid),
+                conn_id: data.conn_id,
+                stream_id: None,
+                rw: Some(data.rw),
+                host: host.clone(),
+                scope: "event",
+                offset: range.map(|(start, _)| start as u64),
+                excerpt: excerpt(payload, range),
+            });
+        }
+        alerts
+    }
+
+    /// return the alerts raised by the request and response of a reassembled exchange,
+    /// skipping rules that already fired on one of the message's events
+    pub fn evaluate_exchange(&mut self, exchange: &HttpExchange) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        if self.rules.is_empty() {
+            return alerts;
+        }
+        let conn = self.conns.get(&(exchange.tgid, exchange.conn_id));
+        let request_head = exchange
+            .request
+            .as_ref()
+            .and_then(|request| HttpHead::parse(&request.raw));
+        // 响应没有 Host，使用请求或连接上记住的值
+        let host = request_head
+            .as_ref()
+            .and_then(|head| head.host.clone())
+            .or_else(|| conn.and_then(|conn| conn.host.clone()));
+        for (scope, message) in [("request", &exchange.request), ("response", &exchange.response)] {
+            let Some(message) = message else {
+                continue;
+            };
+            let head = HttpHead::parse(&message.raw).unwrap_or_default();
+            let subject = Subject {
+                comm: &exchange.comm,
+                uid: conn.map(|conn| conn.uid),
+                rw: message.rw,
+                head: &head,
+                host: host.as_deref(),
+                payload: &message.raw,
+            };
+            for (index, rule) in self.rules.iter_mut().enumerate() {
+                let fired = conn
+                    .and_then(|conn| conn.fired.get(&(index, message.rw)))
+                    .is_some_and(|&ts| ts >= message.start_ns && ts <= message.end_ns);
+                if fired {
+                    continue;
+                }
+                let Some(range) = rule.matches(&subject) else {
+                    continue;
+                };
+                if !rule.window.allow(&rule.name, rule.limit, message.end_ns, self.interval_ns) {
+                    continue;
+                }
+                alerts.push(Alert {
+                    timestamp_ns: message.end_ns,
+                    source: "rule",
+                    rule: rule.name.clone(),
+                    severity: rule.severity.clone(),
+                    comm: exchange.comm.clone(),
+                    pid: exchange.pid,
+                    tgid: exchange.tgid,
+                    uid: subject.uid,
+                    conn_id: exchange.conn_id,
+                    stream_id: exchange.stream_id,
+                    rw: Some(message.rw),
+                    host: host.clone(),
+                    scope,
+                    offset: range.map(|(start, _)| start as u64),
+                    excerpt: excerpt(&message.raw, range),
+                });
+            }
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuleEngine;
+    use crate::config::{AlertRule, Alerts};
+    use crate::http1::{HttpExchange, HttpMessage};
+    use crate::test_util::event;
+    use ssl_observer_common::{READ, WRITE};
+
+    fn rule(name: &str) -> AlertRule {
+        AlertRule {
+            name: name.to_string(),
+            severity: None,
+            comm: Vec::new(),
+            uid: Vec::new(),
+            direction: None,
+            host: None,
+            method: Vec::new(),
+            path: None,
+            status: Vec::new(),
+            content_type: None,
+            payload: None,
+            bytes: None,
+            rate_limit: None,
+        }
+    }
+
+    fn engine() -> RuleEngine {
+        let rules = [
+            AlertRule {
+                direction: Some("write".to_string()),
+                host: Some(r"\.example\.com$".to_string()),
+                payload: Some("ssn=[0-9]{9}".to_string()),
+                ..rule("ssn")
+            },
+            AlertRule {
+                bytes: Some("de ad be ef".to_string()),
+                rate_limit: Some(1),
+                ..rule("magic")
+            },
+            AlertRule {
+                direction: Some("sideways".to_string()),
+                ..rule("invalid")
+            },
+        ];
+        RuleEngine::new(&rules, &Alerts::default())
+    }
+
+    #[test]
+    fn skips_invalid_rule() {
+        assert_eq!(engine().rules.len(), 2);
+    }
+
+    #[test]
+    fn matches_host_direction_and_payload() {
+        let mut engine = engine();
+        let request = b"POST /form HTTP/1.1\r\nHost: api.example.com\r\n\r\nname=a&ssn=123456789";
+        let alerts = engine.evaluate(&event(WRITE, 1, request));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "ssn");
+        assert_eq!(alerts[0].host.as_deref(), Some("api.example.com"));
+        assert!(alerts[0].excerpt.ends_with("ssn=123456789"));
+        // 同一连接后续数据沿用 host，方向不符时不触发
+        assert!(engine.evaluate(&event(READ, 2, b"ssn=123456789")).is_empty());
+        assert_eq!(engine.evaluate(&event(WRITE, 3, b"ssn=123456789")).len(), 1);
+    }
+
+    #[test]
+    fn rate_limit_suppresses_alerts() {
+        let mut engine = engine();
+        // 超过限流的告警被抑制，进入下一个窗口后恢复
+        assert_eq!(engine.evaluate(&event(READ, 4, b"\xde\xad\xbe\xef")).len(), 1);
+        assert!(engine.evaluate(&event(READ, 5, b"\xde\xad\xbe\xef")).is_empty());
+        assert_eq!(engine.evaluate(&event(READ, 61_000_000_004, b"\xde\xad\xbe\xef")).len(), 1);
+    }
+
+    fn message(rw: u8, start_ns: u64, end_ns: u64, raw: &str) -> HttpMessage {
+        HttpMessage {
+            start_line: raw.split("\r\n").next().unwrap().to_string(),
+            raw: raw.as_bytes().to_vec(),
+            start_ns,
+            end_ns,
+            truncated: false,
+            rw,
+            trailers: Vec::new(),
+        }
+    }
+
+    fn exchange(request: HttpMessage, response: HttpMessage) -> HttpExchange {
+        HttpExchange {
+            pid: 7,
+            tgid: 7,
+            conn_id: 1,
+            stream_id: None,
+            comm: String::new(),
+            request: Some(request),
+            response: Some(response),
+        }
+    }
+
+    #[test]
+    fn matches_payload_split_across_events() {
+        let mut engine = engine();
+        let first = b"POST /form HTTP/1.1\r\nHost: api.example.com\r\n\r\nssn=1234";
+        assert!(engine.evaluate(&event(WRITE, 1, first)).is_empty());
+        assert!(engine.evaluate(&event(WRITE, 2, b"56789")).is_empty());
+        let request = message(WRITE, 1, 2, "POST /form HTTP/1.1\r\nHost: api.example.com\r\n\r\nssn=123456789");
+        let response = message(READ, 3, 3, "HTTP/1.1 200 OK\r\n\r\n");
+        let alerts = engine.evaluate_exchange(&exchange(request, response));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].scope, "request");
+        assert_eq!(alerts[0].uid, Some(0));
+        assert_eq!(alerts[0].offset, Some(46));
+    }
+
+    #[test]
+    fn matches_http2_fields() {
+        let rules = [
+            AlertRule {
+                method: vec!["delete".to_string()],
+                path: Some("^/users/".to_string()),
+                host: Some(r"^api\.".to_string()),
+                ..rule("delete")
+            },
+            AlertRule {
+                status: vec![500],
+                ..rule("error")
+            },
+        ];
+        let mut engine = RuleEngine::new(&rules, &Alerts::default());
+        let request = message(WRITE, 1, 1, "DELETE /users/1 HTTP/2\r\nhost: api.example.com\r\n\r\n");
+        let response = message(READ, 2, 2, "HTTP/2 500\r\ncontent-type: text/plain\r\n\r\n");
+        let alerts = engine.evaluate_exchange(&exchange(request, response));
+        let fired: Vec<(&str, &str)> = alerts.iter().map(|alert| (alert.rule.as_str(), alert.scope)).collect();
+        assert_eq!(fired, vec![("delete", "request"), ("error", "response")]);
+        assert_eq!(alerts[1].host.as_deref(), Some("api.example.com"));
+    }
+
+    #[test]
+    fn skips_rules_fired_on_events() {
+        let mut engine = engine();
+        let request = b"POST /form HTTP/1.1\r\nHost: api.example.com\r\n\r\nssn=123456789";
+        assert_eq!(engine.evaluate(&event(WRITE, 1, request)).len(), 1);
+        let request = message(WRITE, 1, 1, std::str::from_utf8(request).unwrap());
+        let response = message(READ, 2, 2, "HTTP/1.1 200 OK\r\n\r\n");
+        assert!(engine.evaluate_exchange(&exchange(request, response)).is_empty());
+    }
+}
@@ -1,26 +1,33 @@
-use sqlx::{Pool, Sqlite, SqlitePool};
-use  std::{fs,error::Error,path::Path,};
+use chrono::Local;
+use log::{info, warn};
+use sqlx::migrate::MigrateError;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection};
+use sqlx::{Connection, Pool, Sqlite, SqlitePool};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Mutex;
 
 use ssl_observer_common::ProbeSslData;
 
-use crate::decode::parse_http;
+use crate::config::{Retention, Rotate, CONFIG};
+use crate::grpc::grpc_call;
+use crate::http1::{HttpExchange, HttpHead};
+use crate::migrate::{check_version, pending, Migration, SCHEMA_VERSION_TABLE};
+use crate::rules::Alert;
+use crate::storage::{retention_cutoff, retention_sizes, SslDataRow, RETENTION_TABLES};
 use crate::utils::{convert_timestamp_to_date, sanitize_comm};
-use crate::config::CONFIG;
+use crate::websocket::WebSocketMessage;
 
-#[derive(sqlx::FromRow)]
-pub struct SslDataRow {
-    pub id: i64,
-    pub timestamp: String,
-    pub pid: i32,
-    pub comm: String,
-    pub buf: String,
-}
-
-pub async fn init_db() -> Result<SqlitePool, Box<dyn Error>> {
-    let db_path= &CONFIG.database.sqlite_path();
-    let database_name = CONFIG.database.sqlite_db_name();
-    let create_table_query: String =format!(r#"CREATE TABLE IF NOT EXISTS {} (
-        id INTEGER PRIMARY KEY AUTO_INCREMENT,
+// 按版本顺序执行的表结构变更，已发布的条目不要修改，只能追加
+pub(crate) const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create table",
+        statements: &[r#"CREATE TABLE IF NOT EXISTS {table} (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
         timestamp TEXT,
         delta_ns INTEGER,
         comm TEXT,
@@ -32,38 +39,250 @@ pub async fn init_db() -> Result<SqlitePool, Box<dyn Error>> {
         is_handshake INTEGER,
         len INTEGER,
         buf TEXT
-    )"#,database_name);
+    )"#],
+    },
+    Migration {
+        version: 2,
+        description: "add raw_buf",
+        statements: &["ALTER TABLE {table} ADD COLUMN raw_buf BLOB"],
+    },
+    Migration {
+        version: 3,
+        description: "add indexes",
+        statements: &[
+            "CREATE INDEX IF NOT EXISTS idx_{table}_timestamp ON {table} (timestamp)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_pid ON {table} (pid)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_comm ON {table} (comm)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_rw ON {table} (rw)",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "add conn_id",
+        statements: &["ALTER TABLE {table} ADD COLUMN conn_id BIGINT"],
+    },
+    Migration {
+        version: 5,
+        description: "create http exchange table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_http (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        latency_ns BIGINT,
+        request TEXT,
+        response TEXT,
+        request_raw BLOB,
+        response_raw BLOB
+    )"#,
+            "CREATE INDEX IF NOT EXISTS idx_{table}_http_timestamp ON {table}_http (timestamp)",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "add structured http columns",
+        statements: &[
+            "ALTER TABLE {table} ADD COLUMN method TEXT",
+            "ALTER TABLE {table} ADD COLUMN path TEXT",
+            "ALTER TABLE {table} ADD COLUMN host TEXT",
+            "ALTER TABLE {table} ADD COLUMN status INTEGER",
+            "ALTER TABLE {table} ADD COLUMN content_type TEXT",
+            "ALTER TABLE {table} ADD COLUMN content_length BIGINT",
+            "ALTER TABLE {table} ADD COLUMN user_agent TEXT",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_method ON {table} (method)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_status ON {table} (status)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_host ON {table} (host)",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "add http2 stream_id",
+        statements: &["ALTER TABLE {table}_http ADD COLUMN stream_id BIGINT"],
+    },
+    Migration {
+        version: 8,
+        description: "add grpc columns",
+        statements: &[
+            "ALTER TABLE {table}_http ADD COLUMN grpc_method TEXT",
+            "ALTER TABLE {table}_http ADD COLUMN grpc_status INTEGER",
+            "ALTER TABLE {table}_http ADD COLUMN grpc_message TEXT",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "create websocket message table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_ws (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        rw INTEGER,
+        opcode INTEGER,
+        compressed INTEGER,
+        close_code INTEGER,
+        payload TEXT,
+        payload_raw BLOB
+    )"#,
+            "CREATE INDEX IF NOT EXISTS idx_{table}_ws_timestamp ON {table}_ws (timestamp)",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "create connection protocol table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_conn (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        conn_id BIGINT,
+        protocol TEXT
+    )"#,
+            "CREATE INDEX IF NOT EXISTS idx_{table}_conn_conn_id ON {table}_conn (tgid, conn_id)",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "create alert table",
+        statements: &[
+            r#"CREATE TABLE IF NOT EXISTS {table}_alert (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp TEXT,
+        rule TEXT,
+        severity TEXT,
+        comm TEXT,
+        pid INTEGER,
+        tgid INTEGER,
+        uid INTEGER,
+        conn_id BIGINT,
+        rw INTEGER,
+        host TEXT,
+        excerpt TEXT
+    )"#,
+            "CREATE INDEX IF NOT EXISTS idx_{table}_alert_timestamp ON {table}_alert (timestamp)",
+            "CREATE INDEX IF NOT EXISTS idx_{table}_alert_rule ON {table}_alert (rule)",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "add alert source, scope, stream and offset columns",
+        statements: &[
+            "ALTER TABLE {table}_alert ADD COLUMN source TEXT",
+            "ALTER TABLE {table}_alert ADD COLUMN scope TEXT",
+            "ALTER TABLE {table}_alert ADD COLUMN stream_id BIGINT",
+            "ALTER TABLE {table}_alert ADD COLUMN match_offset BIGINT",
+        ],
+    },
+    // SQLite 的 INTEGER 已是 64 位，只占位与 MySQL 保持相同的版本号
+    Migration {
+        version: 13,
+        description: "widen delta_ns",
+        statements: &[],
+    },
+    // 保留策略删除数据后用 incremental_vacuum 回收空间，已有的库需要 VACUUM 一次才能切换模式
+    Migration {
+        version: 14,
+        description: "enable incremental vacuum",
+        statements: &["PRAGMA auto_vacuum = INCREMENTAL", "VACUUM"],
+    },
+];
 
+pub async fn init_db(db_path: &str) -> Result<SqlitePool, sqlx::Error> {
     // 检查文件是否存在，如果不存在则创建
     if !Path::new(db_path).exists() {
         fs::File::create(db_path)?;
     }
 
     let pool: Pool<Sqlite> = SqlitePool::connect(db_path).await?;
-    sqlx::query( &create_table_query).execute(&pool).await?;
+    migrate(&pool).await?;
     Ok(pool)
 }
 
-pub async fn query_data(pool: &SqlitePool) -> Result<Vec<SslDataRow>, Box<dyn Error>> {
-    let select_table_query = format!("SELECT id, timestamp, pid, comm, buf FROM {} WHERE is_handshake = 0",&CONFIG.database.mysql_db_name()
+// 检查 schema_version 并执行未应用的迁移
+async fn migrate(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let database_name = CONFIG.database.sqlite_db_name();
+    let create_version_query = format!(
+        "CREATE TABLE IF NOT EXISTS {} (version INTEGER PRIMARY KEY, description TEXT, applied_at TEXT)",
+        SCHEMA_VERSION_TABLE
+    );
+    sqlx::query(&create_version_query).execute(pool).await?;
+
+    let select_version_query = format!(
+        "SELECT COALESCE(MAX(version), 0) FROM {}",
+        SCHEMA_VERSION_TABLE
+    );
+    let current: i64 = sqlx::query_scalar(&select_version_query)
+        .fetch_one(pool)
+        .await?;
+    check_version(current, MIGRATIONS)?;
+
+    for migration in pending(current, MIGRATIONS) {
+        info!(
+            "Applying migration {} ({}) to {}",
+            migration.version, migration.description, database_name
+        );
+        let error = |e| sqlx::Error::Migrate(Box::new(MigrateError::ExecuteMigration(e, migration.version)));
+        // VACUUM 不能在事务中执行，这类迁移在同一个连接上逐条执行后再记录版本，中断后重新执行也无害
+        if migration.statements.contains(&"VACUUM") {
+            let mut conn = pool.acquire().await?;
+            for statement in migration.render(&database_name) {
+                sqlx::query(&statement).execute(&mut *conn).await.map_err(error)?;
+            }
+        }
+        // sqlite 支持事务性 DDL，一个版本要么全部生效要么全部回滚
+        let mut tx = pool.begin().await?;
+        if !migration.statements.contains(&"VACUUM") {
+            for statement in migration.render(&database_name) {
+                sqlx::query(&statement).execute(&mut *tx).await.map_err(error)?;
+            }
+        }
+        let insert_version_query = format!(
+            "INSERT INTO {} (version, description, applied_at) VALUES (?, ?, ?)",
+            SCHEMA_VERSION_TABLE
+        );
+        sqlx::query(&insert_version_query)
+            .bind(migration.version)
+            .bind(migration.description)
+            .bind(Local::now().format("%Y-%m-%d %H:%M:%S").to_string())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+pub async fn query_data(pool: &SqlitePool) -> Result<Vec<SslDataRow>, sqlx::Error> {
+    let select_table_query = format!("SELECT id, timestamp, delta_ns, pid, tgid, rw, conn_id, comm, buf, raw_buf, method, path, host, status, content_type, content_length, user_agent FROM {} WHERE is_handshake = 0",&CONFIG.database.sqlite_db_name()
 );
-    let rows: Vec<SslDataRow> = sqlx::query_as::<Sqlite, _>(
-        &select_table_query,
-    )
-    .fetch_all(pool)
-    .await?;
+    let rows: Vec<SslDataRow> = sqlx::query_as::<Sqlite, _>(&select_table_query)
+        .fetch_all(pool)
+        .await?;
 
     Ok(rows)
 }
 
-
-pub async fn insert_data(pool: &SqlitePool, data: &ProbeSslData) -> Result<(), Box<dyn Error>> {
+pub async fn insert_data(
+    pool: &SqlitePool,
+    data: &ProbeSslData,
+    content: &str,
+) -> Result<i64, sqlx::Error> {
     let date: String = convert_timestamp_to_date(data.timestamp_ns).await?;
     let comm_cleaned: String = sanitize_comm(&data.comm);
-    let content = parse_http(&data.buf[..data.len]).await;
+    let raw_buf: &[u8] = &data.buf[..data.len];
+    // buf 存解码器输出的文本，raw_buf 保留原始字节
+    // 起始行与常用头部单独成列，便于按方法、状态码等查询
+    let head = HttpHead::parse(raw_buf).unwrap_or_default();
 
-    let insert_table_query = format!("INSERT INTO {} (timestamp, delta_ns, comm, pid, tgid, uid, buf_filled, rw, is_handshake, len, buf) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
-    let _res = sqlx::query(&insert_table_query)
+    let insert_table_query = format!("INSERT INTO {} (timestamp, delta_ns, comm, pid, tgid, uid, buf_filled, rw, is_handshake, len, buf, raw_buf, conn_id, method, path, host, status, content_type, content_length, user_agent) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
+    let res = sqlx::query(&insert_table_query)
         .bind(date)
         .bind(data.delta_ns as i64)
         .bind(comm_cleaned)
@@ -75,8 +294,490 @@ pub async fn insert_data(pool: &SqlitePool, data: &ProbeSslData) -> Result<(), B
         .bind(data.is_handshake as i32)
         .bind(data.len as i32)
         .bind(content)
+        .bind(raw_buf)
+        .bind(data.conn_id as i64)
+        .bind(head.method)
+        .bind(head.path)
+        .bind(head.host)
+        .bind(head.status.map(i32::from))
+        .bind(head.content_type)
+        .bind(head.content_length.map(|len| len as i64))
+        .bind(head.user_agent)
+        .execute(pool)
+        .await?;
+
+    Ok(res.last_insert_rowid())
+}
+
+/// sqlite storage that optionally rolls over to a new file every hour or day
+pub struct SqliteStore {
+    rotate: Rotate,
+    // 当前周期标识与对应的连接池
+    current: Mutex<(String, SqlitePool)>,
+}
+
+impl SqliteStore {
+    pub async fn open() -> Result<Self, sqlx::Error> {
+        let rotate = CONFIG.database.sqlite_rotate();
+        let period = current_period(rotate);
+        let pool = init_db(&period_path(&period)).await?;
+        Ok(Self {
+            rotate,
+            current: Mutex::new((period, pool)),
+        })
+    }
+
+    /// return the pool for the current period, opening a new file when it has rolled over
+    pub async fn pool(&self) -> Result<SqlitePool, sqlx::Error> {
+        let mut current = self.current.lock().await;
+        let period = current_period(self.rotate);
+        if period != current.0 {
+            info!("Rotating sqlite database to {}", period_path(&period));
+            let pool = init_db(&period_path(&period)).await?;
+            let old = std::mem::replace(&mut *current, (period, pool));
+            old.1.close().await;
+        }
+        Ok(current.1.clone())
+    }
+
+    /// apply retention to the current file and remove rolled files that are expired or over the limits
+    pub async fn enforce_retention(&self, retention: &Retention) -> Result<u64, sqlx::Error> {
+        let pool = self.pool().await?;
+        let mut limits = *retention;
+        if self.rotate != Rotate::None {
+            let current = period_path(&self.current.lock().await.0);
+            if retention.max_age_secs > 0 {
+                remove_expired_files(&current, Duration::from_secs(retention.max_age_secs));
+            }
+            if retention.max_rows > 0 || retention.max_bytes > 0 {
+                // 保留下来的滚动文件占用的部分从当前文件的限额中扣除
+                let (rows, bytes) = remove_excess_files(&current, &pool, retention).await?;
+                if retention.max_rows > 0 {
+                    limits.max_rows = retention.max_rows.saturating_sub(rows).max(1);
+                }
+                if retention.max_bytes > 0 {
+                    limits.max_bytes = retention.max_bytes.saturating_sub(bytes).max(1);
+                }
+            }
+        }
+        enforce_retention(&pool, &limits).await
+    }
+}
+
+// 周期标识，不滚动时为空
+fn current_period(rotate: Rotate) -> String {
+    match rotate {
+        Rotate::None => String::new(),
+        Rotate::Hourly => Local::now().format("%Y%m%d%H").to_string(),
+        Rotate::Daily => Local::now().format("%Y%m%d").to_string(),
+    }
+}
+
+// ssl_data.db -> ssl_data-2024010112.db
+fn period_path(period: &str) -> String {
+    let db_path = CONFIG.database.sqlite_path();
+    if period.is_empty() {
+        return db_path;
+    }
+    let path = Path::new(&db_path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, period, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, period),
+    };
+    path.with_file_name(file).to_string_lossy().to_string()
+}
+
+// 当前文件以外的滚动文件，从新到旧排列
+fn rolled_files(current: &str) -> Vec<PathBuf> {
+    let db_path = CONFIG.database.sqlite_path();
+    let path = Path::new(&db_path);
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let prefix = format!(
+        "{}-",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Failed to list {}: {}", dir.display(), e);
+            return Vec::new();
+        }
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|rolled| {
+            rolled.extension() == path.extension()
+                && rolled
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+                && rolled != Path::new(current)
+        })
+        .collect();
+    // 周期标识按时间顺序编号，文件名倒序即从新到旧
+    files.sort_by(|a, b| b.cmp(a));
+    files
+}
+
+// 删除修改时间早于 max_age 的滚动文件，当前文件除外
+fn remove_expired_files(current: &str, max_age: Duration) {
+    for path in rolled_files(current) {
+        let expired = fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .map(|modified| {
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    > max_age
+            })
+            .unwrap_or(false);
+        if expired {
+            info!("Removing expired sqlite database {}", path.display());
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// 各表行数的最大值，只在按行数清理时统计
+async fn max_table_rows(conn: &mut SqliteConnection) -> Result<u64, sqlx::Error> {
+    let database_name = CONFIG.database.sqlite_db_name();
+    let mut rows = 0;
+    for (suffix, _) in RETENTION_TABLES {
+        let count_query = format!("SELECT COUNT(*) FROM {}{}", database_name, suffix);
+        let count: i64 = sqlx::query_scalar(&count_query).fetch_one(&mut *conn).await?;
+        rows = rows.max(count as u64);
+    }
+    Ok(rows)
+}
+
+// 只读打开滚动文件统计行数，无法打开时按 0 计
+async fn rolled_rows(path: &Path) -> u64 {
+    let options = SqliteConnectOptions::new().filename(path).read_only(true);
+    let rows = match SqliteConnection::connect_with(&options).await {
+        Ok(mut conn) => {
+            let rows = max_table_rows(&mut conn).await;
+            let _ = conn.close().await;
+            rows
+        }
+        Err(e) => Err(e),
+    };
+    rows.unwrap_or_else(|e| {
+        warn!("Failed to count rows in {}: {}", path.display(), e);
+        0
+    })
+}
+
+// 从当前文件开始由新到旧累计各文件的行数与大小，超出限额的滚动文件及更早的文件整个删除，
+// 返回保留的滚动文件的行数与字节数；滚动文件按磁盘上的文件大小计
+async fn remove_excess_files(
+    current: &str,
+    pool: &SqlitePool,
+    retention: &Retention,
+) -> Result<(u64, u64), sqlx::Error> {
+    let mut rows = 0;
+    if retention.max_rows > 0 {
+        rows = max_table_rows(&mut *pool.acquire().await?).await?;
+    }
+    let sum_query = format!(
+        "SELECT CAST(COALESCE(SUM(size), 0) AS INTEGER) FROM ({}) AS sizes",
+        retention_sizes(&CONFIG.database.sqlite_db_name())
+    );
+    let mut bytes = sqlx::query_scalar::<_, i64>(&sum_query).fetch_one(pool).await? as u64;
+
+    let (mut kept_rows, mut kept_bytes) = (0, 0);
+    let mut over = false;
+    for path in rolled_files(current) {
+        if !over {
+            let file_bytes = fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+            let file_rows = if retention.max_rows > 0 { rolled_rows(&path).await } else { 0 };
+            over = (retention.max_rows > 0 && rows + file_rows > retention.max_rows)
+                || (retention.max_bytes > 0 && bytes + file_bytes > retention.max_bytes);
+            if !over {
+                rows += file_rows;
+                bytes += file_bytes;
+                kept_rows += file_rows;
+                kept_bytes += file_bytes;
+                continue;
+            }
+        }
+        info!("Removing sqlite database {} over the retention limit", path.display());
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+    Ok((kept_rows, kept_bytes))
+}
+
+pub async fn insert_exchange(
+    pool: &SqlitePool,
+    exchange: &HttpExchange,
+    request: Option<&str>,
+    response: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(exchange.timestamp_ns()).await?;
+    let grpc = grpc_call(exchange);
+
+    let insert_table_query = format!("INSERT INTO {}_http (timestamp, comm, pid, tgid, conn_id, stream_id, latency_ns, request, response, request_raw, response_raw, grpc_method, grpc_status, grpc_message) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(&exchange.comm)
+        .bind(exchange.pid)
+        .bind(exchange.tgid)
+        .bind(exchange.conn_id as i64)
+        .bind(exchange.stream_id.map(i64::from))
+        .bind(exchange.latency_ns().map(|ns| ns as i64))
+        .bind(request)
+        .bind(response)
+        .bind(exchange.request.as_ref().map(|message| &message.raw))
+        .bind(exchange.response.as_ref().map(|message| &message.raw))
+        .bind(grpc.as_ref().map(|call| call.method.as_str()))
+        .bind(grpc.as_ref().and_then(|call| call.status))
+        .bind(grpc.as_ref().and_then(|call| call.message.as_deref()))
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_connection(
+    pool: &SqlitePool,
+    data: &ProbeSslData,
+    protocol: &str,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(data.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_conn (timestamp, comm, pid, tgid, conn_id, protocol) VALUES (?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(sanitize_comm(&data.comm))
+        .bind(data.pid)
+        .bind(data.tgid)
+        .bind(data.conn_id as i64)
+        .bind(protocol)
         .execute(pool)
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+pub async fn insert_alert(pool: &SqlitePool, alert: &Alert) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(alert.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_alert (timestamp, source, rule, severity, comm, pid, tgid, uid, conn_id, stream_id, rw, host, scope, match_offset, excerpt) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(alert.source)
+        .bind(&alert.rule)
+        .bind(&alert.severity)
+        .bind(&alert.comm)
+        .bind(alert.pid)
+        .bind(alert.tgid)
+        .bind(alert.uid)
+        .bind(alert.conn_id as i64)
+        .bind(alert.stream_id.map(i64::from))
+        .bind(alert.rw)
+        .bind(&alert.host)
+        .bind(alert.scope)
+        .bind(alert.offset.map(|offset| offset as i64))
+        .bind(&alert.excerpt)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_ws_message(
+    pool: &SqlitePool,
+    message: &WebSocketMessage,
+) -> Result<(), sqlx::Error> {
+    let date: String = convert_timestamp_to_date(message.timestamp_ns).await?;
+
+    let insert_table_query = format!("INSERT INTO {}_ws (timestamp, comm, pid, tgid, conn_id, rw, opcode, compressed, close_code, payload, payload_raw) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",CONFIG.database.sqlite_db_name());
+    sqlx::query(&insert_table_query)
+        .bind(date)
+        .bind(&message.comm)
+        .bind(message.pid)
+        .bind(message.tgid)
+        .bind(message.conn_id as i64)
+        .bind(message.rw)
+        .bind(message.opcode)
+        .bind(message.compressed as i32)
+        .bind(message.close_code)
+        .bind(message.text())
+        .bind(&message.payload)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+// 按保留策略删除最旧的数据，返回删除的行数
+pub async fn enforce_retention(
+    pool: &SqlitePool,
+    retention: &Retention,
+) -> Result<u64, sqlx::Error> {
+    let database_name = CONFIG.database.sqlite_db_name();
+    let mut deleted: u64 = 0;
+
+    for (suffix, _) in RETENTION_TABLES {
+        let table = format!("{}{}", database_name, suffix);
+        // 1. 按时间，timestamp 为 "%Y-%m-%d %H:%M:%S" 字符串，可直接比较
+        if let Some(cutoff) = retention_cutoff(retention) {
+            let delete_query = format!("DELETE FROM {} WHERE timestamp < ?", table);
+            deleted += sqlx::query(&delete_query)
+                .bind(cutoff)
+                .execute(pool)
+                .await?
+                .rows_affected();
+        }
+
+        // 2. 按行数，每张表分别保留最新的 max_rows 行
+        if retention.max_rows > 0 {
+            let select_id_query =
+                format!("SELECT id FROM {} ORDER BY id DESC LIMIT 1 OFFSET ?", table);
+            let oldest_kept: Option<i64> = sqlx::query_scalar(&select_id_query)
+                .bind(retention.max_rows as i64)
+                .fetch_optional(pool)
+                .await?;
+            if let Some(id) = oldest_kept {
+                let delete_query = format!("DELETE FROM {} WHERE id <= ?", table);
+                deleted += sqlx::query(&delete_query)
+                    .bind(id)
+                    .execute(pool)
+                    .await?
+                    .rows_affected();
+            }
+        }
+    }
+
+    // 3. 按字节数，所有表合计；只统计一次超出的部分，再按时间确定删除到哪里
+    if retention.max_bytes > 0 {
+        let sizes = retention_sizes(&database_name);
+        let sum_query = format!(
+            "SELECT CAST(COALESCE(SUM(size), 0) AS INTEGER) FROM ({}) AS sizes",
+            sizes
+        );
+        let total: i64 = sqlx::query_scalar(&sum_query).fetch_one(pool).await?;
+        if total as u64 > retention.max_bytes {
+            // 按时间累计各秒的数据量，累计达到超出部分的那一秒及更早的数据全部删除
+            let cutoff_query = format!(
+                "SELECT timestamp FROM (SELECT timestamp, SUM(SUM(size)) OVER (ORDER BY timestamp) AS freed FROM ({}) AS sizes GROUP BY timestamp) AS running WHERE freed >= ? ORDER BY timestamp LIMIT 1",
+                sizes
+            );
+            let cutoff: Option<String> = sqlx::query_scalar(&cutoff_query)
+                .bind(total - retention.max_bytes as i64)
+                .fetch_optional(pool)
+                .await?;
+            if let Some(cutoff) = cutoff {
+                for (suffix, _) in RETENTION_TABLES {
+                    let delete_query = format!(
+                        "DELETE FROM {}{} WHERE timestamp <= ?",
+                        database_name, suffix
+                    );
+                    deleted += sqlx::query(&delete_query)
+                        .bind(&cutoff)
+                        .execute(pool)
+                        .await?
+                        .rows_affected();
+                }
+            }
+        }
+    }
+
+    // 删除只把页标记为空闲，归还给文件系统才能真正限制文件大小
+    if deleted > 0 {
+        sqlx::query("PRAGMA incremental_vacuum")
+            .execute(pool)
+            .await?;
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{enforce_retention, init_db};
+    use crate::config::{Retention, CONFIG};
+    use sqlx::{Connection, SqliteConnection};
+
+    #[tokio::test]
+    async fn retention_prunes_every_table() {
+        let path = std::env::temp_dir().join(format!("ssl-observer-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pool = init_db(path.to_str().unwrap()).await.unwrap();
+        // 迁移期间打开的池连接会缓存旧的 auto_vacuum，用新连接读取
+        let mut conn = SqliteConnection::connect(path.to_str().unwrap()).await.unwrap();
+        let auto_vacuum: i64 = sqlx::query_scalar("PRAGMA auto_vacuum")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        conn.close().await.unwrap();
+        assert_eq!(auto_vacuum, 2);
+
+        let database_name = CONFIG.database.sqlite_db_name();
+        for second in 0..5 {
+            // 消息表的数据比主表早一小时
+            let timestamp = format!("2024-05-01 10:00:0{}", second);
+            let insert_query = format!(
+                "INSERT INTO {} (timestamp, buf) VALUES (?, ?)",
+                database_name
+            );
+            sqlx::query(&insert_query)
+                .bind(&timestamp)
+                .bind("x")
+                .execute(&pool)
+                .await
+                .unwrap();
+            let insert_query = format!(
+                "INSERT INTO {}_ws (timestamp, payload) VALUES (?, ?)",
+                database_name
+            );
+            sqlx::query(&insert_query)
+                .bind(timestamp.replace(" 10:", " 09:"))
+                .bind("x".repeat(100))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let count = |suffix: &str| {
+            let count_query = format!("SELECT COUNT(*) FROM {}{}", database_name, suffix);
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(&count_query)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let retention = Retention {
+            max_age_secs: 0,
+            max_rows: 3,
+            max_bytes: 0,
+            interval_secs: 60,
+        };
+        assert_eq!(enforce_retention(&pool, &retention).await.unwrap(), 4);
+        assert_eq!(count("").await, 3);
+        assert_eq!(count("_ws").await, 3);
+
+        // 合计超出上限，先删除最旧数据所在的消息表，不足一批时整表删除，主表保持不变
+        let retention = Retention {
+            max_age_secs: 0,
+            max_rows: 0,
+            max_bytes: 100,
+            interval_secs: 60,
+        };
+        enforce_retention(&pool, &retention).await.unwrap();
+        assert_eq!(count("").await, 3);
+        assert_eq!(count("_ws").await, 0);
+
+        pool.close().await;
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+use chrono::{Duration, Local};
+use log::{info, warn};
+use sqlx::MySqlPool;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use ssl_observer_common::ProbeSslData;
+
+use crate::config::{Retention, CONFIG};
+use crate::http1::{HttpExchange, HttpHead};
+use crate::metrics::METRICS;
+use crate::mysql_db;
+use crate::rules::Alert;
+use crate::sqlite_db::{self, SqliteStore};
+use crate::utils::{convert_timestamp_to_date, sanitize_comm};
+use crate::websocket::WebSocketMessage;
+
+/// 保留策略清理的表（表名后缀）及单行数据量的 SQL 表达式
+pub const RETENTION_TABLES: &[(&str, &str)] = &[
+    ("", "COALESCE(LENGTH(buf), 0) + COALESCE(LENGTH(raw_buf), 0)"),
+    (
+        "_http",
+        "COALESCE(LENGTH(request), 0) + COALESCE(LENGTH(response), 0) + COALESCE(LENGTH(request_raw), 0) + COALESCE(LENGTH(response_raw), 0)",
+    ),
+    ("_ws", "COALESCE(LENGTH(payload), 0) + COALESCE(LENGTH(payload_raw), 0)"),
+    ("_conn", "COALESCE(LENGTH(protocol), 0)"),
+    ("_alert", "COALESCE(LENGTH(excerpt), 0)"),
+];
+
+/// union of (timestamp, size) over every retention table, used to apply the byte budget
+pub fn retention_sizes(database_name: &str) -> String {
+    RETENTION_TABLES
+        .iter()
+        .map(|(suffix, size)| {
+            format!(
+                "SELECT timestamp, {} AS size FROM {}{} WHERE timestamp IS NOT NULL",
+                size, database_name, suffix
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" UNION ALL ")
+}
+
+#[derive(sqlx::FromRow)]
+pub struct SslDataRow {
+    pub id: i64,
+    pub timestamp: String,
+    // SSL 调用耗时
+    pub delta_ns: Option<i64>,
+    pub pid: i32,
+    pub tgid: i32,
+    pub rw: i32,
+    pub conn_id: Option<i64>,
+    pub comm: String,
+    pub buf: String,
+    // 迁移前的旧数据没有原始字节
+    pub raw_buf: Option<Vec<u8>>,
+    // 非 HTTP 起始行或迁移前的数据为空
+    pub method: Option<String>,
+    pub path: Option<String>,
+    pub host: Option<String>,
+    pub status: Option<i32>,
+    pub content_type: Option<String>,
+    pub content_length: Option<i64>,
+    pub user_agent: Option<String>,
+}
+
+impl SslDataRow {
+    /// build the row stored for an event, for viewers that show events as they arrive
+    pub async fn from_probe(id: i64, data: &ProbeSslData, content: &str) -> Result<Self, sqlx::Error> {
+        let raw_buf = data.buf[..data.len].to_vec();
+        let head = HttpHead::parse(&raw_buf).unwrap_or_default();
+        Ok(Self {
+            id,
+            timestamp: convert_timestamp_to_date(data.timestamp_ns).await?,
+            delta_ns: Some(data.delta_ns as i64),
+            pid: data.pid as i32,
+            tgid: data.tgid as i32,
+            rw: data.rw as i32,
+            conn_id: Some(data.conn_id as i64),
+            comm: sanitize_comm(&data.comm),
+            buf: content.to_string(),
+            raw_buf: Some(raw_buf),
+            method: head.method,
+            path: head.path,
+            host: head.host,
+            status: head.status.map(i32::from),
+            content_type: head.content_type,
+            content_length: head.content_length.map(|len| len as i64),
+            user_agent: head.user_agent,
+        })
+    }
+
+    /// short description of the HTTP start line, like "GET x/index.html" or "200"
+    pub fn http_summary(&self) -> Option<String> {
+        if let Some(status) = self.status {
+            return Some(status.to_string());
+        }
+        let method = self.method.as_deref()?;
+        Some(format!(
+            "{} {}{}",
+            method,
+            self.host.as_deref().unwrap_or(""),
+            self.path.as_deref().unwrap_or("")
+        ))
+    }
+}
+
+/// storage backend selected by `database.db_type`
+#[derive(Clone)]
+pub enum Storage {
+    Mysql(MySqlPool),
+    Sqlite(Arc<SqliteStore>),
+}
+
+impl Storage {
+    pub async fn init() -> Result<Self, sqlx::Error> {
+        match CONFIG.database.db_type.as_str() {
+            "mysql" => Ok(Storage::Mysql(mysql_db::init_db().await?)),
+            "sqlite" => Ok(Storage::Sqlite(Arc::new(SqliteStore::open().await?))),
+            db_type => Err(sqlx::Error::Configuration(
+                format!("unsupported db_type \"{}\"", db_type).into(),
+            )),
+        }
+    }
+
+    /// store one event and return the id of its row
+    pub async fn insert_data(&self, data: &ProbeSslData, content: &str) -> Result<i64, sqlx::Error> {
+        timed("data", async {
+            match self {
+                Storage::Mysql(pool) => mysql_db::insert_data(pool, data, content).await,
+                Storage::Sqlite(store) => sqlite_db::insert_data(&store.pool().await?, data, content).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn insert_connection(&self, data: &ProbeSslData, protocol: &str) -> Result<(), sqlx::Error> {
+        timed("connection", async {
+            match self {
+                Storage::Mysql(pool) => mysql_db::insert_connection(pool, data, protocol).await,
+                Storage::Sqlite(store) => sqlite_db::insert_connection(&store.pool().await?, data, protocol).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn insert_exchange(&self, exchange: &HttpExchange, request: Option<&str>, response: Option<&str>) -> Result<(), sqlx::Error> {
+        timed("exchange", async {
+            match self {
+                Storage::Mysql(pool) => mysql_db::insert_exchange(pool, exchange, request, response).await,
+                Storage::Sqlite(store) => sqlite_db::insert_exchange(&store.pool().await?, exchange, request, response).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn insert_alert(&self, alert: &Alert) -> Result<(), sqlx::Error> {
+        timed("alert", async {
+            match self {
+                Storage::Mysql(pool) => mysql_db::insert_alert(pool, alert).await,
+                Storage::Sqlite(store) => sqlite_db::insert_alert(&store.pool().await?, alert).await,
+            }
+        })
+        .await
+    }
+
+    pub async fn insert_ws_message(&self, message: &WebSocketMessage) -> Result<(), sqlx::Error> {
+        timed("websocket", async {
+            match self {
+                Storage::Mysql(pool) => mysql_db::insert_ws_message(pool, message).await,
+                Storage::Sqlite(store) => sqlite_db::insert_ws_message(&store.pool().await?, message).await,
+            }
+        })
+        .await
+    }
+
+    /// size and idle connections of the current pool
+    pub async fn pool_stats(&self) -> Option<(u32, usize)> {
+        match self {
+            Storage::Mysql(pool) => Some((pool.size(), pool.num_idle())),
+            Storage::Sqlite(store) => store.pool().await.ok().map(|pool| (pool.size(), pool.num_idle())),
+        }
+    }
+
+    pub async fn query_data(&self) -> Result<Vec<SslDataRow>, sqlx::Error> {
+        match self {
+            Storage::Mysql(pool) => mysql_db::query_data(pool).await,
+            Storage::Sqlite(store) => sqlite_db::query_data(&store.pool().await?).await,
+        }
+    }
+
+    async fn enforce_retention(&self, retention: &Retention) -> Result<u64, sqlx::Error> {
+        match self {
+            Storage::Mysql(pool) => mysql_db::enforce_retention(pool, retention).await,
+            Storage::Sqlite(store) => store.enforce_retention(retention).await,
+        }
+    }
+}
+
+// 记录一次写入的耗时与是否失败
+async fn timed<T>(table: &'static str, insert: impl Future<Output = Result<T, sqlx::Error>>) -> Result<T, sqlx::Error> {
+    let start = Instant::now();
+    let result = insert.await;
+    METRICS.db_insert(table, start.elapsed(), result.is_ok());
+    result
+}
+
+/// return the oldest timestamp to keep, formatted like the `timestamp` column
+pub fn retention_cutoff(retention: &Retention) -> Option<String> {
+    if retention.max_age_secs == 0 {
+        return None;
+    }
+    let cutoff = Local::now() - Duration::seconds(retention.max_age_secs as i64);
+    Some(cutoff.format("%Y-%m-%d %H:%M:%S").to_string())
+}
+
+// 后台定期执行保留策略，未配置任何限制时不启动
+pub fn spawn_retention(storage: Storage) {
+    let retention = &CONFIG.retention;
+    if !retention.is_enabled() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(retention.interval_secs.max(1)));
+        loop {
+            interval.tick().await;
+            match storage.enforce_retention(retention).await {
+                Ok(0) => {}
+                Ok(deleted) => info!("Retention removed {} rows", deleted),
+                Err(e) => warn!("Retention failed: {}", e),
+            }
+        }
+    });
+}
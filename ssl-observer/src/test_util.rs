@@ -0,0 +1,29 @@
+use ssl_observer_common::{ProbeSslData, MAX_BUF_SIZE, TASK_COMM_LEN};
+
+/// captured event of pid 7 on connection 1 carrying `payload`, other fields
+/// can be overridden with struct update syntax
+pub fn event(rw: u8, ts: u64, payload: &[u8]) -> ProbeSslData {
+    let mut data = ProbeSslData {
+        timestamp_ns: ts,
+        delta_ns: 0,
+        conn_id: 1,
+        pid: 7,
+        tgid: 7,
+        uid: 0,
+        buf_filled: 1,
+        rw,
+        is_handshake: false,
+        comm: [0; TASK_COMM_LEN],
+        buf: [0; MAX_BUF_SIZE],
+        len: payload.len(),
+    };
+    data.buf[..payload.len()].copy_from_slice(payload);
+    data
+}
+
+/// `name` as the nul-padded command of an event
+pub fn comm(name: &str) -> [u8; TASK_COMM_LEN] {
+    let mut comm = [0; TASK_COMM_LEN];
+    comm[..name.len()].copy_from_slice(name.as_bytes());
+    comm
+}
@@ -0,0 +1,665 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::Frame;
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Write};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use ssl_observer_common::WRITE;
+
+// 保留的最近事件数，更早的事件从列表中移除
+const MAX_EVENTS: usize = 10_000;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+const PREVIEW_LEN: usize = 80;
+
+/// one captured event as shown in the terminal UI, already decoded and redacted
+pub struct TuiEvent {
+    pub timestamp_ns: u64,
+    pub pid: u32,
+    pub tgid: u32,
+    pub conn_id: u64,
+    pub comm: String,
+    pub rw: u8,
+    pub content: String,
+    pub raw: Vec<u8>,
+}
+
+enum Message {
+    Event(TuiEvent),
+    Log(String),
+    Stop,
+}
+
+/// log records are shown in the footer instead of being written over the screen
+pub struct TuiLog(mpsc::Sender<Message>);
+
+impl Write for TuiLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf).trim_end().to_string();
+        if !line.is_empty() {
+            let _ = self.0.send(Message::Log(line));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// live terminal UI for `--tui`, drawn by its own thread so it works over SSH
+pub struct Tui {
+    sender: mpsc::Sender<Message>,
+    receiver: Option<mpsc::Receiver<Message>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Tui {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            sender,
+            receiver: Some(receiver),
+            thread: None,
+        }
+    }
+
+    /// writer for env_logger, records sent before `start` are shown once the UI is up
+    pub fn logger(&self) -> TuiLog {
+        TuiLog(self.sender.clone())
+    }
+
+    /// take over the terminal, `quit` fires when the user leaves the UI
+    pub fn start(&mut self, quit: Arc<Notify>) -> io::Result<()> {
+        let Some(receiver) = self.receiver.take() else {
+            return Ok(());
+        };
+        // 安装 panic hook，崩溃时恢复终端
+        let mut terminal = ratatui::try_init()?;
+        self.thread = Some(thread::spawn(move || {
+            let mut app = App::default();
+            let result = app.run(&mut terminal, &receiver);
+            ratatui::restore();
+            quit.notify_one();
+            result
+        }));
+        Ok(())
+    }
+
+    pub fn send(&self, event: TuiEvent) {
+        let _ = self.sender.send(Message::Event(event));
+    }
+
+    /// restore the terminal and wait for the UI thread
+    pub fn stop(&mut self) {
+        let _ = self.sender.send(Message::Stop);
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(Err(e)) => eprintln!("Terminal UI failed: {}", e),
+                Err(_) => eprintln!("Terminal UI panicked"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+/// hexdump -C style rendering of a payload
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let (left, right) = hex.split_at(hex.len().min(8));
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        out.push_str(&format!(
+            "{:08x}  {:<23}  {:<23}  |{}|\n",
+            line * 16,
+            left.join(" "),
+            right.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+enum Focus {
+    #[default]
+    Connections,
+    Events,
+    Detail,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Prompt {
+    Process,
+    Search,
+}
+
+struct Connection {
+    tgid: u32,
+    conn_id: u64,
+    pid: u32,
+    comm: String,
+    events: u64,
+    bytes: u64,
+    // 仍在列表中的事件数，为 0 时移除连接
+    retained: usize,
+}
+
+#[derive(Default)]
+struct App {
+    events: VecDeque<TuiEvent>,
+    // events 中第一条事件的序号，淘汰旧事件后序号不变
+    first_seq: u64,
+    // 当前过滤条件下可见事件的序号，过滤条件变化时才重建
+    visible: VecDeque<u64>,
+    // 暂停期间到达的事件，恢复后再加入列表
+    pending: VecDeque<TuiEvent>,
+    connections: Vec<Connection>,
+    connection_index: HashMap<(u32, u64), usize>,
+    focus: Focus,
+    // 0 表示全部连接
+    selected_connection: usize,
+    // None 时跟随最新的事件
+    selected_event: Option<usize>,
+    detail_scroll: u16,
+    process_filter: String,
+    search: String,
+    prompt: Option<(Prompt, String)>,
+    hex: bool,
+    paused: bool,
+    last_log: Option<String>,
+    quit: bool,
+}
+
+impl App {
+    fn run(
+        &mut self,
+        terminal: &mut ratatui::DefaultTerminal,
+        receiver: &mpsc::Receiver<Message>,
+    ) -> io::Result<()> {
+        while !self.quit {
+            loop {
+                match receiver.try_recv() {
+                    Ok(Message::Event(event)) => self.push(event),
+                    Ok(Message::Log(line)) => self.last_log = Some(line),
+                    Ok(Message::Stop) | Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                }
+            }
+            terminal.draw(|frame| self.render(frame))?;
+            if event::poll(POLL_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_key(key);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, event: TuiEvent) {
+        if self.paused {
+            // 恢复后超出 MAX_EVENTS 的部分也会被淘汰，只保留最近的事件
+            self.pending.push_back(event);
+            if self.pending.len() > MAX_EVENTS {
+                self.pending.pop_front();
+            }
+            return;
+        }
+        let key = (event.tgid, event.conn_id);
+        let index = *self.connection_index.entry(key).or_insert_with(|| {
+            self.connections.push(Connection {
+                tgid: event.tgid,
+                conn_id: event.conn_id,
+                pid: event.pid,
+                comm: event.comm.clone(),
+                events: 0,
+                bytes: 0,
+                retained: 0,
+            });
+            self.connections.len() - 1
+        });
+        let connection = &mut self.connections[index];
+        connection.events += 1;
+        connection.bytes += event.raw.len() as u64;
+        connection.retained += 1;
+        if self.matches(self.selected_key(), &self.search.to_lowercase(), &event) {
+            self.visible.push_back(self.first_seq + self.events.len() as u64);
+        }
+        self.events.push_back(event);
+        if self.events.len() > MAX_EVENTS {
+            self.evict();
+        }
+    }
+
+    fn evict(&mut self) {
+        let Some(event) = self.events.pop_front() else {
+            return;
+        };
+        if self.visible.front() == Some(&self.first_seq) {
+            self.visible.pop_front();
+            // 选中的事件随之前移
+            self.selected_event = self.selected_event.map(|index| index.saturating_sub(1));
+        }
+        self.first_seq += 1;
+        let index = self.connection_index[&(event.tgid, event.conn_id)];
+        self.connections[index].retained -= 1;
+        if self.connections[index].retained == 0 {
+            self.remove_connection(index);
+        }
+    }
+
+    // 连接的事件全部淘汰后从列表中移除，选中的连接按 key 保持不变
+    fn remove_connection(&mut self, index: usize) {
+        let selected = self.selected_key();
+        let removed = self.connections.remove(index);
+        self.connection_index.remove(&(removed.tgid, removed.conn_id));
+        for (index, conn) in self.connections.iter().enumerate().skip(index) {
+            self.connection_index.insert((conn.tgid, conn.conn_id), index);
+        }
+        self.selected_connection = selected
+            .and_then(|key| {
+                self.visible_connections()
+                    .iter()
+                    .position(|conn| (conn.tgid, conn.conn_id) == key)
+            })
+            .map_or(0, |position| position + 1);
+        if selected == Some((removed.tgid, removed.conn_id)) {
+            self.refilter();
+        }
+    }
+
+    fn process_matches(&self, comm: &str, pid: u32, tgid: u32) -> bool {
+        let filter = self.process_filter.trim();
+        if filter.is_empty() {
+            return true;
+        }
+        match filter.parse::<u32>() {
+            Ok(id) => id == pid || id == tgid,
+            Err(_) => comm.contains(filter),
+        }
+    }
+
+    fn visible_connections(&self) -> Vec<&Connection> {
+        self.connections
+            .iter()
+            .filter(|conn| self.process_matches(&conn.comm, conn.pid, conn.tgid))
+            .collect()
+    }
+
+    fn selected_key(&self) -> Option<(u32, u64)> {
+        let index = self.selected_connection.checked_sub(1)?;
+        self.visible_connections()
+            .get(index)
+            .map(|conn| (conn.tgid, conn.conn_id))
+    }
+
+    // search 已转为小写
+    fn matches(&self, connection: Option<(u32, u64)>, search: &str, event: &TuiEvent) -> bool {
+        connection.is_none_or(|key| key == (event.tgid, event.conn_id))
+            && self.process_matches(&event.comm, event.pid, event.tgid)
+            && (search.is_empty() || event.content.to_lowercase().contains(search))
+    }
+
+    // 连接、进程过滤或搜索条件变化后重建可见事件
+    fn refilter(&mut self) {
+        let connection = self.selected_key();
+        let search = self.search.to_lowercase();
+        let visible = self
+            .events
+            .iter()
+            .zip(self.first_seq..)
+            .filter(|(event, _)| self.matches(connection, &search, event))
+            .map(|(_, seq)| seq)
+            .collect();
+        self.visible = visible;
+    }
+
+    // 当前连接、进程过滤与搜索条件下的事件下标
+    fn visible_events(&self) -> Vec<usize> {
+        self.visible
+            .iter()
+            .map(|seq| (seq - self.first_seq) as usize)
+            .collect()
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) {
+        if let Some((prompt, mut input)) = self.prompt.take() {
+            match key.code {
+                KeyCode::Enter => {
+                    match prompt {
+                        Prompt::Process => {
+                            self.process_filter = input;
+                            self.selected_connection = 0;
+                        }
+                        Prompt::Search => self.search = input,
+                    }
+                    self.refilter();
+                }
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.prompt = Some((prompt, input));
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.prompt = Some((prompt, input));
+                }
+                _ => self.prompt = Some((prompt, input)),
+            }
+            self.selected_event = None;
+            return;
+        }
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Connections => Focus::Events,
+                    Focus::Events => Focus::Detail,
+                    Focus::Detail => Focus::Connections,
+                }
+            }
+            KeyCode::Char('p') => self.prompt = Some((Prompt::Process, self.process_filter.clone())),
+            KeyCode::Char('/') => self.prompt = Some((Prompt::Search, self.search.clone())),
+            KeyCode::Char('x') => self.hex = !self.hex,
+            KeyCode::Char(' ') => {
+                self.paused = !self.paused;
+                if !self.paused {
+                    for event in std::mem::take(&mut self.pending) {
+                        self.push(event);
+                    }
+                }
+            }
+            KeyCode::Esc => {
+                self.process_filter.clear();
+                self.search.clear();
+                self.selected_connection = 0;
+                self.selected_event = None;
+                self.refilter();
+            }
+            KeyCode::Up | KeyCode::Char('k') => self.scroll(-1),
+            KeyCode::Down | KeyCode::Char('j') => self.scroll(1),
+            KeyCode::PageUp => self.scroll(-20),
+            KeyCode::PageDown => self.scroll(20),
+            KeyCode::Home | KeyCode::Char('g') => self.scroll(i64::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => match self.focus {
+                // 回到末尾后继续跟随新事件
+                Focus::Events => self.selected_event = None,
+                _ => self.scroll(i64::MAX / 2),
+            },
+            _ => {}
+        }
+    }
+
+    fn scroll(&mut self, delta: i64) {
+        let step = |index: usize, len: usize| (index as i64 + delta).clamp(0, len.saturating_sub(1) as i64) as usize;
+        match self.focus {
+            Focus::Connections => {
+                self.selected_connection = step(self.selected_connection, self.visible_connections().len() + 1);
+                self.selected_event = None;
+                self.detail_scroll = 0;
+                self.refilter();
+            }
+            Focus::Events => {
+                let len = self.visible_events().len();
+                let current = self.selected_event.unwrap_or(len.saturating_sub(1));
+                self.selected_event = Some(step(current, len));
+                self.detail_scroll = 0;
+            }
+            Focus::Detail => {
+                self.detail_scroll = (self.detail_scroll as i64 + delta).clamp(0, u16::MAX as i64) as u16;
+            }
+        }
+    }
+
+    fn block(&self, title: String, focus: Focus) -> Block<'static> {
+        let style = if self.focus == focus {
+            Style::default().fg(Color::Cyan)
+        } else {
+            Style::default()
+        };
+        Block::default().borders(Borders::ALL).border_style(style).title(title)
+    }
+
+    fn render(&self, frame: &mut Frame) {
+        let [header, body, footer] =
+            Layout::vertical([Constraint::Length(1), Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] = Layout::horizontal([Constraint::Percentage(30), Constraint::Percentage(70)]).areas(body);
+        let [top, bottom] = Layout::vertical([Constraint::Percentage(45), Constraint::Percentage(55)]).areas(right);
+
+        let mut status = vec![Span::styled(
+            format!(" {} events, {} connections ", self.events.len(), self.connections.len()),
+            Style::default().add_modifier(Modifier::BOLD),
+        )];
+        if self.paused {
+            status.push(Span::styled(
+                format!(" PAUSED ({} pending) ", self.pending.len()),
+                Style::default().fg(Color::Black).bg(Color::Yellow),
+            ));
+        }
+        if !self.process_filter.is_empty() {
+            status.push(Span::raw(format!(" process: {} ", self.process_filter)));
+        }
+        if !self.search.is_empty() {
+            status.push(Span::raw(format!(" search: {} ", self.search)));
+        }
+        status.push(Span::raw(if self.hex { " [hex]" } else { " [text]" }));
+        frame.render_widget(Paragraph::new(Line::from(status)), header);
+
+        self.render_connections(frame, left);
+        let events = self.visible_events();
+        let selected = self.selected_event.unwrap_or(events.len().saturating_sub(1));
+        self.render_events(frame, top, &events, selected);
+        self.render_detail(frame, bottom, events.get(selected).map(|&index| &self.events[index]));
+
+        let footer_text = match &self.prompt {
+            Some((Prompt::Process, input)) => format!("process (comm or pid): {}_", input),
+            Some((Prompt::Search, input)) => format!("search: {}_", input),
+            None => match &self.last_log {
+                Some(line) => line.clone(),
+                None => "q quit  tab focus  ↑↓ move  p process  / search  x hex  space pause  esc clear".to_string(),
+            },
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    fn render_connections(&self, frame: &mut Frame, area: Rect) {
+        let connections = self.visible_connections();
+        let mut items = vec![ListItem::new(format!("All connections ({})", connections.len()))];
+        items.extend(connections.iter().map(|conn| {
+            ListItem::new(format!(
+                "{:<15} {:>7} {:x}  {} ev {} B",
+                conn.comm, conn.tgid, conn.conn_id, conn.events, conn.bytes
+            ))
+        }));
+        let list = List::new(items)
+            .block(self.block(" Connections ".to_string(), Focus::Connections))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected(Some(self.selected_connection));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn render_events(&self, frame: &mut Frame, area: Rect, events: &[usize], selected: usize) {
+        let items: Vec<ListItem> = events
+            .iter()
+            .map(|&index| {
+                let event = &self.events[index];
+                let preview: String = event
+                    .content
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .take(PREVIEW_LEN)
+                    .collect();
+                let (arrow, color) = if event.rw == WRITE { ("W", Color::Green) } else { ("R", Color::Blue) };
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{:>14.6} ", event.timestamp_ns as f64 / 1e9)),
+                    Span::styled(arrow, Style::default().fg(color)),
+                    Span::raw(format!(" {:<15} {:>7} {:>6}  {}", event.comm, event.pid, event.raw.len(), preview)),
+                ]))
+            })
+            .collect();
+        let title = format!(" Events ({}) ", events.len());
+        let list = List::new(items)
+            .block(self.block(title, Focus::Events))
+            .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = ListState::default().with_selected((!events.is_empty()).then_some(selected));
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn render_detail(&self, frame: &mut Frame, area: Rect, event: Option<&TuiEvent>) {
+        let (title, text) = match event {
+            Some(event) => (
+                format!(
+                    " {} pid {} conn {:x} {} bytes ",
+                    event.comm,
+                    event.pid,
+                    event.conn_id,
+                    event.raw.len()
+                ),
+                if self.hex { hex_dump(&event.raw) } else { event.content.replace("\r\n", "\n") },
+            ),
+            None => (" Detail ".to_string(), String::new()),
+        };
+        let paragraph = Paragraph::new(text)
+            .block(self.block(title, Focus::Detail))
+            .wrap(Wrap { trim: false })
+            .scroll((self.detail_scroll, 0));
+        frame.render_widget(paragraph, area);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hex_dump, App, Focus, TuiEvent, MAX_EVENTS};
+    use ratatui::backend::TestBackend;
+    use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::Terminal;
+    use ssl_observer_common::{READ, WRITE};
+
+    fn event(comm: &str, pid: u32, conn_id: u64, rw: u8, content: &str) -> TuiEvent {
+        TuiEvent {
+            timestamp_ns: 1_500_000_000,
+            pid,
+            tgid: pid,
+            conn_id,
+            comm: comm.to_string(),
+            rw,
+            content: content.to_string(),
+            raw: content.as_bytes().to_vec(),
+        }
+    }
+
+    fn press(app: &mut App, code: KeyCode) {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE));
+    }
+
+    fn screen(app: &App) -> String {
+        let mut terminal = Terminal::new(TestBackend::new(120, 30)).unwrap();
+        terminal.draw(|frame| app.render(frame)).unwrap();
+        terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect()
+    }
+
+    fn sample() -> App {
+        let mut app = App::default();
+        app.push(event("curl", 7, 1, WRITE, "GET /a HTTP/1.1\r\n\r\n"));
+        app.push(event("curl", 7, 1, READ, "HTTP/1.1 200 OK\r\n\r\n"));
+        app.push(event("python3", 9, 2, WRITE, "POST /b HTTP/1.1\r\n\r\n"));
+        app
+    }
+
+    #[test]
+    fn renders_hex_dump() {
+        assert_eq!(
+            hex_dump(b"GET / HTTP/1.1\r\n\x00"),
+            "00000000  47 45 54 20 2f 20 48 54  54 50 2f 31 2e 31 0d 0a  |GET / HTTP/1.1..|\n\
+             00000010  00                                                |.|\n"
+        );
+    }
+
+    #[test]
+    fn filters_by_process_and_search() {
+        let mut app = sample();
+        assert_eq!(app.visible_events().len(), 3);
+        press(&mut app, KeyCode::Char('p'));
+        press(&mut app, KeyCode::Char('9'));
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.visible_connections().len(), 1);
+        assert_eq!(app.visible_events(), vec![2]);
+        press(&mut app, KeyCode::Esc);
+        press(&mut app, KeyCode::Char('/'));
+        for c in "200 ok".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.visible_events(), vec![1]);
+        // 新事件按当前条件加入
+        app.push(event("curl", 7, 3, READ, "HTTP/1.1 200 OK\r\n\r\n"));
+        assert_eq!(app.visible_events(), vec![1, 3]);
+    }
+
+    #[test]
+    fn pause_holds_events() {
+        let mut app = sample();
+        // 选中第一条连接，暂停期间的事件在恢复后加入
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.visible_events(), vec![0, 1]);
+        press(&mut app, KeyCode::Char(' '));
+        app.push(event("curl", 7, 1, WRITE, "GET /c HTTP/1.1\r\n\r\n"));
+        assert_eq!(app.visible_events().len(), 2);
+        press(&mut app, KeyCode::Char(' '));
+        assert_eq!(app.visible_events().len(), 3);
+    }
+
+    #[test]
+    fn pending_is_capped() {
+        let mut app = App::default();
+        press(&mut app, KeyCode::Char(' '));
+        for _ in 0..MAX_EVENTS + 10 {
+            app.push(event("curl", 7, 1, WRITE, "x"));
+        }
+        assert_eq!(app.pending.len(), MAX_EVENTS);
+    }
+
+    #[test]
+    fn evicts_events_and_connections() {
+        let mut app = sample();
+        // 选中 python3 的连接后淘汰其全部事件
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        assert_eq!(app.visible_events(), vec![2]);
+        for _ in 0..MAX_EVENTS {
+            app.push(event("curl", 7, 3, READ, "y"));
+        }
+        assert_eq!(app.events.len(), MAX_EVENTS);
+        assert_eq!(app.connections.len(), 1);
+        assert_eq!(app.connection_index.len(), 1);
+        assert_eq!(app.connections[0].retained, MAX_EVENTS);
+        assert_eq!(app.selected_connection, 0);
+        assert_eq!(app.visible_events().len(), MAX_EVENTS);
+        assert_eq!(app.visible_events()[0], 0);
+    }
+
+    #[test]
+    fn renders_selected_event_as_hex() {
+        let mut app = sample();
+        app.focus = Focus::Events;
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Char('x'));
+        let screen = screen(&app);
+        assert!(screen.contains("All connections (2)"));
+        assert!(screen.contains("48 54 54 50 2f 31 2e 31"));
+        assert!(screen.contains("[hex]"));
+    }
+}
@@ -1,42 +1,153 @@
 use egui::{
-    CentralPanel, FontData, FontDefinitions, FontId, Label, RichText, ScrollArea, Visuals, Window,
+    CentralPanel, Color32, ComboBox, FontData, FontDefinitions, FontId, Label, RichText, ScrollArea, Sense,
+    TextEdit, TopBottomPanel, Visuals, Window,
 };
-use sqlx::{MySql, Pool};
+use egui_extras::{Column, TableBuilder};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
-use crate::mysql_db::{query_data, SslDataRow};
+use ssl_observer_common::WRITE;
 
-// 异步显示数据的函数，假设此函数在一个Tokio的异步环境中被调用
-pub async fn display_data_async(pool: &Pool<MySql>) {
-    // 查询数据，这里直接在异步上下文中调用异步函数
-    let data: Vec<SslDataRow> = match query_data(&pool).await {
-        Ok(data) => data,
-        Err(e) => {
+use crate::latency::Log2Histogram;
+use crate::storage::{SslDataRow, Storage};
+use crate::view_filter::{insert_row, sort_rows, visible_rows, CompiledFilter, Direction, Sort, SortColumn, ViewFilter};
+
+// 采集期间界面刷新的间隔
+const REFRESH_INTERVAL: Duration = Duration::from_millis(200);
+// 表格中内容预览的最大字符数
+const PREVIEW_LEN: usize = 120;
+// 可排序的列
+const SORT_COLUMNS: [(&str, SortColumn); 7] = [
+    ("ID", SortColumn::Id),
+    ("Time", SortColumn::Time),
+    ("PID", SortColumn::Pid),
+    ("Command", SortColumn::Comm),
+    ("Dir", SortColumn::Direction),
+    ("Size", SortColumn::Size),
+    ("Latency (µs)", SortColumn::Latency),
+];
+
+// 单行内容预览，控制字符替换为空格
+fn preview(buf: &str) -> String {
+    let mut preview: String = buf
+        .chars()
+        .take(PREVIEW_LEN)
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    if buf.chars().nth(PREVIEW_LEN).is_some() {
+        preview.push_str("...");
+    }
+    preview
+}
+
+// 按进程名与读写方向汇总 SSL 调用耗时
+fn latency_report(data: &[SslDataRow]) -> String {
+    let mut histograms: BTreeMap<(String, &str), Log2Histogram> = BTreeMap::new();
+    for row in data {
+        let Some(delta_ns) = row.delta_ns.filter(|delta| *delta > 0) else {
+            continue;
+        };
+        let operation = if row.rw == WRITE as i32 { "write" } else { "read" };
+        histograms
+            .entry((row.comm.clone(), operation))
+            .or_default()
+            .record(delta_ns as u64);
+    }
+    histograms
+        .iter()
+        .map(|((comm, operation), histogram)| {
+            format!(
+                "SSL {} latency, comm = {} ({} calls)\n{}",
+                operation,
+                comm,
+                histogram.count(),
+                histogram.render("usecs")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// sending side of the live viewer, kept by the capture loop
+pub struct LiveFeed {
+    rows: mpsc::Sender<SslDataRow>,
+    capturing: Arc<AtomicBool>,
+}
+
+impl LiveFeed {
+    pub fn send(&self, row: SslDataRow) {
+        // 窗口关闭后丢弃
+        let _ = self.rows.send(row);
+    }
+
+    /// false while capture is stopped from the viewer
+    pub fn is_capturing(&self) -> bool {
+        self.capturing.load(Ordering::Relaxed)
+    }
+}
+
+/// egui viewer that shows stored rows and streams new ones while capture runs
+pub struct LiveViewer {
+    history: Vec<SslDataRow>,
+    rows: mpsc::Receiver<SslDataRow>,
+    capturing: Arc<AtomicBool>,
+}
+
+impl LiveViewer {
+    /// load the stored rows, new rows are sent through the returned feed
+    pub async fn open(storage: &Storage) -> (LiveFeed, Self) {
+        // 在采集开始前查询，避免与推送的行重复
+        let history = storage.query_data().await.unwrap_or_else(|e| {
             eprintln!("Error querying data: {}", e);
-            return;
-        }
-    };
-
-    // 初始化eframe并启动应用
-    // 注意：此处简化展示，实际应用中需考虑如何与现有异步架构整合
-    let options = eframe::NativeOptions::default();
-    eframe::run_native(
-        "SSL Data Viewer",
-        options,
-        Box::new(move |_cc| {
-            // 确保selected_id字段被初始化
-            Box::new(MyApp::new(data))
-        }),
-    )
-    .expect("Failed to run eframe application");
+            Vec::new()
+        });
+        let (sender, rows) = mpsc::channel();
+        let capturing = Arc::new(AtomicBool::new(true));
+        let feed = LiveFeed {
+            rows: sender,
+            capturing: capturing.clone(),
+        };
+        let viewer = Self {
+            history,
+            rows,
+            capturing,
+        };
+        (feed, viewer)
+    }
+
+    /// run the window on the current thread until it is closed
+    pub fn run(self) -> Result<(), eframe::Error> {
+        let options = eframe::NativeOptions::default();
+        eframe::run_native(
+            "SSL Data Viewer",
+            options,
+            Box::new(move |_cc| Box::new(MyApp::new(self))),
+        )
+    }
 }
 
 struct MyApp {
     data: Vec<SslDataRow>,
+    live: LiveViewer,
+    // 暂停期间到达的行，恢复后再加入列表
+    pending: Vec<SslDataRow>,
+    paused: bool,
+    auto_scroll: bool,
+    filter: ViewFilter,
+    // 过滤条件有误时保存错误信息，不显示任何行
+    compiled: Result<CompiledFilter, String>,
+    sort: Sort,
+    // 通过过滤的行在 data 中的下标，按 sort 排序
+    visible: Vec<usize>,
     expanded_id: Option<i64>, // 选中的ID，用于展示完整buf
     fonts: FontDefinitions,   // 添加这个字段来存储字体定义
+    latency: String,          // 耗时直方图，打开时按当前数据计算
+    show_latency: bool,
 }
 impl MyApp {
-    fn new(data: Vec<SslDataRow>) -> Self {
+    fn new(mut live: LiveViewer) -> Self {
         // 初始化字体定义，加载自定义字体
         let mut fonts = FontDefinitions::default();
         if let Ok(font_data) = std::fs::read("./LXGWWenKai-Bold.ttf") {
@@ -54,10 +165,161 @@ impl MyApp {
             eprintln!("Custom font file not found at specified path.");
         }
 
-        Self {
-            data,
+        let mut app = Self {
+            data: std::mem::take(&mut live.history),
+            live,
+            pending: Vec::new(),
+            paused: false,
+            auto_scroll: true,
+            filter: ViewFilter::default(),
+            compiled: ViewFilter::default().compile(),
+            sort: Sort::default(),
+            visible: Vec::new(),
             expanded_id: None,
             fonts,
+            latency: String::new(),
+            show_latency: false,
+        };
+        app.refresh();
+        app
+    }
+
+    // 过滤条件变化后重新筛选全部行
+    fn refresh(&mut self) {
+        self.compiled = self.filter.compile();
+        self.visible = match &self.compiled {
+            Ok(filter) => visible_rows(&self.data, filter, self.sort),
+            Err(_) => Vec::new(),
+        };
+    }
+
+    // 新行只需检查自身是否通过过滤，并按排序插入到对应位置
+    fn add_rows(&mut self, rows: Vec<SslDataRow>) {
+        for row in rows {
+            let matches = self.compiled.as_ref().is_ok_and(|filter| filter.matches(&row));
+            self.data.push(row);
+            if matches {
+                insert_row(&self.data, &mut self.visible, self.data.len() - 1, self.sort);
+            }
+        }
+    }
+
+    fn filter_panel(&mut self, ui: &mut egui::Ui) {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            ui.label("PID");
+            changed |= ui.add(TextEdit::singleline(&mut self.filter.pid).desired_width(60.0)).changed();
+            ui.label("Command");
+            changed |= ui.add(TextEdit::singleline(&mut self.filter.comm).desired_width(100.0)).changed();
+            let direction = self.filter.direction;
+            ComboBox::from_id_source("direction")
+                .selected_text(match direction {
+                    Direction::All => "Read & Write",
+                    Direction::Read => "Read",
+                    Direction::Write => "Write",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.filter.direction, Direction::All, "Read & Write");
+                    ui.selectable_value(&mut self.filter.direction, Direction::Read, "Read");
+                    ui.selectable_value(&mut self.filter.direction, Direction::Write, "Write");
+                });
+            changed |= direction != self.filter.direction;
+            ui.label("From");
+            changed |= ui
+                .add(TextEdit::singleline(&mut self.filter.from).hint_text("2024-05-01 10:00").desired_width(150.0))
+                .changed();
+            ui.label("To");
+            changed |= ui.add(TextEdit::singleline(&mut self.filter.to).desired_width(150.0)).changed();
+            ui.label("Search");
+            changed |= ui.add(TextEdit::singleline(&mut self.filter.search).desired_width(200.0)).changed();
+            changed |= ui.checkbox(&mut self.filter.regex, "Regex").changed();
+            if ui.button("Clear").clicked() {
+                self.filter = ViewFilter::default();
+                changed = true;
+            }
+            ui.separator();
+            match &self.compiled {
+                Ok(_) => ui.label(format!("{} / {} rows", self.visible.len(), self.data.len())),
+                Err(e) => ui.colored_label(Color32::RED, e),
+            };
+        });
+        if changed {
+            self.refresh();
+        }
+    }
+
+    fn table(&mut self, ui: &mut egui::Ui, text_size: f32) {
+        let font = FontId::monospace(text_size);
+        let row_height = text_size + 6.0;
+        let mut sort_by = None;
+        let mut clicked = None;
+        TableBuilder::new(ui)
+            .striped(true)
+            .resizable(true)
+            .sense(Sense::click())
+            .stick_to_bottom(self.auto_scroll)
+            .column(Column::auto().at_least(60.0))
+            .column(Column::auto().at_least(200.0))
+            .columns(Column::auto().at_least(70.0), 5)
+            .column(Column::initial(220.0).clip(true))
+            .column(Column::remainder().clip(true))
+            .header(row_height, |mut header| {
+                for (name, column) in SORT_COLUMNS {
+                    header.col(|ui| {
+                        let marker = match self.sort {
+                            Sort { column: sorted, ascending: true } if sorted == column => " ▲",
+                            Sort { column: sorted, ascending: false } if sorted == column => " ▼",
+                            _ => "",
+                        };
+                        if ui.button(format!("{}{}", name, marker)).clicked() {
+                            sort_by = Some(column);
+                        }
+                    });
+                }
+                header.col(|ui| {
+                    ui.strong("HTTP");
+                });
+                header.col(|ui| {
+                    ui.strong("Buffer");
+                });
+            })
+            .body(|body| {
+                // 只绘制可见区域内的行
+                body.rows(row_height, self.visible.len(), |mut table_row| {
+                    let row = &self.data[self.visible[table_row.index()]];
+                    table_row.set_selected(self.expanded_id == Some(row.id));
+                    let size = row.raw_buf.as_ref().map_or(row.buf.len(), Vec::len);
+                    let cells = [
+                        row.id.to_string(),
+                        row.timestamp.clone(),
+                        row.pid.to_string(),
+                        row.comm.clone(),
+                        if row.rw == WRITE as i32 { "write" } else { "read" }.to_string(),
+                        size.to_string(),
+                        row.delta_ns.map_or(String::new(), |delta| (delta / 1000).to_string()),
+                        row.http_summary().unwrap_or_default(),
+                        preview(&row.buf),
+                    ];
+                    for cell in cells {
+                        table_row.col(|ui| {
+                            ui.add(Label::new(RichText::new(cell).font(font.clone())).selectable(false));
+                        });
+                    }
+                    if table_row.response().clicked() {
+                        clicked = Some(row.id);
+                    }
+                });
+            });
+        if let Some(column) = sort_by {
+            // 再次点击同一列时反转顺序
+            self.sort = Sort {
+                column,
+                ascending: self.sort.column != column || !self.sort.ascending,
+            };
+            sort_rows(&self.data, &mut self.visible, self.sort);
+        }
+        if clicked.is_some() {
+            self.expanded_id = clicked;
         }
     }
 }
@@ -71,54 +333,59 @@ impl eframe::App for MyApp {
         // 设置界面为亮色主题
         ctx.set_visuals(Visuals::light());
 
-        CentralPanel::default().show(ctx, |ui| {
-            ScrollArea::vertical().show(ui, |ui| {
-                for row in &self.data {
-                    ui.horizontal(|ui| {
-                        ui.label(
-                            egui::RichText::new(format!("ID: {}", row.id))
-                                .font(egui::FontId::monospace(text_size)),
-                        );
-                        ui.label(
-                            egui::RichText::new(format!("Time: {}", row.timestamp))
-                                .font(egui::FontId::monospace(text_size)),
-                        );
-                        ui.label(
-                            egui::RichText::new(format!("PID: {}", row.pid))
-                                .font(egui::FontId::monospace(text_size)),
-                        );
-                        ui.label(
-                            egui::RichText::new(format!("Command: {}", row.comm))
-                                .font(egui::FontId::monospace(text_size)),
-                        );
-
-                        // 添加横线分隔
-                        ui.separator();
-
-                        // 修改按钮逻辑，记录点击的ID
-                        if ui.button("Expand").clicked() {
-                            self.expanded_id = Some(row.id);
-                        }
-                        // 显示buf的预览（例如前50个字符）
-                        let preview_buf = if row.buf.chars().count() > 50 {
-                            format!(
-                                "{}...",
-                                &row.buf[..row.buf.chars().take(50).collect::<String>().len()]
-                            )
-                        } else {
-                            row.buf.clone() // 如果少于50个字符，直接使用原始字符串
-                        };
-
-                        ui.label(
-                            egui::RichText::new(preview_buf)
-                                .font(egui::FontId::monospace(text_size)), // 这里设置了18号字体作为示例
-                        );
-                    });
+        // 取出采集循环推送的新行
+        let rows: Vec<SslDataRow> = self.live.rows.try_iter().collect();
+        if self.paused {
+            self.pending.extend(rows);
+        } else {
+            self.add_rows(rows);
+        }
+        ctx.request_repaint_after(REFRESH_INTERVAL);
 
-                    // 在每个item后添加额外的间距作为视觉上的分隔
-                    ui.add_space(10.0);
+        TopBottomPanel::top("toolbar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let capturing = self.live.capturing.load(Ordering::Relaxed);
+                if ui.button(if capturing { "Stop Capture" } else { "Start Capture" }).clicked() {
+                    self.live.capturing.store(!capturing, Ordering::Relaxed);
+                }
+                if ui.button(if self.paused { "Resume" } else { "Pause" }).clicked() {
+                    self.paused = !self.paused;
+                    let pending = std::mem::take(&mut self.pending);
+                    self.add_rows(pending);
                 }
+                ui.checkbox(&mut self.auto_scroll, "Auto-scroll");
+                if ui.toggle_value(&mut self.show_latency, "Latency").clicked() && self.show_latency {
+                    self.latency = latency_report(&self.data);
+                }
+                ui.separator();
+                let mut status = format!("{} rows", self.data.len());
+                if !self.pending.is_empty() {
+                    status.push_str(&format!(", {} pending", self.pending.len()));
+                }
+                if !capturing {
+                    status.push_str(", capture stopped");
+                }
+                ui.label(status);
             });
+        });
+
+        TopBottomPanel::top("filters").show(ctx, |ui| self.filter_panel(ui));
+
+        // 各进程的 SSL 调用耗时分布
+        Window::new("SSL Call Latency")
+            .open(&mut self.show_latency)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ScrollArea::both().show(ui, |ui| {
+                    if self.latency.is_empty() {
+                        ui.label("No latency recorded.");
+                    }
+                    ui.label(RichText::new(&self.latency).font(FontId::monospace(14.0)));
+                });
+            });
+
+        CentralPanel::default().show(ctx, |ui| {
+            self.table(ui, text_size);
 
             // 展示完整buf的弹窗
             if let Some(selected_id) = self.expanded_id {
@@ -128,6 +395,23 @@ impl eframe::App for MyApp {
                         .collapsible(true)
                         .resizable(true)
                         .show(ctx, |ui| {
+                            // 原始字节长度，便于判断解码是否有损
+                            ui.label(format!("Raw: {} bytes", row.raw_buf.as_ref().map_or(0, Vec::len)));
+                            // 结构化的 HTTP 字段
+                            let fields = [
+                                ("Method", row.method.clone()),
+                                ("Path", row.path.clone()),
+                                ("Host", row.host.clone()),
+                                ("Status", row.status.map(|status| status.to_string())),
+                                ("Content-Type", row.content_type.clone()),
+                                ("Content-Length", row.content_length.map(|len| len.to_string())),
+                                ("User-Agent", row.user_agent.clone()),
+                            ];
+                            for (name, value) in fields {
+                                if let Some(value) = value {
+                                    ui.label(format!("{}: {}", name, value));
+                                }
+                            }
                             let full_buf = &row.buf; // 直接获取row的buf字段
                                                      // 使用TextEdit以支持文本自动换行和界面自适应
                             let full_buf_clone = full_buf.clone(); // 克隆buf以用于展示，避免直接修改原数据
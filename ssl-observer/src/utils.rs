@@ -16,8 +16,7 @@ pub async fn convert_timestamp_to_date(timestamp_ns: u64) -> Result<String, sqlx
     let datetime: DateTime<Local> = match calculate_specific_time(timestamp).await {
         Ok(dt) => dt.into(),
         Err(_) => {
-            return Err(sqlx::Error::from(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            return Err(sqlx::Error::from(std::io::Error::other(
                 "Failed to calculate specific time",
             )))
         }
@@ -54,7 +53,7 @@ pub async fn calculate_specific_time(offset_seconds: u64) -> Result<SystemTime,
         .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid uptime data format"))?;
 
     // 当前时间减去系统运行秒数得到系统启动时间
-    let boot_time = SystemTime::now() - Duration::from_secs_f64(uptime_seconds as f64);
+    let boot_time = SystemTime::now() - Duration::from_secs_f64(uptime_seconds);
 
     // 在系统启动时间基础上加上偏移秒数得到目标时间
     let target_time = boot_time + Duration::from_secs(offset_seconds);
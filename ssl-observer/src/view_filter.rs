@@ -0,0 +1,317 @@
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+
+use ssl_observer_common::{READ, WRITE};
+
+use crate::storage::SslDataRow;
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum Direction {
+    #[default]
+    All,
+    Read,
+    Write,
+}
+
+/// filter inputs of the data viewer, kept as typed so the fields can be edited in place
+#[derive(Clone, Default)]
+pub struct ViewFilter {
+    /// pid or tgid
+    pub pid: String,
+    /// case-insensitive substring of the command
+    pub comm: String,
+    pub direction: Direction,
+    /// inclusive bounds compared with the start of `timestamp`, like "2024-05-01 10:00"
+    pub from: String,
+    pub to: String,
+    /// case-insensitive text searched in `buf`, a regex when `regex` is set
+    pub search: String,
+    pub regex: bool,
+}
+
+/// filter compiled once per change, then applied to every row
+pub struct CompiledFilter {
+    pid: Option<i32>,
+    comm: String,
+    rw: Option<i32>,
+    from: String,
+    to: String,
+    // 纯文本搜索也转义为正则，不区分大小写时无需逐行转换小写
+    search: Option<Regex>,
+}
+
+impl ViewFilter {
+    pub fn compile(&self) -> Result<CompiledFilter, String> {
+        let pid = match self.pid.trim() {
+            "" => None,
+            pid => Some(pid.parse().map_err(|_| format!("invalid pid \"{}\"", pid))?),
+        };
+        let search = match self.search.as_str() {
+            "" => None,
+            search => {
+                let pattern = if self.regex { search.to_string() } else { regex::escape(search) };
+                Some(
+                    RegexBuilder::new(&pattern)
+                        .case_insensitive(true)
+                        .build()
+                        .map_err(|e| e.to_string())?,
+                )
+            }
+        };
+        Ok(CompiledFilter {
+            pid,
+            comm: self.comm.trim().to_lowercase(),
+            rw: match self.direction {
+                Direction::All => None,
+                Direction::Read => Some(READ as i32),
+                Direction::Write => Some(WRITE as i32),
+            },
+            from: self.from.trim().to_string(),
+            to: self.to.trim().to_string(),
+            search,
+        })
+    }
+}
+
+impl CompiledFilter {
+    pub fn matches(&self, row: &SslDataRow) -> bool {
+        if self.pid.is_some_and(|pid| pid != row.pid && pid != row.tgid) {
+            return false;
+        }
+        if self.rw.is_some_and(|rw| rw != row.rw) {
+            return false;
+        }
+        if !self.comm.is_empty() && !row.comm.to_lowercase().contains(&self.comm) {
+            return false;
+        }
+        // 时间戳格式固定，按字符串比较即可；上界只比较同样长度的前缀
+        if !self.from.is_empty() && row.timestamp.as_str() < self.from.as_str() {
+            return false;
+        }
+        if !self.to.is_empty() {
+            let prefix = row.timestamp.get(..self.to.len()).unwrap_or(&row.timestamp);
+            if prefix > self.to.as_str() {
+                return false;
+            }
+        }
+        self.search.as_ref().is_none_or(|search| search.is_match(&row.buf))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Default)]
+pub enum SortColumn {
+    #[default]
+    Id,
+    Time,
+    Pid,
+    Comm,
+    Direction,
+    Size,
+    Latency,
+}
+
+/// sort key of the data viewer table
+#[derive(Clone, Copy, PartialEq)]
+pub struct Sort {
+    pub column: SortColumn,
+    pub ascending: bool,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Self {
+            column: SortColumn::Id,
+            ascending: true,
+        }
+    }
+}
+
+impl Sort {
+    /// true when rows in insertion order are already sorted, so new rows can be appended
+    pub fn is_insertion_order(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn compare(&self, a: &SslDataRow, b: &SslDataRow) -> Ordering {
+        let size = |row: &SslDataRow| row.raw_buf.as_ref().map_or(row.buf.len(), Vec::len);
+        let ordering = match self.column {
+            SortColumn::Id => a.id.cmp(&b.id),
+            SortColumn::Time => a.timestamp.cmp(&b.timestamp),
+            SortColumn::Pid => a.pid.cmp(&b.pid),
+            SortColumn::Comm => a.comm.cmp(&b.comm),
+            SortColumn::Direction => a.rw.cmp(&b.rw),
+            SortColumn::Size => size(a).cmp(&size(b)),
+            SortColumn::Latency => a.delta_ns.cmp(&b.delta_ns),
+        };
+        // 相同时按 id 保持稳定的顺序
+        let ordering = ordering.then(a.id.cmp(&b.id));
+        if self.ascending {
+            ordering
+        } else {
+            ordering.reverse()
+        }
+    }
+}
+
+/// indices of the rows that pass `filter`, in `sort` order
+pub fn visible_rows(data: &[SslDataRow], filter: &CompiledFilter, sort: Sort) -> Vec<usize> {
+    let mut rows: Vec<usize> = (0..data.len()).filter(|&index| filter.matches(&data[index])).collect();
+    sort_rows(data, &mut rows, sort);
+    rows
+}
+
+/// reorder row indices after the sort key changed
+pub fn sort_rows(data: &[SslDataRow], rows: &mut [usize], sort: Sort) {
+    if !sort.is_insertion_order() {
+        rows.sort_unstable_by(|&a, &b| sort.compare(&data[a], &data[b]));
+    }
+}
+
+/// insert a newly appended row into sorted indices without sorting them again
+pub fn insert_row(data: &[SslDataRow], rows: &mut Vec<usize>, index: usize, sort: Sort) {
+    if sort.is_insertion_order() {
+        rows.push(index);
+        return;
+    }
+    // 比较时以 id 兜底，不会有相等的行
+    let position = rows
+        .binary_search_by(|&probe| sort.compare(&data[probe], &data[index]))
+        .unwrap_or_else(|position| position);
+    rows.insert(position, index);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert_row, visible_rows, Direction, Sort, SortColumn, ViewFilter};
+    use crate::storage::SslDataRow;
+    use ssl_observer_common::{READ, WRITE};
+
+    fn row(id: i64, timestamp: &str, pid: i32, comm: &str, rw: u8, buf: &str) -> SslDataRow {
+        SslDataRow {
+            id,
+            timestamp: timestamp.to_string(),
+            delta_ns: Some(id * 1000),
+            pid,
+            tgid: pid,
+            rw: rw as i32,
+            conn_id: None,
+            comm: comm.to_string(),
+            buf: buf.to_string(),
+            raw_buf: Some(buf.as_bytes().to_vec()),
+            method: None,
+            path: None,
+            host: None,
+            status: None,
+            content_type: None,
+            content_length: None,
+            user_agent: None,
+        }
+    }
+
+    fn data() -> Vec<SslDataRow> {
+        vec![
+            row(1, "2024-05-01 09:59:59", 7, "curl", WRITE, "GET /a HTTP/1.1"),
+            row(2, "2024-05-01 10:00:01", 7, "curl", READ, "HTTP/1.1 200 OK"),
+            row(3, "2024-05-01 10:30:00", 9, "python3", WRITE, "POST /login HTTP/1.1"),
+            row(4, "2024-05-01 11:00:00", 9, "python3", READ, "HTTP/1.1 404 Not Found"),
+        ]
+    }
+
+    fn visible(filter: ViewFilter, sort: Sort) -> Vec<usize> {
+        visible_rows(&data(), &filter.compile().unwrap(), sort)
+    }
+
+    #[test]
+    fn filters_by_process_and_direction() {
+        assert_eq!(visible(ViewFilter::default(), Sort::default()), vec![0, 1, 2, 3]);
+        let filter = ViewFilter {
+            pid: "9".to_string(),
+            direction: Direction::Read,
+            ..ViewFilter::default()
+        };
+        assert_eq!(visible(filter, Sort::default()), vec![3]);
+    }
+
+    #[test]
+    fn searches_text_and_regex() {
+        let filter = ViewFilter {
+            comm: "CURL".to_string(),
+            search: "200 ok".to_string(),
+            ..ViewFilter::default()
+        };
+        assert_eq!(visible(filter, Sort::default()), vec![1]);
+        // 纯文本中的正则元字符按字面匹配
+        let filter = ViewFilter {
+            search: "1.1 4".to_string(),
+            ..ViewFilter::default()
+        };
+        assert_eq!(visible(filter, Sort::default()), vec![3]);
+        let filter = ViewFilter {
+            search: "(".to_string(),
+            ..ViewFilter::default()
+        };
+        assert!(visible(filter, Sort::default()).is_empty());
+        let filter = ViewFilter {
+            search: r"^(GET|POST) /\w+".to_string(),
+            regex: true,
+            ..ViewFilter::default()
+        };
+        assert_eq!(visible(filter, Sort::default()), vec![0, 2]);
+    }
+
+    #[test]
+    fn bounds_time_by_prefix() {
+        // 上界按前缀比较，包含 10 点内的所有行
+        let filter = ViewFilter {
+            from: "2024-05-01 10:00".to_string(),
+            to: "2024-05-01 10".to_string(),
+            ..ViewFilter::default()
+        };
+        assert_eq!(visible(filter, Sort::default()), vec![1, 2]);
+    }
+
+    #[test]
+    fn sorts_by_column() {
+        let sort = Sort {
+            column: SortColumn::Size,
+            ascending: false,
+        };
+        assert_eq!(visible(ViewFilter::default(), sort), vec![3, 2, 1, 0]);
+        let sort = Sort {
+            column: SortColumn::Comm,
+            ascending: true,
+        };
+        assert_eq!(visible(ViewFilter::default(), sort), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn inserts_rows_in_order() {
+        let mut data = data();
+        let sort = Sort {
+            column: SortColumn::Size,
+            ascending: true,
+        };
+        let filter = ViewFilter::default().compile().unwrap();
+        let mut rows = visible_rows(&data, &filter, sort);
+        data.push(row(5, "2024-05-01 11:00:01", 9, "python3", WRITE, "GET /bb HTTP/1.1"));
+        insert_row(&data, &mut rows, 4, sort);
+        assert_eq!(rows, visible_rows(&data, &filter, sort));
+        insert_row(&data, &mut rows, 4, Sort::default());
+        assert_eq!(rows.last(), Some(&4));
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let filter = ViewFilter {
+            search: "(".to_string(),
+            regex: true,
+            ..ViewFilter::default()
+        };
+        assert!(filter.compile().is_err());
+        let filter = ViewFilter {
+            pid: "abc".to_string(),
+            ..ViewFilter::default()
+        };
+        assert!(filter.compile().is_err());
+    }
+}
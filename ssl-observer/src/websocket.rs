@@ -0,0 +1,404 @@
+use flate2::{Decompress, FlushDecompress, Status};
+use log::debug;
+use std::collections::HashMap;
+
+use ssl_observer_common::{ProbeSslData, READ, WRITE};
+
+use crate::http1::HttpExchange;
+use crate::utils::sanitize_comm;
+
+// 单个消息的最大缓存，超过后按截断输出
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+// 连接空闲超过该时间后清理
+const IDLE_TIMEOUT_NS: u64 = 60 * 1_000_000_000;
+const SWEEP_INTERVAL: u64 = 1024;
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+/// one WebSocket message, fragments joined and permessage-deflate undone
+pub struct WebSocketMessage {
+    pub timestamp_ns: u64,
+    pub pid: u32,
+    pub tgid: u32,
+    pub conn_id: u64,
+    pub comm: String,
+    /// READ or WRITE, as seen by the observed process
+    pub rw: u8,
+    pub opcode: u8,
+    /// sent by the client, frames were masked
+    pub masked: bool,
+    pub compressed: bool,
+    pub payload: Vec<u8>,
+    /// close frame status code
+    pub close_code: Option<u16>,
+    pub truncated: bool,
+}
+
+impl WebSocketMessage {
+    pub fn kind(&self) -> &'static str {
+        match self.opcode {
+            OPCODE_TEXT => "text",
+            OPCODE_BINARY => "binary",
+            OPCODE_CLOSE => "close",
+            OPCODE_PING => "ping",
+            OPCODE_PONG => "pong",
+            _ => "unknown",
+        }
+    }
+
+    /// return the payload as text, close frames without their status code
+    pub fn text(&self) -> String {
+        let payload = match self.close_code {
+            Some(_) => &self.payload[2..],
+            None => &self.payload[..],
+        };
+        String::from_utf8_lossy(payload).to_string()
+    }
+}
+
+// 拼接分片后的消息，未收到 FIN 时暂存在 WebSocketSide 中
+struct Fragments {
+    opcode: u8,
+    masked: bool,
+    compressed: bool,
+    payload: Vec<u8>,
+    truncated: bool,
+}
+
+// 单方向的帧流
+#[derive(Default)]
+struct WebSocketSide {
+    buf: Vec<u8>,
+    fragments: Option<Fragments>,
+    // permessage-deflate 的解压上下文，context takeover 时跨消息保留
+    inflater: Option<Decompress>,
+}
+
+// 一条升级后的连接
+struct WebSocketConn {
+    sides: [WebSocketSide; 2],
+    deflate: bool,
+    client_no_context_takeover: bool,
+    server_no_context_takeover: bool,
+    last_ns: u64,
+    pid: u32,
+    comm: String,
+}
+
+// 一个完整的帧
+struct Frame {
+    fin: bool,
+    rsv1: bool,
+    opcode: u8,
+    masked: bool,
+    payload: Vec<u8>,
+}
+
+// 从缓冲区开头解析一个完整的帧并去掉掩码，返回帧与占用的字节数
+fn parse_frame(buf: &[u8]) -> Option<(Frame, usize)> {
+    if buf.len() < 2 {
+        return None;
+    }
+    let masked = buf[1] & 0x80 != 0;
+    let (len, mut pos) = match buf[1] & 0x7f {
+        126 => (u16::from_be_bytes(buf.get(2..4)?.try_into().ok()?) as u64, 4),
+        127 => (u64::from_be_bytes(buf.get(2..10)?.try_into().ok()?), 10),
+        len => (len as u64, 2),
+    };
+    let mask = if masked {
+        let mask: [u8; 4] = buf.get(pos..pos + 4)?.try_into().ok()?;
+        pos += 4;
+        Some(mask)
+    } else {
+        None
+    };
+    let end = pos.checked_add(usize::try_from(len).ok()?)?;
+    let mut payload = buf.get(pos..end)?.to_vec();
+    if let Some(mask) = mask {
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    Some((
+        Frame {
+            fin: buf[0] & 0x80 != 0,
+            rsv1: buf[0] & 0x40 != 0,
+            opcode: buf[0] & 0x0f,
+            masked,
+            payload,
+        },
+        end,
+    ))
+}
+
+// 补上被省略的空块结尾后做 raw deflate 解压
+fn inflate(inflater: &mut Decompress, data: &[u8]) -> Result<Vec<u8>, flate2::DecompressError> {
+    let mut input = data.to_vec();
+    input.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+    let mut output = Vec::new();
+    let mut pos = 0;
+    loop {
+        output.reserve(32 * 1024);
+        let total_in = inflater.total_in();
+        let status = inflater.decompress_vec(&input[pos..], &mut output, FlushDecompress::Sync)?;
+        pos += (inflater.total_in() - total_in) as usize;
+        let full = output.len() == output.capacity();
+        if status == Status::StreamEnd || (pos >= input.len() && !full) || output.len() > MAX_MESSAGE_SIZE {
+            break;
+        }
+        if !full && status == Status::BufError {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+impl WebSocketConn {
+    fn feed(&mut self, direction: usize, ts: u64) -> Vec<Fragments> {
+        let mut messages = Vec::new();
+        let side = &mut self.sides[direction];
+        let mut pos = 0;
+        while let Some((frame, consumed)) = parse_frame(&side.buf[pos..]) {
+            pos += consumed;
+            // 控制帧不分片，可以插在分片消息中间
+            if frame.opcode >= OPCODE_CLOSE {
+                messages.push(Fragments {
+                    opcode: frame.opcode,
+                    masked: frame.masked,
+                    compressed: false,
+                    payload: frame.payload,
+                    truncated: false,
+                });
+                continue;
+            }
+            let fragments = match (frame.opcode, side.fragments.take()) {
+                (OPCODE_CONTINUATION, Some(mut fragments)) => {
+                    if fragments.payload.len() + frame.payload.len() > MAX_MESSAGE_SIZE {
+                        fragments.truncated = true;
+                    } else {
+                        fragments.payload.extend_from_slice(&frame.payload);
+                    }
+                    fragments
+                }
+                // 没有开头的分片，从中途加入时丢弃
+                (OPCODE_CONTINUATION, None) => continue,
+                (opcode, _) => Fragments {
+                    opcode,
+                    masked: frame.masked,
+                    compressed: frame.rsv1 && self.deflate,
+                    payload: frame.payload,
+                    truncated: false,
+                },
+            };
+            if !frame.fin {
+                side.fragments = Some(fragments);
+                continue;
+            }
+            messages.push(fragments);
+        }
+        side.buf.drain(..pos);
+        if side.buf.len() > MAX_MESSAGE_SIZE {
+            side.buf.clear();
+        }
+
+        // 解压 permessage-deflate 消息
+        let no_context_takeover = [self.server_no_context_takeover, self.client_no_context_takeover];
+        for message in messages.iter_mut().filter(|message| message.compressed) {
+            let inflater = side.inflater.get_or_insert_with(|| Decompress::new(false));
+            match inflate(inflater, &message.payload) {
+                Ok(inflated) => message.payload = inflated,
+                Err(e) => {
+                    debug!("permessage-deflate failed: {}", e);
+                    side.inflater = None;
+                    continue;
+                }
+            }
+            // 客户端发送的帧带掩码
+            if no_context_takeover[message.masked as usize] {
+                side.inflater = None;
+            }
+        }
+        self.last_ns = ts;
+        messages
+    }
+}
+
+/// decodes WebSocket frames on connections upgraded by a 101 response
+#[derive(Default)]
+pub struct WebSocketTracker {
+    conns: HashMap<(u32, u64), WebSocketConn>,
+    events: u64,
+}
+
+impl WebSocketTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// start tracking a connection after its upgrade exchange, `remainder` holds bytes already read past the 101 response
+    pub fn upgrade(&mut self, exchange: &HttpExchange, remainder: [Vec<u8>; 2]) -> Vec<WebSocketMessage> {
+        let response = match &exchange.response {
+            Some(response) if response.status() == Some(101) => response,
+            _ => return Vec::new(),
+        };
+        if !response
+            .header("upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+        {
+            return Vec::new();
+        }
+        // 例如 "permessage-deflate; client_max_window_bits; server_no_context_takeover"
+        let extensions = response
+            .header("sec-websocket-extensions")
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let has = |param: &str| extensions.split([';', ',']).any(|part| part.trim() == param);
+        let key = (exchange.tgid, exchange.conn_id);
+        let mut conn = WebSocketConn {
+            sides: Default::default(),
+            deflate: has("permessage-deflate"),
+            client_no_context_takeover: has("client_no_context_takeover"),
+            server_no_context_takeover: has("server_no_context_takeover"),
+            last_ns: response.end_ns,
+            pid: exchange.pid,
+            comm: exchange.comm.clone(),
+        };
+        let mut messages = Vec::new();
+        for (direction, bytes) in remainder.into_iter().enumerate() {
+            conn.sides[direction].buf = bytes;
+            let completed = conn.feed(direction, response.end_ns);
+            messages.extend(to_messages(key, &conn, direction, response.end_ns, completed));
+        }
+        self.conns.insert(key, conn);
+        messages
+    }
+
+    /// return whether the event belongs to an upgraded WebSocket connection
+    pub fn is_tracked(&self, data: &ProbeSslData) -> bool {
+        self.conns.contains_key(&(data.tgid, data.conn_id))
+    }
+
+    /// feed one event of an upgraded connection, return the messages completed by it
+    pub fn feed(&mut self, data: &ProbeSslData) -> Vec<WebSocketMessage> {
+        let mut messages = Vec::new();
+        if data.is_handshake || data.len == 0 {
+            return messages;
+        }
+        let key = (data.tgid, data.conn_id);
+        let ts = data.timestamp_ns;
+        if let Some(conn) = self.conns.get_mut(&key) {
+            let direction = if data.rw == WRITE { 1 } else { 0 };
+            conn.pid = data.pid;
+            if conn.comm.is_empty() {
+                conn.comm = sanitize_comm(&data.comm);
+            }
+            conn.sides[direction].buf.extend_from_slice(&data.buf[..data.len]);
+            let completed = conn.feed(direction, ts);
+            messages.extend(to_messages(key, conn, direction, ts, completed));
+        }
+
+        self.events += 1;
+        if self.events.is_multiple_of(SWEEP_INTERVAL) {
+            self.conns
+                .retain(|_, conn| ts.saturating_sub(conn.last_ns) <= IDLE_TIMEOUT_NS);
+        }
+        messages
+    }
+}
+
+fn to_messages(
+    key: (u32, u64),
+    conn: &WebSocketConn,
+    direction: usize,
+    ts: u64,
+    completed: Vec<Fragments>,
+) -> Vec<WebSocketMessage> {
+    completed
+        .into_iter()
+        .map(|message| WebSocketMessage {
+            timestamp_ns: ts,
+            pid: conn.pid,
+            tgid: key.0,
+            conn_id: key.1,
+            comm: conn.comm.clone(),
+            rw: if direction == 1 { WRITE } else { READ },
+            opcode: message.opcode,
+            masked: message.masked,
+            compressed: message.compressed,
+            close_code: if message.opcode == OPCODE_CLOSE && message.payload.len() >= 2 {
+                Some(u16::from_be_bytes([message.payload[0], message.payload[1]]))
+            } else {
+                None
+            },
+            payload: message.payload,
+            truncated: message.truncated,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebSocketMessage, WebSocketTracker, OPCODE_CLOSE, OPCODE_TEXT};
+    use crate::http1::Http1Tracker;
+    use crate::test_util::event;
+    use flate2::{Compress, Compression, FlushCompress};
+    use ssl_observer_common::{READ, WRITE};
+
+    // 完成协商了 permessage-deflate 的升级，返回 101 响应后紧跟的消息
+    fn upgrade(websocket: &mut WebSocketTracker) -> Vec<WebSocketMessage> {
+        let mut http1 = Http1Tracker::new();
+        http1.feed(&event(WRITE, 10, b"GET /ws HTTP/1.1\r\nUpgrade: websocket\r\n\r\n"));
+        // 101 响应后紧跟一个未压缩的服务端文本帧
+        let mut response = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nSec-WebSocket-Extensions: permessage-deflate\r\n\r\n".to_vec();
+        response.extend_from_slice(&[0x81, 0x02, b'h', b'i']);
+        let exchanges = http1.feed(&event(READ, 20, &response));
+        assert_eq!(exchanges.len(), 1);
+        let remainder = http1.take_upgraded(7, 1);
+        websocket.upgrade(&exchanges[0], remainder)
+    }
+
+    #[test]
+    fn parses_frames_after_upgrade() {
+        let messages = upgrade(&mut WebSocketTracker::new());
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].opcode, OPCODE_TEXT);
+        assert_eq!(messages[0].text(), "hi");
+    }
+
+    #[test]
+    fn inflates_masked_fragments() {
+        let mut websocket = WebSocketTracker::new();
+        upgrade(&mut websocket);
+
+        // 客户端发送的压缩消息，分成两个带掩码的分片
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut compressed = Vec::with_capacity(64);
+        compress
+            .compress_vec(b"hello", &mut compressed, FlushCompress::Sync)
+            .unwrap();
+        compressed.truncate(compressed.len() - 4);
+        let mask = [1, 2, 3, 4];
+        let mut frames = Vec::new();
+        for (i, chunk) in compressed.chunks(compressed.len() / 2 + 1).enumerate() {
+            let first = if i == 0 { 0x41 } else { 0x80 };
+            frames.extend_from_slice(&[first, 0x80 | chunk.len() as u8]);
+            frames.extend_from_slice(&mask);
+            frames.extend(chunk.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+        }
+        // 关闭帧 1000
+        frames.extend_from_slice(&[0x88, 0x82]);
+        frames.extend_from_slice(&mask);
+        frames.extend_from_slice(&[0x03 ^ 1, 0xe8 ^ 2]);
+        let messages = websocket.feed(&event(WRITE, 30, &frames));
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].masked && messages[0].compressed);
+        assert_eq!(messages[0].text(), "hello");
+        assert_eq!(messages[1].opcode, OPCODE_CLOSE);
+        assert_eq!(messages[1].close_code, Some(1000));
+    }
+}
@@ -0,0 +1,263 @@
+use boreal::scanner::{ScanParams, ScanResult};
+use boreal::{Compiler, MetadataValue, Scanner};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::config::{Alerts, Yara};
+use crate::http1::{HttpExchange, HttpHead};
+use crate::rules::{excerpt, Alert, RateWindow};
+use crate::websocket::WebSocketMessage;
+
+// 规则没有 severity 元数据时使用
+const DEFAULT_SEVERITY: &str = "high";
+
+// 一条规则在一段数据中的首个匹配
+struct YaraMatch {
+    rule: String,
+    severity: String,
+    // 条件中不含字符串的规则没有匹配位置
+    range: Option<(usize, usize)>,
+}
+
+/// scans reassembled HTTP and WebSocket messages with the YARA rules in config.toml
+pub struct YaraScanner {
+    scanner: Option<Scanner>,
+    limit: u32,
+    interval_ns: u64,
+    windows: HashMap<String, RateWindow>,
+}
+
+impl YaraScanner {
+    pub fn new(config: &Yara, alerts: &Alerts) -> Self {
+        let mut compiler = Compiler::new();
+        let mut loaded = 0;
+        for path in &config.rule_files {
+            match compiler.add_rules_file(path) {
+                Ok(status) => {
+                    for warning in status.warnings() {
+                        warn!("{}: {}", path, warning);
+                    }
+                    loaded += 1;
+                }
+                Err(e) => warn!("Failed to load YARA rules {}: {}", path, e),
+            }
+        }
+        let scanner = (loaded > 0).then(|| {
+            info!("Loaded YARA rules from {} files", loaded);
+            let mut scanner = compiler.finalize();
+            let timeout = (config.timeout_ms > 0).then(|| Duration::from_millis(config.timeout_ms));
+            scanner.set_scan_params(ScanParams::default().timeout_duration(timeout));
+            scanner
+        });
+        Self {
+            scanner,
+            limit: alerts.rate_limit,
+            interval_ns: alerts.rate_interval_secs.max(1) * 1_000_000_000,
+            windows: HashMap::new(),
+        }
+    }
+
+    // 扫描一段数据，返回限流后的匹配
+    fn scan(&mut self, data: &[u8], now_ns: u64) -> Vec<YaraMatch> {
+        let Some(scanner) = self.scanner.as_ref() else {
+            return Vec::new();
+        };
+        // 超时后仍返回已得到的部分结果
+        let result: ScanResult = scanner.scan_mem(data).unwrap_or_else(|(e, result)| {
+            warn!("YARA scan incomplete: {:?}", e);
+            result
+        });
+        let mut matches = Vec::new();
+        for rule in result.rules {
+            let severity = rule
+                .metadatas
+                .iter()
+                .find(|meta| scanner.get_string_symbol(meta.name) == "severity")
+                .and_then(|meta| match meta.value {
+                    MetadataValue::Bytes(value) => {
+                        Some(String::from_utf8_lossy(scanner.get_bytes_symbol(value)).into_owned())
+                    }
+                    _ => None,
+                })
+                .unwrap_or_else(|| DEFAULT_SEVERITY.to_string());
+            let range = rule
+                .matches
+                .iter()
+                .flat_map(|string| string.matches.iter())
+                .map(|found| {
+                    let start = found.base + found.offset;
+                    (start, start + found.length)
+                })
+                .min();
+            let name = format!("{}:{}", rule.namespace, rule.name);
+            let window = self.windows.entry(name.clone()).or_default();
+            if window.allow(&name, self.limit, now_ns, self.interval_ns) {
+                matches.push(YaraMatch {
+                    rule: name,
+                    severity,
+                    range,
+                });
+            }
+        }
+        matches
+    }
+
+    /// scan the request and response of an exchange
+    pub fn scan_exchange(&mut self, exchange: &HttpExchange) -> Vec<Alert> {
+        if self.scanner.is_none() {
+            return Vec::new();
+        }
+        let host = exchange
+            .request
+            .as_ref()
+            .and_then(|request| HttpHead::parse(&request.raw)?.host);
+        let mut alerts = Vec::new();
+        for (scope, message) in [("request", &exchange.request), ("response", &exchange.response)] {
+            let Some(message) = message else {
+                continue;
+            };
+            for found in self.scan(&message.raw, message.end_ns) {
+                alerts.push(Alert {
+                    timestamp_ns: message.end_ns,
+                    source: "yara",
+                    rule: found.rule,
+                    severity: found.severity,
+                    comm: exchange.comm.clone(),
+                    pid: exchange.pid,
+                    tgid: exchange.tgid,
+                    uid: None,
+                    conn_id: exchange.conn_id,
+                    stream_id: exchange.stream_id,
+                    rw: None,
+                    host: host.clone(),
+                    scope,
+                    offset: found.range.map(|(start, _)| start as u64),
+                    excerpt: excerpt(&message.raw, found.range),
+                });
+            }
+        }
+        alerts
+    }
+
+    /// scan one websocket message after reassembly and decompression
+    pub fn scan_ws(&mut self, message: &WebSocketMessage) -> Vec<Alert> {
+        self.scan(&message.payload, message.timestamp_ns)
+            .into_iter()
+            .map(|found| Alert {
+                timestamp_ns: message.timestamp_ns,
+                source: "yara",
+                rule: found.rule,
+                severity: found.severity,
+                comm: message.comm.clone(),
+                pid: message.pid,
+                tgid: message.tgid,
+                uid: None,
+                conn_id: message.conn_id,
+                stream_id: None,
+                rw: Some(message.rw),
+                host: None,
+                scope: "websocket",
+                offset: found.range.map(|(start, _)| start as u64),
+                excerpt: excerpt(&message.payload, found.range),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::YaraScanner;
+    use crate::config::{Alerts, Yara};
+    use crate::http1::{HttpExchange, HttpMessage};
+    use crate::websocket::{WebSocketMessage, OPCODE_TEXT};
+    use ssl_observer_common::WRITE;
+
+    const RULES: &str = r#"
+        rule c2_beacon {
+            meta:
+                severity = "critical"
+            strings:
+                $id = "beacon-id="
+            condition:
+                $id
+        }
+    "#;
+
+    fn scanner() -> YaraScanner {
+        // 测试并行运行，文件名按线程区分
+        let name = format!("ssl-observer-{}-{:?}.yar", std::process::id(), std::thread::current().id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, RULES).unwrap();
+        let config = Yara {
+            rule_files: vec![path.to_string_lossy().into_owned()],
+            ..Yara::default()
+        };
+        let scanner = YaraScanner::new(&config, &Alerts::default());
+        std::fs::remove_file(&path).unwrap();
+        scanner
+    }
+
+    fn ws_message(payload: &[u8]) -> WebSocketMessage {
+        WebSocketMessage {
+            timestamp_ns: 3,
+            pid: 7,
+            tgid: 7,
+            conn_id: 1,
+            comm: "implant".to_string(),
+            rw: WRITE,
+            opcode: OPCODE_TEXT,
+            masked: true,
+            compressed: false,
+            payload: payload.to_vec(),
+            close_code: None,
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn scans_exchange() {
+        let raw = b"POST /poll HTTP/1.1\r\nHost: c2.example\r\nContent-Length: 13\r\n\r\nbeacon-id=42\n".to_vec();
+        let exchange = HttpExchange {
+            pid: 7,
+            tgid: 7,
+            conn_id: 1,
+            stream_id: None,
+            comm: "implant".to_string(),
+            request: Some(HttpMessage {
+                start_line: "POST /poll HTTP/1.1".to_string(),
+                raw,
+                start_ns: 1,
+                end_ns: 2,
+                truncated: false,
+                rw: WRITE,
+                trailers: Vec::new(),
+            }),
+            response: None,
+        };
+        let alerts = scanner().scan_exchange(&exchange);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].rule, "default:c2_beacon");
+        assert_eq!(alerts[0].severity, "critical");
+        assert_eq!(alerts[0].scope, "request");
+        assert_eq!(alerts[0].offset, Some(61));
+        assert_eq!(alerts[0].host.as_deref(), Some("c2.example"));
+    }
+
+    #[test]
+    fn scans_websocket_message() {
+        let message = ws_message(b"{\"beacon-id=\":1}");
+        let alerts = scanner().scan_ws(&message);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].scope, "websocket");
+        assert_eq!(alerts[0].offset, Some(2));
+        assert_eq!(alerts[0].rw, Some(WRITE));
+    }
+
+    #[test]
+    fn no_rule_files_no_alerts() {
+        let mut scanner = YaraScanner::new(&Yara::default(), &Alerts::default());
+        let message = ws_message(b"beacon-id=1");
+        assert!(scanner.scan_ws(&message).is_empty());
+    }
+}